@@ -0,0 +1,327 @@
+//! Local backups of system Bluetooth state
+//!
+//! Before a sync changes the keys of a device on this system, the device's
+//! current state is saved to a local backup store, one backup per operation.
+//! A broken sync can then be undone with `bluevein restore <id>` without
+//! touching the ESP.
+
+use crate::bluetooth::BluetoothDevice;
+use crate::crypto::{self, ConfigCipher};
+use crate::error::BlueVeinError;
+use crate::report::Operation;
+use crate::types::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+#[cfg(target_os = "linux")]
+fn default_backup_dir() -> PathBuf {
+    PathBuf::from("/var/lib/bluevein/backups")
+}
+#[cfg(target_os = "windows")]
+fn default_backup_dir() -> PathBuf {
+    crate::paths::data_file("backups")
+}
+
+/// Number of backups kept, oldest are removed first
+const MAX_BACKUPS: usize = 20;
+
+/// State of one device before it was changed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeviceBackup {
+    pub adapter: MacAddress,
+    pub device: MacAddress,
+    /// Keys before the change, `None` if the device was not paired
+    pub state: Option<BluetoothDevice>,
+}
+
+/// Devices changed by one operation, as they were before it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Backup {
+    pub id: String,
+    /// Seconds since the Unix epoch
+    pub created_at: u64,
+    pub operation: Operation,
+    pub devices: Vec<DeviceBackup>,
+}
+
+impl Backup {
+    /// Start an empty backup for an operation started at `created_at`
+    pub fn new(operation: Operation, created_at: u64) -> Self {
+        Self {
+            id: format!("{}-{}", created_at, operation.slug()),
+            created_at,
+            operation,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Whether the device is already backed up (its first state is the one to keep)
+    pub fn contains(&self, adapter: &MacAddress, device: &MacAddress) -> bool {
+        self.devices
+            .iter()
+            .any(|entry| entry.adapter == *adapter && entry.device == *device)
+    }
+}
+
+impl fmt::Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}  {}, {} device(s)",
+            self.id,
+            self.operation,
+            self.devices.len()
+        )
+    }
+}
+
+/// Directory of local backups
+///
+/// Backups hold key material: files are only readable by root on Linux and
+/// are encrypted when config encryption is configured.
+pub struct BackupStore {
+    dir: PathBuf,
+}
+
+impl Default for BackupStore {
+    fn default() -> Self {
+        Self::new(default_backup_dir())
+    }
+}
+
+impl BackupStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    /// Give a new backup an id that is not taken yet
+    pub fn assign_id(&self, backup: &mut Backup) {
+        let base = backup.id.clone();
+        let mut n = 2;
+        while self.path(&backup.id).exists() {
+            backup.id = format!("{}-{}", base, n);
+            n += 1;
+        }
+    }
+
+    /// Write a backup, replacing an earlier version with the same id
+    pub fn save(
+        &self,
+        backup: &Backup,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<(), BlueVeinError> {
+        create_private_dir(&self.dir)
+            .map_err(|e| BlueVeinError::io("Failed to create backup directory", e))?;
+
+        let json = Zeroizing::new(serde_json::to_vec_pretty(backup).map_err(|e| {
+            BlueVeinError::BackendUnavailable(format!("Failed to serialize backup: {}", e))
+        })?);
+        let data = match cipher {
+            Some(cipher) => Zeroizing::new(cipher.seal(&json)?),
+            None => json,
+        };
+
+        // Write to a temporary file first so a crash never leaves a torn backup
+        let path = self.path(&backup.id);
+        let tmp = path.with_extension("json.tmp");
+        write_private_file(&tmp, &data)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| BlueVeinError::io(format!("Failed to write {}", path.display()), e))?;
+
+        self.prune();
+        Ok(())
+    }
+
+    /// Load a backup by id
+    pub fn load(&self, id: &str, cipher: Option<&ConfigCipher>) -> Result<Backup, BlueVeinError> {
+        if id.is_empty() || id.contains(['/', '\\', '.']) {
+            return Err(BlueVeinError::BackupNotFound(id.to_string()));
+        }
+        let data = Zeroizing::new(fs::read(self.path(id)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => BlueVeinError::BackupNotFound(id.to_string()),
+            _ => BlueVeinError::io(format!("Failed to read backup {}", id), e),
+        })?);
+        self.decode(&data, cipher)
+    }
+
+    fn decode(&self, data: &[u8], cipher: Option<&ConfigCipher>) -> Result<Backup, BlueVeinError> {
+        let plaintext = if crypto::is_envelope(data) {
+            let cipher = cipher.ok_or_else(|| {
+                BlueVeinError::Encryption(
+                    "backup is encrypted but no key is configured".to_string(),
+                )
+            })?;
+            cipher.open(data)?
+        } else {
+            Zeroizing::new(data.to_vec())
+        };
+        serde_json::from_slice(&plaintext)
+            .map_err(|e| BlueVeinError::StoreCorrupt(format!("backup: {}", e)))
+    }
+
+    /// All readable backups, newest first
+    pub fn list(&self, cipher: Option<&ConfigCipher>) -> Result<Vec<Backup>, BlueVeinError> {
+        let mut backups: Vec<Backup> = self
+            .ids()?
+            .iter()
+            .filter_map(|id| self.load(id, cipher).ok())
+            .collect();
+        backups.sort_by(|a, b| (b.created_at, &b.id).cmp(&(a.created_at, &a.id)));
+        Ok(backups)
+    }
+
+    /// Ids of stored backups, oldest first
+    fn ids(&self) -> Result<Vec<String>, BlueVeinError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(BlueVeinError::io("Failed to read backup directory", e)),
+        };
+
+        let mut ids: Vec<(u64, String)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let id = name.strip_suffix(".json")?.to_string();
+                let created_at = id.split('-').next()?.parse().ok()?;
+                Some((created_at, id))
+            })
+            .collect();
+        ids.sort();
+        Ok(ids.into_iter().map(|(_, id)| id).collect())
+    }
+
+    /// Remove the oldest backups beyond [`MAX_BACKUPS`]
+    fn prune(&self) {
+        let Ok(ids) = self.ids() else {
+            return;
+        };
+        for id in ids.iter().take(ids.len().saturating_sub(MAX_BACKUPS)) {
+            let _ = fs::remove_file(self.path(id));
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(target_os = "windows")]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(target_os = "linux")]
+fn write_private_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+#[cfg(target_os = "windows")]
+fn write_private_file(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeySource;
+    use tempfile::{tempdir, TempDir};
+
+    fn store(dir: &TempDir) -> BackupStore {
+        BackupStore::new(dir.path().join("backups"))
+    }
+
+    fn backup(created_at: u64) -> Backup {
+        let mut backup = Backup::new(Operation::Bidirectional, created_at);
+        backup.devices.push(DeviceBackup {
+            adapter: "00:11:22:33:44:55".parse().unwrap(),
+            device: "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+            state: Some(BluetoothDevice::classic(
+                "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+                "0123456789ABCDEF0123456789ABCDEF".parse().unwrap(),
+            )),
+        });
+        backup
+    }
+
+    #[test]
+    fn test_save_load_and_list() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        let first = backup(100);
+        store.save(&first, None).unwrap();
+
+        let mut second = backup(100);
+        store.assign_id(&mut second);
+        assert_eq!(second.id, "100-bidirectional-2");
+        store.save(&second, None).unwrap();
+
+        assert_eq!(store.load(&first.id, None).unwrap(), first);
+        let ids: Vec<String> = store
+            .list(None)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.id)
+            .collect();
+        assert_eq!(ids, vec!["100-bidirectional-2", "100-bidirectional"]);
+        assert!(matches!(
+            store.load("../etc/passwd", None),
+            Err(BlueVeinError::BackupNotFound(_))
+        ));
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(store.path(&first.id))
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn test_encrypted_backup_and_pruning() {
+        let dir = tempdir().unwrap();
+        let store = store(&dir);
+        let key_path = dir.path().join("bluevein.key");
+        fs::write(&key_path, [5u8; 32]).unwrap();
+        let cipher = ConfigCipher::new(KeySource::KeyFile(key_path));
+
+        for created_at in 0..(MAX_BACKUPS as u64 + 3) {
+            store.save(&backup(created_at), Some(&cipher)).unwrap();
+        }
+
+        let raw = fs::read(store.path("22-bidirectional")).unwrap();
+        assert!(!String::from_utf8_lossy(&raw).contains("0123456789ABCDEF"));
+        assert!(store.load("22-bidirectional", None).is_err());
+
+        let backups = store.list(Some(&cipher)).unwrap();
+        assert_eq!(backups.len(), MAX_BACKUPS);
+        assert_eq!(backups[0].id, "22-bidirectional");
+        assert!(store.load("0-bidirectional", Some(&cipher)).is_err());
+    }
+}
@@ -1,10 +1,13 @@
+use crate::config::{KnownFields, UnknownFields};
+use crate::error::BlueVeinError;
+use crate::settings::SyncSettings;
+use crate::types::{Key128, MacAddress};
 use serde::{Deserialize, Serialize};
-use std::error::Error;
 
 /// Long Term Key for BLE devices
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct LeLongTermKey {
-    pub key: String,
+    pub key: Key128,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub authenticated: Option<u8>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -13,6 +16,8 @@ pub struct LeLongTermKey {
     pub ediv: Option<u16>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rand: Option<u64>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 impl LeLongTermKey {
@@ -25,20 +30,23 @@ impl LeLongTermKey {
 /// Connection Signature Resolving Key with metadata
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct CsrkKey {
-    pub key: String,
+    pub key: Key128,
     #[serde(default)]
     pub counter: u32,
     #[serde(default)]
     pub authenticated: bool,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 impl CsrkKey {
     #[allow(dead_code)]
-    pub fn new(key: String) -> Self {
+    pub fn new(key: Key128) -> Self {
         Self {
             key,
             counter: 0,
             authenticated: false,
+            extra: UnknownFields::default(),
         }
     }
 }
@@ -51,23 +59,27 @@ pub struct LeKeys {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub peripheral_ltk: Option<LeLongTermKey>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub irk: Option<String>,
+    pub irk: Option<Key128>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub csrk_local: Option<CsrkKey>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub csrk_remote: Option<CsrkKey>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub address_type: Option<String>, // "public" or "random"
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 /// Classic Bluetooth specific keys
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ClassicKeys {
-    pub link_key: String,
+    pub link_key: Key128,
     #[serde(default = "default_link_key_type")]
     pub key_type: u8,
     #[serde(default)]
     pub pin_length: u8,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 fn default_link_key_type() -> u8 {
@@ -76,11 +88,12 @@ fn default_link_key_type() -> u8 {
 
 impl ClassicKeys {
     #[allow(dead_code)]
-    pub fn new(link_key: String) -> Self {
+    pub fn new(link_key: Key128) -> Self {
         Self {
             link_key,
             key_type: 4,
             pin_length: 0,
+            extra: UnknownFields::default(),
         }
     }
 }
@@ -88,27 +101,74 @@ impl ClassicKeys {
 /// Bluetooth device information (supports both Classic and LE)
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct BluetoothDevice {
-    pub mac_address: String,
+    pub mac_address: MacAddress,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub classic: Option<ClassicKeys>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub le: Option<LeKeys>,
+    /// Name reported by the device, used by sync filters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Class of Device (Classic devices only), used by sync filters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<u32>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl KnownFields for LeLongTermKey {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+    }
+}
+
+impl KnownFields for CsrkKey {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+    }
+}
+
+impl KnownFields for LeKeys {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+        self.ltk.clear_unknown_fields();
+        self.peripheral_ltk.clear_unknown_fields();
+        self.csrk_local.clear_unknown_fields();
+        self.csrk_remote.clear_unknown_fields();
+    }
+}
+
+impl KnownFields for ClassicKeys {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+    }
+}
+
+impl KnownFields for BluetoothDevice {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+        self.classic.clear_unknown_fields();
+        self.le.clear_unknown_fields();
+    }
 }
 
 impl BluetoothDevice {
     /// Create a classic Bluetooth device
     #[allow(dead_code)]
-    pub fn classic(mac_address: String, link_key: String) -> Self {
+    pub fn classic(mac_address: MacAddress, link_key: Key128) -> Self {
         Self {
             mac_address,
             classic: Some(ClassicKeys::new(link_key)),
             le: None,
+            name: None,
+            class: None,
+            extra: UnknownFields::default(),
         }
     }
 
     /// Create a BLE device with LTK
     #[allow(dead_code)]
-    pub fn le_with_ltk(mac_address: String, ltk: LeLongTermKey) -> Self {
+    pub fn le_with_ltk(mac_address: MacAddress, ltk: LeLongTermKey) -> Self {
         Self {
             mac_address,
             classic: None,
@@ -116,6 +176,9 @@ impl BluetoothDevice {
                 ltk: Some(ltk),
                 ..Default::default()
             }),
+            name: None,
+            class: None,
+            extra: UnknownFields::default(),
         }
     }
 
@@ -124,22 +187,52 @@ impl BluetoothDevice {
         self.classic.is_some() || self.le.is_some()
     }
 
+    /// Keep fields unknown to this build from a previous version of the device
+    pub fn inherit_unknown_fields(&mut self, previous: &BluetoothDevice) {
+        self.extra.inherit(&previous.extra);
+        if let (Some(classic), Some(prev)) = (&mut self.classic, &previous.classic) {
+            classic.extra.inherit(&prev.extra);
+        }
+        if let (Some(le), Some(prev)) = (&mut self.le, &previous.le) {
+            le.extra.inherit(&prev.extra);
+            if let (Some(ltk), Some(prev)) = (&mut le.ltk, &prev.ltk) {
+                ltk.extra.inherit(&prev.extra);
+            }
+            if let (Some(ltk), Some(prev)) = (&mut le.peripheral_ltk, &prev.peripheral_ltk) {
+                ltk.extra.inherit(&prev.extra);
+            }
+            if let (Some(csrk), Some(prev)) = (&mut le.csrk_local, &prev.csrk_local) {
+                csrk.extra.inherit(&prev.extra);
+            }
+            if let (Some(csrk), Some(prev)) = (&mut le.csrk_remote, &prev.csrk_remote) {
+                csrk.extra.inherit(&prev.extra);
+            }
+        }
+    }
+
     /// Merge two devices, combining keys from both
     /// Useful for dual-mode devices or when syncing between platforms
     pub fn merge_with(&self, other: &BluetoothDevice) -> BluetoothDevice {
+        let mut extra = other.extra.clone();
+        extra.inherit(&self.extra);
         BluetoothDevice {
-            mac_address: self.mac_address.clone(),
+            mac_address: self.mac_address,
             classic: other.classic.clone().or_else(|| self.classic.clone()),
             le: match (&self.le, &other.le) {
                 (Some(le1), Some(le2)) => Some(Self::merge_le_keys(le1, le2)),
                 (Some(le), None) | (None, Some(le)) => Some(le.clone()),
                 (None, None) => None,
             },
+            name: other.name.clone().or_else(|| self.name.clone()),
+            class: other.class.or(self.class),
+            extra,
         }
     }
 
     /// Merge LE keys from two sources, preferring non-None values from other
     fn merge_le_keys(le1: &LeKeys, le2: &LeKeys) -> LeKeys {
+        let mut extra = le2.extra.clone();
+        extra.inherit(&le1.extra);
         LeKeys {
             ltk: le2.ltk.clone().or_else(|| le1.ltk.clone()),
             peripheral_ltk: le2
@@ -153,6 +246,7 @@ impl BluetoothDevice {
                 .address_type
                 .clone()
                 .or_else(|| le1.address_type.clone()),
+            extra,
         }
     }
 }
@@ -169,23 +263,28 @@ impl BluetoothDevice {
 ///
 /// # Returns
 /// * `Ok(())` if key is valid
-/// * `Err` with descriptive message if invalid
-pub fn validate_bluetooth_key(key: &str, key_name: &str) -> Result<(), Box<dyn Error>> {
+/// * `Err(BlueVeinError::InvalidKey)` with descriptive message if invalid
+pub fn validate_bluetooth_key(key: &str, key_name: &str) -> Result<(), BlueVeinError> {
     const EXPECTED_LENGTH: usize = 32; // 16 bytes = 32 hex chars
 
-    // Check if all characters are valid hex
+    // Check if all characters are valid hex (never echo the key itself)
     if !key.chars().all(|c| c.is_ascii_hexdigit()) {
-        return Err(format!("{} contains non-hexadecimal characters: {}", key_name, key).into());
+        return Err(BlueVeinError::invalid_key(
+            key_name,
+            "contains non-hexadecimal characters",
+        ));
     }
 
     // Check length
     let actual_length = key.len();
     if actual_length != EXPECTED_LENGTH {
-        return Err(format!(
-            "{} has invalid length: expected {} hex characters (16 bytes), got {} characters",
-            key_name, EXPECTED_LENGTH, actual_length
-        )
-        .into());
+        return Err(BlueVeinError::invalid_key(
+            key_name,
+            format!(
+                "invalid length: expected {} hex characters (16 bytes), got {} characters",
+                EXPECTED_LENGTH, actual_length
+            ),
+        ));
     }
 
     Ok(())
@@ -194,91 +293,55 @@ pub fn validate_bluetooth_key(key: &str, key_name: &str) -> Result<(), Box<dyn E
 /// Trait for platform-specific Bluetooth management
 pub trait BluetoothManager: Send {
     /// Get list of Bluetooth adapter MAC addresses
-    fn get_adapters(&self) -> Result<Vec<String>, Box<dyn Error>>;
+    fn get_adapters(&self) -> Result<Vec<MacAddress>, BlueVeinError>;
 
     /// Get all paired devices for an adapter
-    fn get_devices(&self, adapter_mac: &str) -> Result<Vec<BluetoothDevice>, Box<dyn Error>>;
+    fn get_devices(&self, adapter_mac: &MacAddress) -> Result<Vec<BluetoothDevice>, BlueVeinError>;
 
     /// Get specific device information
+    ///
+    /// Returns `BlueVeinError::DeviceNotFound` if the device has no pairing keys.
     fn get_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<BluetoothDevice, Box<dyn Error>>;
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<BluetoothDevice, BlueVeinError>;
 
     /// Set/update device keys (both classic and LE)
     fn set_device(
         &mut self,
-        adapter_mac: &str,
+        adapter_mac: &MacAddress,
         device: &BluetoothDevice,
-    ) -> Result<(), Box<dyn Error>>;
-
-    /// Remove device
-    #[allow(dead_code)]
-    fn remove_device(&mut self, adapter_mac: &str, device_mac: &str) -> Result<(), Box<dyn Error>>;
-}
-
-/// Format MAC address to standard format (XX:XX:XX:XX:XX:XX)
-pub fn normalize_mac(mac: &str) -> String {
-    let cleaned: String = mac.chars().filter(|c| c.is_alphanumeric()).collect();
-    let mut result = String::new();
+    ) -> Result<(), BlueVeinError>;
 
-    for (i, c) in cleaned.chars().enumerate() {
-        if i > 0 && i % 2 == 0 {
-            result.push(':');
-        }
-        result.push(c.to_ascii_uppercase());
-    }
-
-    result
-}
-
-/// Convert MAC address to Windows registry format (XX:XX:XX:XX:XX:XX -> XXXXXXXXXXXX)
-#[allow(dead_code)]
-pub fn mac_to_windows_format(mac: &str) -> String {
-    mac.chars()
-        .filter(|c| c.is_alphanumeric())
-        .collect::<String>()
-        .to_uppercase()
-}
-
-/// Convert Windows registry MAC format to standard (XXXXXXXXXXXX -> XX:XX:XX:XX:XX:XX)
-#[allow(dead_code)]
-pub fn windows_format_to_mac(win_mac: &str) -> String {
-    normalize_mac(win_mac)
-}
+    /// Remove device and its keys (no-op if it is not paired)
+    fn remove_device(
+        &mut self,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<(), BlueVeinError>;
 
-/// Check if a string is a valid MAC address in raw hex format (12 hex characters, no separators).
-/// Used to filter out non-MAC registry values like "CentralIRK", "LocalIRK", etc.
-#[allow(dead_code)]
-pub fn is_valid_mac_hex(name: &str) -> bool {
-    name.len() == 12 && name.chars().all(|c| c.is_ascii_hexdigit())
+    /// Apply changed daemon settings
+    fn apply_settings(&mut self, _settings: &SyncSettings) {}
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_normalize_mac() {
-        assert_eq!(normalize_mac("aabbccddeeff"), "AA:BB:CC:DD:EE:FF");
-        assert_eq!(normalize_mac("aa:bb:cc:dd:ee:ff"), "AA:BB:CC:DD:EE:FF");
-        assert_eq!(normalize_mac("AA-BB-CC-DD-EE-FF"), "AA:BB:CC:DD:EE:FF");
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
     }
 
-    #[test]
-    fn test_mac_conversions() {
-        let mac = "AA:BB:CC:DD:EE:FF";
-        let win_format = mac_to_windows_format(mac);
-        assert_eq!(win_format, "AABBCCDDEEFF");
-        assert_eq!(windows_format_to_mac(&win_format), mac);
+    fn key(s: &str) -> Key128 {
+        s.parse().unwrap()
     }
 
     #[test]
     fn test_classic_device() {
         let device = BluetoothDevice::classic(
-            "AA:BB:CC:DD:EE:FF".to_string(),
-            "0123456789ABCDEF".to_string(),
+            mac("AA:BB:CC:DD:EE:FF"),
+            key("0123456789ABCDEF0123456789ABCDEF"),
         );
         assert!(device.classic.is_some());
         assert!(device.le.is_none());
@@ -292,13 +355,14 @@ mod tests {
     #[test]
     fn test_le_device() {
         let ltk = LeLongTermKey {
-            key: "0123456789ABCDEF".to_string(),
+            key: key("0123456789ABCDEF0123456789ABCDEF"),
             authenticated: Some(1),
             enc_size: Some(16),
             ediv: Some(100),
             rand: Some(12345),
+            extra: UnknownFields::default(),
         };
-        let device = BluetoothDevice::le_with_ltk("AA:BB:CC:DD:EE:FF".to_string(), ltk);
+        let device = BluetoothDevice::le_with_ltk(mac("AA:BB:CC:DD:EE:FF"), ltk);
         assert!(device.classic.is_none());
         assert!(device.le.is_some());
         assert!(device.has_keys());
@@ -307,36 +371,38 @@ mod tests {
     #[test]
     fn test_ltk_authenticated_default() {
         let ltk = LeLongTermKey {
-            key: "0123456789ABCDEF".to_string(),
+            key: key("0123456789ABCDEF0123456789ABCDEF"),
             authenticated: None,
             enc_size: Some(16),
             ediv: Some(100),
             rand: Some(12345),
+            extra: UnknownFields::default(),
         };
         assert_eq!(ltk.authenticated_or_default(), 0);
     }
 
     #[test]
     fn test_csrk_key_creation() {
-        let csrk = CsrkKey::new("0123456789ABCDEF".to_string());
+        let csrk = CsrkKey::new(key("0123456789ABCDEF0123456789ABCDEF"));
         assert_eq!(csrk.counter, 0);
-        assert_eq!(csrk.authenticated, false);
+        assert!(!csrk.authenticated);
     }
 
     #[test]
     fn test_merge_devices() {
         let device1 = BluetoothDevice::classic(
-            "AA:BB:CC:DD:EE:FF".to_string(),
-            "0123456789ABCDEF".to_string(),
+            mac("AA:BB:CC:DD:EE:FF"),
+            key("0123456789ABCDEF0123456789ABCDEF"),
         );
         let ltk = LeLongTermKey {
-            key: "FEDCBA9876543210".to_string(),
+            key: key("FEDCBA9876543210FEDCBA9876543210"),
             authenticated: Some(1),
             enc_size: Some(16),
             ediv: Some(100),
             rand: Some(12345),
+            extra: UnknownFields::default(),
         };
-        let device2 = BluetoothDevice::le_with_ltk("AA:BB:CC:DD:EE:FF".to_string(), ltk);
+        let device2 = BluetoothDevice::le_with_ltk(mac("AA:BB:CC:DD:EE:FF"), ltk);
 
         let merged = device1.merge_with(&device2);
         assert!(merged.classic.is_some());
@@ -370,8 +436,10 @@ mod tests {
     fn test_validate_bluetooth_key_invalid_chars() {
         let key = "0123456789ABCDEFGHIJ456789ABCDEF"; // Contains G, H, I, J
         let result = validate_bluetooth_key(key, "TestKey");
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("non-hexadecimal"));
+        assert!(matches!(result, Err(BlueVeinError::InvalidKey { .. })));
+        let message = result.unwrap_err().to_string();
+        assert!(message.contains("non-hexadecimal"));
+        assert!(!message.contains(key));
     }
 
     #[test]
@@ -380,32 +448,4 @@ mod tests {
         let key = "0123456789abcdef0123456789abcdef";
         assert!(validate_bluetooth_key(key, "TestKey").is_ok());
     }
-
-    #[test]
-    fn test_is_valid_mac_hex_valid() {
-        assert!(is_valid_mac_hex("AABBCCDDEEFF"));
-        assert!(is_valid_mac_hex("aabbccddeeff"));
-        assert!(is_valid_mac_hex("98038E6A754D"));
-    }
-
-    #[test]
-    fn test_is_valid_mac_hex_rejects_special_keys() {
-        // These are real Windows registry value names that are NOT device MACs
-        assert!(!is_valid_mac_hex("CentralIRK"));
-        assert!(!is_valid_mac_hex("LocalIRK"));
-        assert!(!is_valid_mac_hex("MasterIRK"));
-    }
-
-    #[test]
-    fn test_is_valid_mac_hex_rejects_wrong_length() {
-        assert!(!is_valid_mac_hex("AABBCCDDEE"));     // 10 chars - too short
-        assert!(!is_valid_mac_hex("AABBCCDDEEFF00")); // 14 chars - too long
-        assert!(!is_valid_mac_hex(""));                // empty
-    }
-
-    #[test]
-    fn test_is_valid_mac_hex_rejects_non_hex() {
-        assert!(!is_valid_mac_hex("AABBCCDDEEGG")); // G is not hex
-        assert!(!is_valid_mac_hex("AA:BB:CC:DD:E")); // contains colons
-    }
 }
@@ -1,21 +1,185 @@
+//! Shared configuration stored in bluevein.json
+//!
+//! The file carries a `schema_version`. Older layouts are upgraded on read by
+//! a chain of migrations, fields unknown to this build are preserved across a
+//! read-modify-write, and `min_writer_version` lets a newer BlueVein stop an
+//! older one from writing back data it cannot represent.
+
 use crate::bluetooth::BluetoothDevice;
+use crate::inventory::Installation;
+use crate::snapshot::Snapshot;
+use crate::types::MacAddress;
+use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use zeroize::Zeroize;
+
+/// Granularity of last-seen and last-sync stamps, in seconds
+///
+/// Stamps are only advanced once they are this old, so a sync that changes
+/// nothing else does not have to rewrite the store.
+pub const STAMP_RESOLUTION_SECS: u64 = 24 * 60 * 60;
+
+/// Layout version written by this build
+///
+/// * 1 - flat map of adapter MAC -> devices (no version field)
+/// * 2 - versioned object with an `adapters` map
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Oldest schema version that can safely write configs produced by this build
+const MIN_WRITER_VERSION: u32 = 2;
+
+/// Upgrade step from one schema version to the next
+type Migration = fn(Value) -> Result<Value, String>;
+
+/// Upgrade step from version `n` to `n + 1` is `MIGRATIONS[n - 1]`
+const MIGRATIONS: &[Migration] = &[migrate_v1_to_v2];
+
+/// JSON fields this build does not know about
+///
+/// Kept verbatim so a read-modify-write does not drop data written by a newer
+/// BlueVein. They take part in `==`; comparisons that only care about what
+/// this build understands use [`KnownFields::eq_known_fields`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UnknownFields(Map<String, Value>);
+
+impl UnknownFields {
+    /// Copy over fields from a previous version of the same value that are missing here
+    pub fn inherit(&mut self, previous: &UnknownFields) {
+        for (name, value) in &previous.0 {
+            self.0.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+    }
+
+    /// Drop every field
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Values carrying [`UnknownFields`], possibly nested
+pub trait KnownFields: Clone + PartialEq {
+    /// Drop fields unknown to this build, here and in nested values
+    fn clear_unknown_fields(&mut self);
+
+    /// Compare only the fields this build knows about
+    ///
+    /// Used to decide whether something changed when one side was read from
+    /// the system and the other from a store a newer BlueVein may have written.
+    fn eq_known_fields(&self, other: &Self) -> bool {
+        let (mut this, mut other) = (self.clone(), other.clone());
+        this.clear_unknown_fields();
+        other.clear_unknown_fields();
+        this == other
+    }
+}
+
+impl<T: KnownFields> KnownFields for Option<T> {
+    fn clear_unknown_fields(&mut self) {
+        if let Some(value) = self {
+            value.clear_unknown_fields();
+        }
+    }
+}
 
 /// Bluetooth device configuration for an adapter
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
 pub struct DeviceConfig {
     /// Paired devices: MAC address -> Device info (Classic and/or LE keys)
-    pub devices: HashMap<String, BluetoothDevice>,
+    pub devices: BTreeMap<MacAddress, BluetoothDevice>,
+    /// When an installation last saw this adapter, seconds since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+    /// Device MAC address -> when an installation last had it paired
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub devices_last_seen: BTreeMap<MacAddress, u64>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+/// Marker for a device forgotten on every installation
+///
+/// Installations that still have the device paired remove it on their next
+/// sync and acknowledge the tombstone; it is dropped once every known
+/// installation has done so.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Tombstone {
+    /// When the device was forgotten, seconds since the Unix epoch
+    pub forgotten_at: u64,
+    /// Ids of installations that have removed the device locally
+    #[serde(default)]
+    pub acknowledged: Vec<String>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
 }
 
 /// Root configuration structure
-/// Key: Adapter MAC address
-/// Value: Device configuration for that adapter
-#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct BlueVeinConfig {
+    /// Layout version of this config
+    pub schema_version: u32,
+    /// Oldest schema version allowed to write this config back
+    pub min_writer_version: u32,
+    /// Incremented on every write
+    #[serde(default)]
+    pub generation: u64,
+    /// Adapter MAC address -> device configuration for that adapter
+    #[serde(default)]
+    pub adapters: BTreeMap<MacAddress, DeviceConfig>,
+    /// Installation id -> what that install last reported about itself
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub installations: BTreeMap<String, Installation>,
+    /// Device MAC address -> tombstone of a device forgotten everywhere
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tombstones: BTreeMap<MacAddress, Tombstone>,
     #[serde(flatten)]
-    pub adapters: HashMap<String, DeviceConfig>,
+    pub extra: UnknownFields,
+}
+
+impl Default for BlueVeinConfig {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            min_writer_version: MIN_WRITER_VERSION,
+            generation: 0,
+            adapters: BTreeMap::new(),
+            installations: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+            extra: UnknownFields::default(),
+        }
+    }
+}
+
+impl KnownFields for DeviceConfig {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+        for device in self.devices.values_mut() {
+            device.clear_unknown_fields();
+        }
+    }
+}
+
+impl KnownFields for Tombstone {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+    }
+}
+
+impl KnownFields for BlueVeinConfig {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+        for adapter in self.adapters.values_mut() {
+            adapter.clear_unknown_fields();
+        }
+        for installation in self.installations.values_mut() {
+            installation.clear_unknown_fields();
+        }
+        for tombstone in self.tombstones.values_mut() {
+            tombstone.clear_unknown_fields();
+        }
+    }
 }
 
 impl BlueVeinConfig {
@@ -24,9 +188,28 @@ impl BlueVeinConfig {
         Self::default()
     }
 
-    /// Parse configuration from JSON string
+    /// Parse configuration from JSON string, migrating older layouts
     pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(json)
+        let mut value: Value = serde_json::from_str(json)?;
+        let version = schema_version_of(&value).map_err(serde_json::Error::custom)?;
+
+        let result = migrate(&mut value, version)
+            .map_err(serde_json::Error::custom)
+            .and_then(|_| Self::deserialize(&value));
+
+        // The parsed tree holds key material as plain strings
+        wipe(&mut value);
+
+        result.map_err(|e| {
+            if version > SCHEMA_VERSION {
+                serde_json::Error::custom(format!(
+                    "config uses schema version {} (this build supports up to {}): {}",
+                    version, SCHEMA_VERSION, e
+                ))
+            } else {
+                e
+            }
+        })
     }
 
     /// Serialize configuration to JSON string
@@ -34,60 +217,412 @@ impl BlueVeinConfig {
         serde_json::to_string_pretty(self)
     }
 
+    /// Check that this build may write the config back without degrading it
+    pub fn check_writable(&self) -> Result<(), String> {
+        if self.min_writer_version > SCHEMA_VERSION {
+            return Err(format!(
+                "config requires BlueVein schema version {} or newer to write (this build supports {}); update BlueVein",
+                self.min_writer_version, SCHEMA_VERSION
+            ));
+        }
+        Ok(())
+    }
+
     /// Get devices for a specific adapter
     pub fn get_adapter_devices(
         &self,
-        adapter_mac: &str,
-    ) -> Option<&HashMap<String, BluetoothDevice>> {
+        adapter_mac: &MacAddress,
+    ) -> Option<&BTreeMap<MacAddress, BluetoothDevice>> {
         self.adapters.get(adapter_mac).map(|config| &config.devices)
     }
 
     /// Set devices for a specific adapter
+    ///
+    /// Unknown fields of the adapter and of devices that are kept are preserved.
     pub fn set_adapter_devices(
         &mut self,
-        adapter_mac: String,
-        devices: HashMap<String, BluetoothDevice>,
+        adapter_mac: MacAddress,
+        mut devices: BTreeMap<MacAddress, BluetoothDevice>,
     ) {
-        self.adapters.insert(adapter_mac, DeviceConfig { devices });
+        let adapter = self.adapters.entry(adapter_mac).or_default();
+        for (device_mac, device) in devices.iter_mut() {
+            if let Some(previous) = adapter.devices.get(device_mac) {
+                device.inherit_unknown_fields(previous);
+            }
+        }
+        adapter.devices = devices;
+        let DeviceConfig {
+            devices,
+            devices_last_seen,
+            ..
+        } = adapter;
+        devices_last_seen.retain(|device_mac, _| devices.contains_key(device_mac));
     }
 
     /// Add or update a single device for an adapter
-    pub fn update_device(&mut self, adapter_mac: String, device: BluetoothDevice) {
-        let device_mac = device.mac_address.clone();
-        self.adapters
-            .entry(adapter_mac)
-            .or_insert_with(|| DeviceConfig {
-                devices: HashMap::new(),
+    ///
+    /// Unknown fields of the previous entry for this device are preserved.
+    /// A tombstone for the device is dropped, since it has been paired again.
+    pub fn update_device(&mut self, adapter_mac: MacAddress, mut device: BluetoothDevice) {
+        let device_mac = device.mac_address;
+        self.tombstones.remove(&device_mac);
+        let adapter = self.adapters.entry(adapter_mac).or_default();
+        if let Some(previous) = adapter.devices.get(&device_mac) {
+            device.inherit_unknown_fields(previous);
+        }
+        adapter.devices.insert(device_mac, device);
+    }
+
+    /// Record the inventory entry of an installation, keeping its unknown fields
+    ///
+    /// `last_sync` keeps its previous value if nothing else changed and it is
+    /// younger than [`STAMP_RESOLUTION_SECS`].
+    pub fn record_installation(&mut self, id: String, mut installation: Installation) {
+        if let Some(previous) = self.installations.get(&id) {
+            installation.extra.inherit(&previous.extra);
+            let unchanged = Installation {
+                last_sync: previous.last_sync,
+                ..installation.clone()
+            }
+            .eq_known_fields(previous);
+            let recent =
+                installation.last_sync.saturating_sub(previous.last_sync) < STAMP_RESOLUTION_SECS;
+            if unchanged && recent {
+                installation.last_sync = previous.last_sync;
+            }
+        }
+        self.installations.insert(id, installation);
+    }
+
+    /// Remove a device from every adapter and leave a tombstone for other installations
+    ///
+    /// `origin` is the id of the installation doing this, which has already
+    /// removed the device locally. Returns whether the device was in the config.
+    pub fn forget_device(
+        &mut self,
+        device_mac: MacAddress,
+        origin: Option<&str>,
+        now: u64,
+    ) -> bool {
+        let mut found = false;
+        for adapter in self.adapters.values_mut() {
+            found |= adapter.devices.remove(&device_mac).is_some();
+            adapter.devices_last_seen.remove(&device_mac);
+        }
+
+        self.tombstones.insert(
+            device_mac,
+            Tombstone {
+                forgotten_at: now,
+                acknowledged: origin.map(str::to_string).into_iter().collect(),
+                extra: UnknownFields::default(),
+            },
+        );
+        found
+    }
+
+    /// Devices whose tombstones the given installation has not acknowledged yet
+    ///
+    /// Without an installation id every tombstone is pending.
+    pub fn pending_tombstones(&self, installation_id: Option<&str>) -> Vec<MacAddress> {
+        let mut pending: Vec<MacAddress> = self
+            .tombstones
+            .iter()
+            .filter(|(_, tombstone)| match installation_id {
+                Some(id) => !tombstone.acknowledged.iter().any(|acked| acked == id),
+                None => true,
             })
-            .devices
-            .insert(device_mac, device);
+            .map(|(device_mac, _)| *device_mac)
+            .collect();
+        pending.sort();
+        pending
+    }
+
+    /// Mark a tombstone as applied by an installation
+    pub fn acknowledge_tombstone(&mut self, device_mac: &MacAddress, installation_id: &str) {
+        if let Some(tombstone) = self.tombstones.get_mut(device_mac) {
+            if !tombstone
+                .acknowledged
+                .iter()
+                .any(|id| id == installation_id)
+            {
+                tombstone.acknowledged.push(installation_id.to_string());
+            }
+        }
+    }
+
+    /// Drop tombstones acknowledged by every known installation
+    pub fn prune_tombstones(&mut self) {
+        if self.installations.is_empty() {
+            return;
+        }
+        let installations = &self.installations;
+        self.tombstones.retain(|_, tombstone| {
+            !installations
+                .keys()
+                .all(|id| tombstone.acknowledged.contains(id))
+        });
+    }
+
+    /// Replay the changes `self` made to `base` on top of `theirs`
+    ///
+    /// Used when the store was written by someone else after `base` was
+    /// read. Added, changed and removed devices, new tombstones and
+    /// acknowledgements and updated inventory entries are carried over;
+    /// everything else comes from `theirs`.
+    pub fn rebase(&self, base: &BlueVeinConfig, theirs: &BlueVeinConfig) -> BlueVeinConfig {
+        let mut merged = theirs.clone();
+
+        for (adapter_mac, adapter) in &self.adapters {
+            for (device_mac, device) in &adapter.devices {
+                let changed = base
+                    .get_device(adapter_mac, device_mac)
+                    .is_none_or(|base_device| !base_device.eq_known_fields(device));
+                if changed {
+                    merged.update_device(*adapter_mac, device.clone());
+                }
+            }
+        }
+        for (adapter_mac, adapter) in &base.adapters {
+            if !self.adapters.contains_key(adapter_mac) {
+                merged.adapters.remove(adapter_mac);
+                continue;
+            }
+            for device_mac in adapter.devices.keys() {
+                if self.get_device(adapter_mac, device_mac).is_none() {
+                    if let Some(merged_adapter) = merged.adapters.get_mut(adapter_mac) {
+                        merged_adapter.devices.remove(device_mac);
+                        merged_adapter.devices_last_seen.remove(device_mac);
+                    }
+                }
+            }
+        }
+
+        for (device_mac, tombstone) in &self.tombstones {
+            match merged.tombstones.get_mut(device_mac) {
+                Some(merged_tombstone) => {
+                    for id in &tombstone.acknowledged {
+                        if !merged_tombstone.acknowledged.contains(id) {
+                            merged_tombstone.acknowledged.push(id.clone());
+                        }
+                    }
+                }
+                // Tombstones pruned by `theirs` stay pruned
+                None if base.tombstones.contains_key(device_mac) => {}
+                None => {
+                    merged.tombstones.insert(*device_mac, tombstone.clone());
+                }
+            }
+        }
+
+        for (id, installation) in &self.installations {
+            let changed = base
+                .installations
+                .get(id)
+                .is_none_or(|base_installation| !base_installation.eq_known_fields(installation));
+            if changed {
+                merged
+                    .installations
+                    .insert(id.clone(), installation.clone());
+            }
+        }
+        merged
+    }
+
+    /// Merge `self` onto `theirs` when the config `self` was read from is gone
+    ///
+    /// Without a base, removals cannot be told apart from additions, so
+    /// devices only one side has are kept. Devices both sides changed come
+    /// from the side with the newer last-seen stamp; every written device is
+    /// stamped, so an unstamped device here was paired since the read and
+    /// counts as newest. Tombstones from both sides are kept and remove the
+    /// device unless it was paired here since the read.
+    pub fn merge_by_stamps(&self, theirs: &BlueVeinConfig) -> BlueVeinConfig {
+        let mut merged = theirs.clone();
+
+        for (adapter_mac, adapter) in &self.adapters {
+            let merged_adapter = merged.adapters.entry(*adapter_mac).or_default();
+            merged_adapter.last_seen = merged_adapter.last_seen.max(adapter.last_seen);
+            merged_adapter.extra.inherit(&adapter.extra);
+            for (device_mac, device) in &adapter.devices {
+                let our_stamp = adapter.devices_last_seen.get(device_mac).copied();
+                let their_stamp = merged_adapter.devices_last_seen.get(device_mac).copied();
+                let take_ours = match merged_adapter.devices.get(device_mac) {
+                    None => true,
+                    Some(their_device) => {
+                        !their_device.eq_known_fields(device)
+                            && our_stamp.is_none_or(|ours| ours >= their_stamp.unwrap_or(0))
+                    }
+                };
+                if !take_ours {
+                    continue;
+                }
+
+                let mut device = device.clone();
+                if let Some(previous) = merged_adapter.devices.get(device_mac) {
+                    device.inherit_unknown_fields(previous);
+                }
+                merged_adapter.devices.insert(*device_mac, device);
+                match our_stamp {
+                    Some(stamp) => {
+                        let stamp = stamp.max(their_stamp.unwrap_or(0));
+                        merged_adapter.devices_last_seen.insert(*device_mac, stamp);
+                    }
+                    None => {
+                        merged_adapter.devices_last_seen.remove(device_mac);
+                    }
+                }
+            }
+        }
+
+        for (device_mac, tombstone) in &self.tombstones {
+            let merged_tombstone = merged
+                .tombstones
+                .entry(*device_mac)
+                .or_insert_with(|| tombstone.clone());
+            merged_tombstone.forgotten_at =
+                merged_tombstone.forgotten_at.max(tombstone.forgotten_at);
+            for id in &tombstone.acknowledged {
+                if !merged_tombstone.acknowledged.contains(id) {
+                    merged_tombstone.acknowledged.push(id.clone());
+                }
+            }
+        }
+        // Stamped devices were seen before the tombstone was written,
+        // unstamped ones were paired again since
+        let tombstones = &mut merged.tombstones;
+        for adapter in merged.adapters.values_mut() {
+            let DeviceConfig {
+                devices,
+                devices_last_seen,
+                ..
+            } = adapter;
+            devices.retain(|device_mac, _| {
+                !(tombstones.contains_key(device_mac)
+                    && devices_last_seen.remove(device_mac).is_some())
+            });
+            for device_mac in devices.keys() {
+                tombstones.remove(device_mac);
+            }
+        }
+
+        for (id, installation) in &self.installations {
+            merged
+                .installations
+                .entry(id.clone())
+                .or_insert_with(|| installation.clone());
+        }
+        merged
+    }
+
+    /// Stamp adapters and devices paired on this system as seen at `now`
+    ///
+    /// Entries that have never been stamped (written before stamps existed)
+    /// start their clock now, so retention never removes them right away.
+    /// Stamps younger than [`STAMP_RESOLUTION_SECS`] are left as they are.
+    pub fn mark_seen(&mut self, snapshot: &Snapshot, now: u64) {
+        let refresh = |stamp: &mut u64| {
+            if now.saturating_sub(*stamp) >= STAMP_RESOLUTION_SECS {
+                *stamp = now;
+            }
+        };
+
+        for (adapter_mac, device_macs) in snapshot.adapters() {
+            if let Some(adapter) = self.adapters.get_mut(adapter_mac) {
+                refresh(adapter.last_seen.get_or_insert(now));
+                for device_mac in device_macs {
+                    if adapter.devices.contains_key(device_mac) {
+                        refresh(adapter.devices_last_seen.entry(*device_mac).or_insert(now));
+                    }
+                }
+            }
+        }
+
+        for adapter in self.adapters.values_mut() {
+            adapter.last_seen.get_or_insert(now);
+            for device_mac in adapter.devices.keys() {
+                adapter.devices_last_seen.entry(*device_mac).or_insert(now);
+            }
+        }
     }
 
     /// Get a specific device
-    pub fn get_device(&self, adapter_mac: &str, device_mac: &str) -> Option<&BluetoothDevice> {
+    pub fn get_device(
+        &self,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Option<&BluetoothDevice> {
         self.get_adapter_devices(adapter_mac)
             .and_then(|devices| devices.get(device_mac))
     }
 }
 
+/// Detect the layout version of a parsed config
+fn schema_version_of(value: &Value) -> Result<u32, String> {
+    match value.get("schema_version") {
+        // Version 1 had no version field
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| format!("invalid schema_version {}", version)),
+    }
+}
+
+/// Run the migration chain from `version` up to [`SCHEMA_VERSION`]
+fn migrate(value: &mut Value, version: u32) -> Result<(), String> {
+    for step in MIGRATIONS.iter().skip(version as usize - 1) {
+        *value = step(value.take())?;
+    }
+    Ok(())
+}
+
+/// 1 -> 2: wrap the flat adapter map into a versioned object
+fn migrate_v1_to_v2(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Object(adapters) => Ok(json!({
+            "schema_version": 2,
+            "min_writer_version": 2,
+            "adapters": adapters,
+        })),
+        _ => Err("expected a JSON object".to_string()),
+    }
+}
+
+/// Zero every string in a JSON tree
+fn wipe(value: &mut Value) {
+    match value {
+        Value::String(s) => s.zeroize(),
+        Value::Array(items) => items.iter_mut().for_each(wipe),
+        Value::Object(map) => map.values_mut().for_each(wipe),
+        _ => {}
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::bluetooth::BluetoothDevice;
 
+    const ADAPTER: &str = "00:11:22:33:44:55";
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+    const KEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
     #[test]
     fn test_config_serialization() {
         let mut config = BlueVeinConfig::new();
-        let mut devices = HashMap::new();
+        let mut devices = BTreeMap::new();
         devices.insert(
-            "AA:BB:CC:DD:EE:FF".to_string(),
-            BluetoothDevice::classic(
-                "AA:BB:CC:DD:EE:FF".to_string(),
-                "0123456789ABCDEF".to_string(),
-            ),
+            mac(DEVICE),
+            BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap()),
         );
 
-        config.set_adapter_devices("00:11:22:33:44:55".to_string(), devices);
+        config.set_adapter_devices(mac(ADAPTER), devices);
 
         let json = config.to_json().unwrap();
         let parsed = BlueVeinConfig::from_json(&json).unwrap();
@@ -95,16 +630,339 @@ mod tests {
         assert_eq!(config, parsed);
     }
 
+    #[test]
+    fn test_serialization_is_deterministic() {
+        let macs = [
+            "CC:CC:CC:CC:CC:CC",
+            "AA:AA:AA:AA:AA:AA",
+            "BB:BB:BB:BB:BB:BB",
+        ];
+        let build = |order: &[&str]| {
+            let mut config = BlueVeinConfig::new();
+            for device in order {
+                for adapter in order {
+                    config.update_device(
+                        mac(adapter),
+                        BluetoothDevice::classic(mac(device), KEY.parse().unwrap()),
+                    );
+                }
+            }
+            config.to_json().unwrap()
+        };
+
+        let json = build(&macs);
+        assert_eq!(json, build(&[macs[2], macs[0], macs[1]]));
+        let first = json.find("AA:AA:AA:AA:AA:AA").unwrap();
+        assert!(first < json.find("BB:BB:BB:BB:BB:BB").unwrap());
+        assert!(first < json.find("CC:CC:CC:CC:CC:CC").unwrap());
+    }
+
+    #[test]
+    fn test_installation_stamp_resolution() {
+        let installation = |last_sync: u64, version: &str| Installation {
+            os: "linux".to_string(),
+            hostname: "arch".to_string(),
+            version: version.to_string(),
+            last_sync,
+            adapters: BTreeMap::new(),
+            extra: UnknownFields::default(),
+        };
+        let mut config = BlueVeinConfig::new();
+        config.record_installation("id".to_string(), installation(100, "0.1.0"));
+
+        config.record_installation("id".to_string(), installation(200, "0.1.0"));
+        assert_eq!(config.installations["id"].last_sync, 100);
+
+        config.record_installation("id".to_string(), installation(300, "0.2.0"));
+        assert_eq!(config.installations["id"].last_sync, 300);
+
+        config.record_installation(
+            "id".to_string(),
+            installation(300 + STAMP_RESOLUTION_SECS, "0.2.0"),
+        );
+        assert_eq!(
+            config.installations["id"].last_sync,
+            300 + STAMP_RESOLUTION_SECS
+        );
+    }
+
+    #[test]
+    fn test_rebase_onto_concurrent_write() {
+        let key = |k: &str| k.parse().unwrap();
+        let mut base = BlueVeinConfig::new();
+        for device in ["AA:AA:AA:AA:AA:AA", "BB:BB:BB:BB:BB:BB"] {
+            base.update_device(
+                mac(ADAPTER),
+                BluetoothDevice::classic(mac(device), key(KEY)),
+            );
+        }
+
+        // Another writer added a device and re-keyed one
+        let mut theirs = base.clone();
+        theirs.generation = 1;
+        theirs.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac("CC:CC:CC:CC:CC:CC"), key(KEY)),
+        );
+        theirs.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(
+                mac("AA:AA:AA:AA:AA:AA"),
+                key("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"),
+            ),
+        );
+
+        // This side, based on the stale read, forgot a device and added one
+        let mut ours = base.clone();
+        ours.forget_device(mac("BB:BB:BB:BB:BB:BB"), Some("ours"), 5);
+        ours.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac("DD:DD:DD:DD:DD:DD"), key(KEY)),
+        );
+
+        let merged = ours.rebase(&base, &theirs);
+        assert_eq!(merged.generation, 1);
+        let devices: Vec<String> = merged.adapters[&mac(ADAPTER)]
+            .devices
+            .keys()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            devices,
+            vec![
+                "AA:AA:AA:AA:AA:AA",
+                "CC:CC:CC:CC:CC:CC",
+                "DD:DD:DD:DD:DD:DD"
+            ]
+        );
+        assert_eq!(
+            merged.get_device(&mac(ADAPTER), &mac("AA:AA:AA:AA:AA:AA")),
+            theirs.get_device(&mac(ADAPTER), &mac("AA:AA:AA:AA:AA:AA"))
+        );
+        assert!(merged.tombstones.contains_key(&mac("BB:BB:BB:BB:BB:BB")));
+    }
+
+    #[test]
+    fn test_merge_by_stamps_without_base() {
+        let key = |k: &str| k.parse().unwrap();
+        let rekeyed = |device: &str| {
+            BluetoothDevice::classic(mac(device), key("FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"))
+        };
+        let mut stored = BlueVeinConfig::new();
+        for device in [
+            "AA:AA:AA:AA:AA:AA",
+            "BB:BB:BB:BB:BB:BB",
+            "CC:CC:CC:CC:CC:CC",
+        ] {
+            stored.update_device(
+                mac(ADAPTER),
+                BluetoothDevice::classic(mac(device), key(KEY)),
+            );
+        }
+        stored.mark_seen(&Snapshot::default(), 100);
+
+        // Another writer re-keyed AA later and forgot CC
+        let mut theirs = stored.clone();
+        theirs.generation = 4;
+        theirs.update_device(mac(ADAPTER), rekeyed("AA:AA:AA:AA:AA:AA"));
+        let adapter = theirs.adapters.get_mut(&mac(ADAPTER)).unwrap();
+        adapter
+            .devices_last_seen
+            .insert(mac("AA:AA:AA:AA:AA:AA"), 200);
+        theirs.forget_device(mac("CC:CC:CC:CC:CC:CC"), Some("theirs"), 300);
+
+        // This side re-keyed BB and newly paired DD
+        let mut ours = stored.clone();
+        ours.update_device(mac(ADAPTER), rekeyed("BB:BB:BB:BB:BB:BB"));
+        ours.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac("DD:DD:DD:DD:DD:DD"), key(KEY)),
+        );
+
+        let merged = ours.merge_by_stamps(&theirs);
+        assert_eq!(merged.generation, 4);
+        let device = |d: &str| merged.get_device(&mac(ADAPTER), &mac(d)).cloned();
+        // Devices both sides changed come from the newer stamp, ties go to this side
+        assert_eq!(
+            device("AA:AA:AA:AA:AA:AA"),
+            Some(rekeyed("AA:AA:AA:AA:AA:AA"))
+        );
+        assert_eq!(
+            device("BB:BB:BB:BB:BB:BB"),
+            Some(rekeyed("BB:BB:BB:BB:BB:BB"))
+        );
+        assert_eq!(device("CC:CC:CC:CC:CC:CC"), None);
+        assert!(device("DD:DD:DD:DD:DD:DD").is_some());
+        assert!(merged.tombstones.contains_key(&mac("CC:CC:CC:CC:CC:CC")));
+
+        // A device paired again here since the read clears the tombstone
+        let mut ours = stored.clone();
+        ours.adapters
+            .get_mut(&mac(ADAPTER))
+            .unwrap()
+            .devices_last_seen
+            .clear();
+        let merged = ours.merge_by_stamps(&theirs);
+        assert!(merged
+            .get_device(&mac(ADAPTER), &mac("CC:CC:CC:CC:CC:CC"))
+            .is_some());
+        assert!(merged.tombstones.is_empty());
+    }
+
     #[test]
     fn test_update_device() {
         let mut config = BlueVeinConfig::new();
-        let device =
-            BluetoothDevice::classic("AA:BB:CC:DD:EE:FF".to_string(), "KEY123".to_string());
-        config.update_device("00:11:22:33:44:55".to_string(), device);
-
-        let stored = config
-            .get_device("00:11:22:33:44:55", "AA:BB:CC:DD:EE:FF")
-            .unwrap();
-        assert_eq!(stored.classic.as_ref().unwrap().link_key, "KEY123");
+        let device = BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap());
+        config.update_device(mac(ADAPTER), device);
+
+        let stored = config.get_device(&mac(ADAPTER), &mac(DEVICE)).unwrap();
+        assert_eq!(
+            stored.classic.as_ref().unwrap().link_key.to_hex().as_str(),
+            KEY
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_addresses_and_keys() {
+        let bad_adapter = format!(
+            r#"{{"not-a-mac": {{"devices": {{"{d}": {{"mac_address": "{d}", "classic": {{"link_key": "{k}"}}}}}}}}}}"#,
+            d = DEVICE,
+            k = KEY
+        );
+        assert!(BlueVeinConfig::from_json(&bad_adapter).is_err());
+
+        let bad_key = format!(
+            r#"{{"{a}": {{"devices": {{"{d}": {{"mac_address": "{d}", "classic": {{"link_key": "KEY123"}}}}}}}}}}"#,
+            a = ADAPTER,
+            d = DEVICE
+        );
+        assert!(BlueVeinConfig::from_json(&bad_key).is_err());
+    }
+
+    #[test]
+    fn test_migrates_v1_layout() {
+        let v1 = format!(
+            r#"{{"{a}": {{"devices": {{"{d}": {{"mac_address": "{d}", "classic": {{"link_key": "{k}"}}}}}}}}}}"#,
+            a = ADAPTER,
+            d = DEVICE,
+            k = KEY
+        );
+        let config = BlueVeinConfig::from_json(&v1).unwrap();
+
+        assert_eq!(config.schema_version, SCHEMA_VERSION);
+        assert!(config.get_device(&mac(ADAPTER), &mac(DEVICE)).is_some());
+
+        let json: Value = serde_json::from_str(&config.to_json().unwrap()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert!(json["adapters"][ADAPTER]["devices"][DEVICE].is_object());
+    }
+
+    #[test]
+    fn test_preserves_unknown_fields() {
+        let stored = format!(
+            r#"{{"schema_version": 3, "min_writer_version": 2, "future_root": 1,
+                "adapters": {{"{a}": {{"future_adapter": true, "devices": {{"{d}": {{
+                    "mac_address": "{d}", "future_device": "x",
+                    "classic": {{"link_key": "{k}", "future_classic": [1, 2]}}}}}}}}}}}}"#,
+            a = ADAPTER,
+            d = DEVICE,
+            k = KEY
+        );
+        let mut config = BlueVeinConfig::from_json(&stored).unwrap();
+        assert!(config.check_writable().is_ok());
+
+        // Replace the device as a backend would, without the unknown fields
+        let mut updated = BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap());
+        updated.classic.as_mut().unwrap().pin_length = 4;
+        config.update_device(mac(ADAPTER), updated);
+
+        let json: Value = serde_json::from_str(&config.to_json().unwrap()).unwrap();
+        let adapter = &json["adapters"][ADAPTER];
+        let device = &adapter["devices"][DEVICE];
+        assert_eq!(json["future_root"], 1);
+        assert_eq!(adapter["future_adapter"], true);
+        assert_eq!(device["future_device"], "x");
+        assert_eq!(device["classic"]["future_classic"], json!([1, 2]));
+        assert_eq!(device["classic"]["pin_length"], 4);
+
+        // Unknown fields count for equality unless explicitly ignored
+        let stored = BlueVeinConfig::from_json(&stored).unwrap();
+        let plain = BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap());
+        let device = stored.get_device(&mac(ADAPTER), &mac(DEVICE)).unwrap();
+        assert_ne!(device, &plain);
+        assert!(device.eq_known_fields(&plain));
+
+        // A base differing only in unknown fields is not a local change
+        let mut theirs = stored.clone();
+        let mut repaired = plain.clone();
+        repaired.classic.as_mut().unwrap().pin_length = 6;
+        theirs.update_device(mac(ADAPTER), repaired);
+        let mut ours = stored.clone();
+        ours.clear_unknown_fields();
+        let merged = ours.rebase(&stored, &theirs);
+        assert_eq!(
+            merged.get_device(&mac(ADAPTER), &mac(DEVICE)),
+            theirs.get_device(&mac(ADAPTER), &mac(DEVICE))
+        );
+    }
+
+    #[test]
+    fn test_refuses_write_when_too_old() {
+        let stored = r#"{"schema_version": 7, "min_writer_version": 5, "adapters": {}}"#;
+        let config = BlueVeinConfig::from_json(stored).unwrap();
+        assert!(config.check_writable().is_err());
+
+        // Unparseable newer layouts say so instead of a bare parse error
+        let stored = r#"{"schema_version": 7, "min_writer_version": 7, "adapters": []}"#;
+        let err = BlueVeinConfig::from_json(stored).unwrap_err();
+        assert!(err.to_string().contains("schema version 7"));
+    }
+
+    #[test]
+    fn test_tombstone_lifecycle() {
+        let mut config = BlueVeinConfig::new();
+        config.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap()),
+        );
+        for id in ["linux", "windows"] {
+            config.record_installation(
+                id.to_string(),
+                serde_json::from_value(json!({
+                    "os": id, "hostname": "host", "version": "0.1.0", "last_sync": 0
+                }))
+                .unwrap(),
+            );
+        }
+
+        assert!(config.forget_device(mac(DEVICE), Some("linux"), 100));
+        assert!(config.get_device(&mac(ADAPTER), &mac(DEVICE)).is_none());
+        assert!(config.pending_tombstones(Some("linux")).is_empty());
+        assert_eq!(
+            config.pending_tombstones(Some("windows")),
+            vec![mac(DEVICE)]
+        );
+
+        // Survives a round trip and is kept until every installation acknowledged it
+        let mut config = BlueVeinConfig::from_json(&config.to_json().unwrap()).unwrap();
+        config.prune_tombstones();
+        assert!(config.tombstones.contains_key(&mac(DEVICE)));
+
+        config.acknowledge_tombstone(&mac(DEVICE), "windows");
+        config.prune_tombstones();
+        assert!(config.tombstones.is_empty());
+    }
+
+    #[test]
+    fn test_repairing_clears_tombstone() {
+        let mut config = BlueVeinConfig::new();
+        config.forget_device(mac(DEVICE), Some("linux"), 100);
+
+        config.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac(DEVICE), KEY.parse().unwrap()),
+        );
+        assert!(config.tombstones.is_empty());
+        assert!(config.get_device(&mac(ADAPTER), &mac(DEVICE)).is_some());
     }
 }
@@ -0,0 +1,538 @@
+//! Optional encryption of bluevein.json at rest
+//!
+//! When a key is configured, the serialized [`BlueVeinConfig`] is sealed in
+//! an XChaCha20-Poly1305 envelope. The envelope header (version, cipher, KDF
+//! and salt) is bound as associated data, so any modification of the file -
+//! header or ciphertext - is detected as tampering rather than a parse error.
+//!
+//! The encryption key comes from one of:
+//! - `BLUEVEIN_PASSPHRASE` (stretched with Argon2id)
+//! - `BLUEVEIN_KEY_FILE`, or the default key file if it exists (HKDF-SHA256)
+//!
+//! Every OS installation must be configured with the same passphrase or a
+//! copy of the same key file.
+//!
+//! [`BlueVeinConfig`]: crate::config::BlueVeinConfig
+
+use crate::efi::EfiError;
+use crate::log;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+/// Envelope format version
+const ENVELOPE_VERSION: u32 = 1;
+
+/// AEAD used for the payload
+const CIPHER_NAME: &str = "xchacha20poly1305";
+
+/// HKDF info string for key files
+const HKDF_INFO: &[u8] = b"bluevein config encryption v1";
+
+/// Minimum accepted key file size
+const MIN_KEY_FILE_LEN: usize = 16;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Argon2id parameters for new passphrase envelopes (OWASP recommendation)
+const ARGON2_M_COST: u32 = 19 * 1024;
+const ARGON2_T_COST: u32 = 2;
+const ARGON2_P_COST: u32 = 1;
+
+/// Upper bounds for Argon2id parameters read from an envelope header
+///
+/// The header comes from the ESP, which anyone able to tamper with the config
+/// can write, so unbounded costs would let them exhaust memory or CPU.
+const ARGON2_MAX_M_COST: u32 = 256 * 1024;
+const ARGON2_MAX_T_COST: u32 = 16;
+const ARGON2_MAX_P_COST: u32 = 8;
+
+/// Key file used when `BLUEVEIN_KEY_FILE` is not set
+#[cfg(target_os = "linux")]
+fn default_key_file() -> PathBuf {
+    PathBuf::from("/etc/bluevein/bluevein.key")
+}
+#[cfg(target_os = "windows")]
+fn default_key_file() -> PathBuf {
+    crate::paths::data_file("bluevein.key")
+}
+
+/// Where the encryption key comes from
+pub enum KeySource {
+    /// Passphrase, stretched with Argon2id
+    Passphrase(Zeroizing<String>),
+    /// Local secret file, expanded with HKDF-SHA256
+    KeyFile(PathBuf),
+}
+
+/// Key derivation recorded in the envelope header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "name", rename_all = "kebab-case")]
+enum Kdf {
+    Argon2id {
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    },
+    HkdfSha256,
+}
+
+/// Authenticated part of the envelope
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Header {
+    bluevein_envelope: u32,
+    cipher: String,
+    kdf: Kdf,
+    /// Hex-encoded KDF salt
+    salt: String,
+}
+
+/// On-disk envelope
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    #[serde(flatten)]
+    header: Header,
+    /// Hex-encoded nonce
+    nonce: String,
+    /// Hex-encoded ciphertext and authentication tag
+    ciphertext: String,
+}
+
+/// Only used to tell envelopes from plaintext configs
+#[derive(Deserialize)]
+struct EnvelopeProbe {
+    #[allow(dead_code)]
+    bluevein_envelope: u32,
+}
+
+/// Derived key, cached so the passphrase is not stretched on every access
+struct DerivedKey {
+    kdf: Kdf,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; 32]>,
+}
+
+/// Seals and opens the config envelope
+pub struct ConfigCipher {
+    source: KeySource,
+    allow_plaintext: bool,
+    cache: Mutex<Option<DerivedKey>>,
+}
+
+/// Check whether stored config bytes are an encrypted envelope
+pub fn is_envelope(data: &[u8]) -> bool {
+    serde_json::from_slice::<EnvelopeProbe>(data).is_ok()
+}
+
+impl ConfigCipher {
+    pub fn new(source: KeySource) -> Self {
+        Self {
+            source,
+            allow_plaintext: false,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Accept (and re-encrypt on the next write) an existing plaintext config
+    pub fn allow_plaintext(mut self, allow: bool) -> Self {
+        self.allow_plaintext = allow;
+        self
+    }
+
+    /// Whether a plaintext config may be read
+    pub fn plaintext_allowed(&self) -> bool {
+        self.allow_plaintext
+    }
+
+    /// Build a cipher from the environment, `None` if encryption is not configured
+    ///
+    /// * `BLUEVEIN_PASSPHRASE` - passphrase (takes precedence)
+    /// * `BLUEVEIN_KEY_FILE` - path to a secret file
+    /// * `BLUEVEIN_ALLOW_PLAINTEXT=1` - accept an unencrypted config once, to migrate it
+    pub fn from_env() -> Option<Self> {
+        let source = if let Some(passphrase) = env::var("BLUEVEIN_PASSPHRASE")
+            .ok()
+            .filter(|p| !p.is_empty())
+        {
+            KeySource::Passphrase(Zeroizing::new(passphrase))
+        } else if let Some(path) = env::var_os("BLUEVEIN_KEY_FILE").filter(|p| !p.is_empty()) {
+            KeySource::KeyFile(PathBuf::from(path))
+        } else if default_key_file().exists() {
+            KeySource::KeyFile(default_key_file())
+        } else {
+            return None;
+        };
+
+        let allow_plaintext = env::var("BLUEVEIN_ALLOW_PLAINTEXT")
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+
+        Some(Self::new(source).allow_plaintext(allow_plaintext))
+    }
+
+    /// Human-readable description of the key source (never the key itself)
+    pub fn describe(&self) -> String {
+        match &self.source {
+            KeySource::Passphrase(_) => "passphrase".to_string(),
+            KeySource::KeyFile(path) => format!("key file {}", path.display()),
+        }
+    }
+
+    /// KDF this cipher uses for new envelopes
+    fn kdf(&self) -> Kdf {
+        match self.source {
+            KeySource::Passphrase(_) => Kdf::Argon2id {
+                m_cost: ARGON2_M_COST,
+                t_cost: ARGON2_T_COST,
+                p_cost: ARGON2_P_COST,
+            },
+            KeySource::KeyFile(_) => Kdf::HkdfSha256,
+        }
+    }
+
+    /// Derive the envelope key for a KDF and salt
+    fn derive(&self, kdf: &Kdf, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, EfiError> {
+        let mut key = Zeroizing::new([0u8; 32]);
+
+        match (&self.source, kdf) {
+            (
+                KeySource::Passphrase(passphrase),
+                Kdf::Argon2id {
+                    m_cost,
+                    t_cost,
+                    p_cost,
+                },
+            ) => {
+                if *m_cost > ARGON2_MAX_M_COST
+                    || *t_cost > ARGON2_MAX_T_COST
+                    || *p_cost > ARGON2_MAX_P_COST
+                {
+                    return Err(EfiError::Tampered(format!(
+                        "Argon2 parameters m={} t={} p={} exceed the accepted limits",
+                        m_cost, t_cost, p_cost
+                    )));
+                }
+                let params = Params::new(*m_cost, *t_cost, *p_cost, Some(key.len()))
+                    .map_err(|e| EfiError::Tampered(format!("invalid Argon2 parameters: {}", e)))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+                    .map_err(|e| EfiError::EncryptionError(format!("Argon2 failed: {}", e)))?;
+            }
+            (KeySource::KeyFile(path), Kdf::HkdfSha256) => {
+                let secret = read_key_file(path)?;
+                Hkdf::<Sha256>::new(Some(salt), &secret)
+                    .expand(HKDF_INFO, key.as_mut())
+                    .map_err(|e| EfiError::EncryptionError(format!("HKDF failed: {}", e)))?;
+            }
+            (KeySource::Passphrase(_), Kdf::HkdfSha256) => {
+                return Err(EfiError::EncryptionError(
+                    "config was encrypted with a key file, but a passphrase is configured"
+                        .to_string(),
+                ));
+            }
+            (KeySource::KeyFile(_), Kdf::Argon2id { .. }) => {
+                return Err(EfiError::EncryptionError(
+                    "config was encrypted with a passphrase, but a key file is configured"
+                        .to_string(),
+                ));
+            }
+        }
+
+        Ok(key)
+    }
+
+    /// Return the key for `kdf`/`salt`, deriving it only if not cached
+    fn key_for(&self, kdf: &Kdf, salt: [u8; SALT_LEN]) -> Result<Zeroizing<[u8; 32]>, EfiError> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = cache.as_ref() {
+            if cached.kdf == *kdf && cached.salt == salt {
+                return Ok(cached.key.clone());
+            }
+        }
+
+        let key = self.derive(kdf, &salt)?;
+        *cache = Some(DerivedKey {
+            kdf: kdf.clone(),
+            salt,
+            key: key.clone(),
+        });
+        Ok(key)
+    }
+
+    /// Encrypt a serialized config into envelope JSON
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>, EfiError> {
+        let kdf = self.kdf();
+
+        // Reuse the cached salt (and key) when possible; nonces are always fresh
+        let cached_salt = self
+            .cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .filter(|cached| cached.kdf == kdf)
+            .map(|cached| cached.salt);
+        let salt = cached_salt.unwrap_or_else(|| {
+            let mut salt = [0u8; SALT_LEN];
+            OsRng.fill_bytes(&mut salt);
+            salt
+        });
+        let key = self.key_for(&kdf, salt)?;
+
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let header = Header {
+            bluevein_envelope: ENVELOPE_VERSION,
+            cipher: CIPHER_NAME.to_string(),
+            kdf,
+            salt: hex::encode(salt),
+        };
+        let aad = associated_data(&header)?;
+
+        let ciphertext = XChaCha20Poly1305::new(key.as_ref().into())
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| EfiError::EncryptionError("encryption failed".to_string()))?;
+
+        let envelope = Envelope {
+            header,
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+        };
+        serde_json::to_vec_pretty(&envelope)
+            .map_err(|e| EfiError::EncryptionError(format!("Failed to serialize envelope: {}", e)))
+    }
+
+    /// Verify and decrypt envelope JSON
+    ///
+    /// Any malformed or modified envelope is reported as `EfiError::Tampered`.
+    pub fn open(&self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        let envelope: Envelope = serde_json::from_slice(data)
+            .map_err(|e| EfiError::Tampered(format!("malformed envelope: {}", e)))?;
+        let header = &envelope.header;
+
+        if header.bluevein_envelope != ENVELOPE_VERSION {
+            return Err(EfiError::EncryptionError(format!(
+                "unsupported envelope version {} (this build supports {})",
+                header.bluevein_envelope, ENVELOPE_VERSION
+            )));
+        }
+        if header.cipher != CIPHER_NAME {
+            return Err(EfiError::Tampered(format!(
+                "unexpected cipher {:?}",
+                header.cipher
+            )));
+        }
+
+        let salt: [u8; SALT_LEN] = decode_fixed(&header.salt, "salt")?;
+        let nonce: [u8; NONCE_LEN] = decode_fixed(&envelope.nonce, "nonce")?;
+        let ciphertext = hex::decode(&envelope.ciphertext)
+            .map_err(|_| EfiError::Tampered("ciphertext is not valid hex".to_string()))?;
+
+        let key = self.key_for(&header.kdf, salt)?;
+        let aad = associated_data(header)?;
+
+        XChaCha20Poly1305::new(key.as_ref().into())
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| {
+                EfiError::Tampered(
+                    "authentication failed (wrong key, or the file was modified)".to_string(),
+                )
+            })
+    }
+}
+
+/// Serialize the header exactly as it is bound to the ciphertext
+fn associated_data(header: &Header) -> Result<Vec<u8>, EfiError> {
+    serde_json::to_vec(header)
+        .map_err(|e| EfiError::EncryptionError(format!("Failed to serialize header: {}", e)))
+}
+
+/// Decode a fixed-size hex field of the envelope
+fn decode_fixed<const N: usize>(hex_value: &str, field: &str) -> Result<[u8; N], EfiError> {
+    let mut bytes = [0u8; N];
+    hex::decode_to_slice(hex_value, &mut bytes)
+        .map_err(|_| EfiError::Tampered(format!("invalid {} in envelope", field)))?;
+    Ok(bytes)
+}
+
+/// Read the local secret file
+fn read_key_file(path: &Path) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    let secret = Zeroizing::new(fs::read(path).map_err(|e| {
+        EfiError::EncryptionError(format!("Failed to read key file {}: {}", path.display(), e))
+    })?);
+
+    if secret.len() < MIN_KEY_FILE_LEN {
+        return Err(EfiError::EncryptionError(format!(
+            "key file {} is too short (need at least {} bytes)",
+            path.display(),
+            MIN_KEY_FILE_LEN
+        )));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = fs::metadata(path) {
+            if metadata.permissions().mode() & 0o077 != 0 {
+                log!(
+                    "[BlueVein] Warning: key file {} is accessible by other users (chmod 600 it)",
+                    path.display()
+                );
+            }
+        }
+    }
+
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::{tempdir, TempDir};
+
+    const PLAINTEXT: &[u8] = br#"{"00:11:22:33:44:55":{"devices":{}}}"#;
+
+    /// Write a key file into the test's temporary directory
+    fn key_file_cipher(dir: &TempDir, name: &str, contents: &[u8]) -> ConfigCipher {
+        let path = dir.path().join(format!("{}.key", name));
+        fs::write(&path, contents).unwrap();
+        ConfigCipher::new(KeySource::KeyFile(path))
+    }
+
+    /// Mutate a JSON field of a sealed envelope
+    fn edit(sealed: &[u8], f: impl FnOnce(&mut serde_json::Value)) -> Vec<u8> {
+        let mut value: serde_json::Value = serde_json::from_slice(sealed).unwrap();
+        f(&mut value);
+        serde_json::to_vec(&value).unwrap()
+    }
+
+    #[test]
+    fn test_key_file_roundtrip() {
+        let dir = tempdir().unwrap();
+        let cipher = key_file_cipher(&dir, "roundtrip", &[7u8; 32]);
+        let sealed = cipher.seal(PLAINTEXT).unwrap();
+
+        assert!(is_envelope(&sealed));
+        assert!(!is_envelope(PLAINTEXT));
+        assert!(!String::from_utf8_lossy(&sealed).contains("00:11:22:33:44:55"));
+        assert_eq!(cipher.open(&sealed).unwrap().as_slice(), PLAINTEXT);
+
+        // A fresh cipher with the same secret (the other OS) can open it too
+        let other_os = key_file_cipher(&dir, "roundtrip-other", &[7u8; 32]);
+        assert_eq!(other_os.open(&sealed).unwrap().as_slice(), PLAINTEXT);
+    }
+
+    #[test]
+    fn test_passphrase_roundtrip() {
+        let cipher = ConfigCipher::new(KeySource::Passphrase(Zeroizing::new(
+            "correct horse battery staple".to_string(),
+        )));
+        let sealed = cipher.seal(PLAINTEXT).unwrap();
+        assert_eq!(cipher.open(&sealed).unwrap().as_slice(), PLAINTEXT);
+
+        let wrong = ConfigCipher::new(KeySource::Passphrase(Zeroizing::new("hunter2".to_string())));
+        assert!(matches!(wrong.open(&sealed), Err(EfiError::Tampered(_))));
+
+        // Costs from a modified header are bounded before anything is allocated
+        for (field, value) in [("m_cost", u32::MAX), ("t_cost", 1000), ("p_cost", 64)] {
+            let expensive = edit(&sealed, |v| v["kdf"][field] = value.into());
+            assert!(matches!(
+                cipher.open(&expensive),
+                Err(EfiError::Tampered(message)) if message.contains("exceed")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_wrong_key_is_rejected() {
+        let dir = tempdir().unwrap();
+        let sealed = key_file_cipher(&dir, "wrong-a", &[1u8; 32])
+            .seal(PLAINTEXT)
+            .unwrap();
+        let other = key_file_cipher(&dir, "wrong-b", &[2u8; 32]);
+        assert!(matches!(other.open(&sealed), Err(EfiError::Tampered(_))));
+    }
+
+    #[test]
+    fn test_modified_ciphertext_is_tampering() {
+        let dir = tempdir().unwrap();
+        let cipher = key_file_cipher(&dir, "ciphertext", &[3u8; 32]);
+        let sealed = cipher.seal(PLAINTEXT).unwrap();
+
+        let tampered = edit(&sealed, |v| {
+            let ct = v["ciphertext"].as_str().unwrap().to_string();
+            let flipped = if ct.starts_with('0') { "1" } else { "0" };
+            v["ciphertext"] = format!("{}{}", flipped, &ct[1..]).into();
+        });
+        assert!(matches!(cipher.open(&tampered), Err(EfiError::Tampered(_))));
+    }
+
+    #[test]
+    fn test_modified_header_is_tampering() {
+        let dir = tempdir().unwrap();
+        let cipher = key_file_cipher(&dir, "header", &[4u8; 32]);
+        let sealed = cipher.seal(PLAINTEXT).unwrap();
+
+        let new_salt = edit(&sealed, |v| v["salt"] = hex::encode([0u8; SALT_LEN]).into());
+        assert!(matches!(cipher.open(&new_salt), Err(EfiError::Tampered(_))));
+
+        let bad_nonce = edit(&sealed, |v| v["nonce"] = "00".into());
+        assert!(matches!(
+            cipher.open(&bad_nonce),
+            Err(EfiError::Tampered(_))
+        ));
+
+        let bad_cipher = edit(&sealed, |v| v["cipher"] = "none".into());
+        assert!(matches!(
+            cipher.open(&bad_cipher),
+            Err(EfiError::Tampered(_))
+        ));
+    }
+
+    #[test]
+    fn test_kdf_mismatch_is_reported() {
+        let dir = tempdir().unwrap();
+        let sealed = key_file_cipher(&dir, "kdf", &[5u8; 32])
+            .seal(PLAINTEXT)
+            .unwrap();
+        let passphrase =
+            ConfigCipher::new(KeySource::Passphrase(Zeroizing::new("secret".to_string())));
+        assert!(matches!(
+            passphrase.open(&sealed),
+            Err(EfiError::EncryptionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_short_key_file_is_rejected() {
+        let dir = tempdir().unwrap();
+        let cipher = key_file_cipher(&dir, "short", b"tiny");
+        assert!(matches!(
+            cipher.seal(PLAINTEXT),
+            Err(EfiError::EncryptionError(_))
+        ));
+    }
+}
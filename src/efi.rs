@@ -1,12 +1,19 @@
 use crate::config::BlueVeinConfig;
+use crate::crypto::{self, ConfigCipher};
+use crate::efivar::{self, EfiVariable};
+use crate::history::History;
+use crate::image;
 use crate::log;
+use crate::settings::StoreSettings;
 use fat32_raw::Fat32Volume;
+use sha2::{Digest, Sha256};
 use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
 
 #[derive(Debug)]
 pub enum EfiError {
@@ -14,6 +21,12 @@ pub enum EfiError {
     ReadError(String),
     WriteError(String),
     ParseError(String),
+    /// Encrypted config failed authentication or its envelope was modified
+    Tampered(String),
+    /// Encryption is misconfigured (missing key, unreadable key file, ...)
+    EncryptionError(String),
+    /// Config was written by a newer BlueVein that this build must not overwrite
+    IncompatibleSchema(String),
 }
 
 impl fmt::Display for EfiError {
@@ -23,55 +36,209 @@ impl fmt::Display for EfiError {
             EfiError::ReadError(msg) => write!(f, "Failed to read from EFI: {}", msg),
             EfiError::WriteError(msg) => write!(f, "Failed to write to EFI: {}", msg),
             EfiError::ParseError(msg) => write!(f, "Failed to parse config: {}", msg),
+            EfiError::Tampered(msg) => write!(f, "Config integrity check failed: {}", msg),
+            EfiError::EncryptionError(msg) => write!(f, "Config encryption error: {}", msg),
+            EfiError::IncompatibleSchema(msg) => write!(f, "Incompatible config schema: {}", msg),
         }
     }
 }
 
 impl Error for EfiError {}
 
-const CONFIG_FILENAME: &str = "bluevein.json";
+/// Location of the config before it could be configured, in the root of the partition
+const LEGACY_CONFIG_PATH: &str = "bluevein.json";
+const LEGACY_HISTORY_PATH: &str = "bluevein.history.json";
 
-// Common EFI mount points
-#[cfg(target_os = "linux")]
-#[allow(dead_code)]
-const EFI_MOUNT_POINTS: &[&str] = &["/boot/efi", "/efi", "/boot"];
+/// Where the config and its history are kept on the partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorePaths {
+    /// Config path relative to the partition root, `/`-separated
+    pub config: String,
+    /// History path, next to the config
+    pub history: String,
+}
+
+impl StorePaths {
+    /// Paths for a config at `path`, e.g. `/EFI/BlueVein/config.json`
+    pub fn new(path: &str) -> Result<Self, EfiError> {
+        let parts: Vec<&str> = path.split(['/', '\\']).filter(|p| !p.is_empty()).collect();
+        let invalid = |reason: &str| {
+            EfiError::ParseError(format!("invalid config path {:?}: {}", path, reason))
+        };
+        let Some(filename) = parts.last() else {
+            return Err(invalid("no file name"));
+        };
+        if parts.iter().any(|part| *part == "." || *part == "..") {
+            return Err(invalid("relative components are not allowed"));
+        }
+
+        let config = parts.join("/");
+        let stem = filename.strip_suffix(".json").unwrap_or(filename);
+        let history = match config.rsplit_once('/') {
+            Some((dir, _)) => format!("{}/{}.history.json", dir, stem),
+            None => format!("{}.history.json", stem),
+        };
+        Ok(Self { config, history })
+    }
+
+    /// Paths from `BLUEVEIN_CONFIG_PATH`, `default` if unset
+    ///
+    /// An invalid path is reported and the default is used.
+    pub fn from_env(default: Self) -> Self {
+        match env::var("BLUEVEIN_CONFIG_PATH") {
+            Ok(path) if !path.trim().is_empty() => Self::new(path.trim()).unwrap_or_else(|e| {
+                log!("[BlueVein] ✗ Ignoring BLUEVEIN_CONFIG_PATH: {}", e);
+                default
+            }),
+            _ => default,
+        }
+    }
+
+    fn is_legacy(&self) -> bool {
+        self.config == LEGACY_CONFIG_PATH
+    }
+}
+
+impl Default for StorePaths {
+    fn default() -> Self {
+        Self {
+            config: LEGACY_CONFIG_PATH.to_string(),
+            history: LEGACY_HISTORY_PATH.to_string(),
+        }
+    }
+}
+
+/// What to do before writing plaintext keys to a mounted ESP other users can read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExposurePolicy {
+    /// Write anyway and log a warning
+    Warn,
+    /// Refuse the write
+    Refuse,
+    /// Remove the permissions of others where the filesystem allows it,
+    /// refuse the write where it does not
+    #[default]
+    Restrict,
+}
 
-/// EFI context with device path
+impl std::str::FromStr for ExposurePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "warn" => Ok(Self::Warn),
+            "refuse" => Ok(Self::Refuse),
+            "restrict" => Ok(Self::Restrict),
+            other => Err(format!(
+                "unknown exposure policy {:?}, expected warn, refuse or restrict",
+                other
+            )),
+        }
+    }
+}
+
+impl ExposurePolicy {
+    /// Policy from `BLUEVEIN_EXPOSED_ESP` (`warn`, `refuse` or `restrict`), `default` if unset
+    ///
+    /// An invalid value is reported and the default is used.
+    pub fn from_env(default: Self) -> Self {
+        match env::var("BLUEVEIN_EXPOSED_ESP") {
+            Ok(policy) if !policy.trim().is_empty() => policy.parse().unwrap_or_else(|e| {
+                log!("[BlueVein] ✗ Ignoring BLUEVEIN_EXPOSED_ESP: {}", e);
+                default
+            }),
+            _ => default,
+        }
+    }
+}
+
+/// EFI context with the ESPs to use and optional config encryption
 pub struct EfiContext {
-    pub device: String,
+    /// Devices, mount points or `efivar` the config is mirrored to, empty to auto-detect one
+    pub targets: Vec<String>,
+    pub paths: StorePaths,
+    pub cipher: Option<ConfigCipher>,
+    /// Applied to plaintext writes, i.e. when `cipher` is None
+    pub exposure: ExposurePolicy,
 }
 
 impl EfiContext {
     pub fn new(device: impl Into<String>) -> Self {
+        let device = device.into();
         Self {
-            device: device.into(),
+            targets: device
+                .split(',')
+                .map(str::trim)
+                .filter(|target| !target.is_empty())
+                .map(str::to_string)
+                .collect(),
+            paths: StorePaths::default(),
+            cipher: None,
+            exposure: ExposurePolicy::default(),
         }
     }
 
-    pub fn from_env() -> Self {
-        env::var("BLUEVEIN_EFI_DEVICE")
+    /// Build the context from the store settings and the encryption variables
+    ///
+    /// `BLUEVEIN_EFI_DEVICE`, `BLUEVEIN_CONFIG_PATH` and `BLUEVEIN_EXPOSED_ESP`
+    /// override the settings. `BLUEVEIN_EFI_DEVICE` takes a comma-separated
+    /// list of devices, mount points or `efivar` for the UEFI variable. If no
+    /// target is configured and the system has several ESPs, all of them are used.
+    pub fn from_settings(store: &StoreSettings) -> Self {
+        let mut context = env::var("BLUEVEIN_EFI_DEVICE")
             .ok()
             .map(Self::new)
-            .unwrap_or_default()
+            .unwrap_or_else(|| Self::new(store.targets.join(",")));
+        if context.targets.is_empty() {
+            context.targets = discover_mirror_targets();
+        }
+        context.paths = StorePaths::from_env(store.paths.clone());
+        context.cipher = ConfigCipher::from_env();
+        context.exposure = ExposurePolicy::from_env(store.exposure);
+
+        if !context.paths.is_legacy() {
+            log!("[BlueVein] Config stored at /{}", context.paths.config);
+        }
+        if context.targets.len() > 1 {
+            log!(
+                "[BlueVein] Mirroring config across {} ESPs: {}",
+                context.targets.len(),
+                context.display_name()
+            );
+        }
+        match &context.cipher {
+            Some(cipher) => log!(
+                "[BlueVein] Config encryption enabled ({})",
+                cipher.describe()
+            ),
+            None => log!(
+                "[BlueVein] Config encryption disabled (bluevein.json is stored in plain text)"
+            ),
+        }
+
+        context
     }
 
-    pub fn display_name(&self) -> &str {
-        if self.device.is_empty() {
-            "auto-detected"
+    pub fn display_name(&self) -> String {
+        if self.targets.is_empty() {
+            "auto-detected".to_string()
         } else {
-            &self.device
+            self.targets.join(", ")
         }
     }
 
     pub fn validate(&self) -> Result<(), EfiError> {
-        if self.device.is_empty() {
-            return Ok(());
+        for target in &self.targets {
+            if let Some(variable) = efivar::parse_target(target) {
+                variable.check().map_err(EfiError::ReadError)?;
+                continue;
+            }
+            if Path::new(target).is_dir() {
+                continue;
+            }
+            open_volume(Some(target))?;
         }
 
-        Fat32Volume::open_esp(Some(&self.device))
-            .map_err(|e| EfiError::ReadError(format!("Failed to open ESP partition: {}", e)))?
-            .ok_or_else(|| EfiError::ReadError("ESP partition not found".to_string()))?;
-
         Ok(())
     }
 }
@@ -82,34 +249,92 @@ impl Default for EfiContext {
     }
 }
 
+/// Every ESP of a system with more than one, mounted ones by mount point
+fn discover_mirror_targets() -> Vec<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let esps: Vec<String> = crate::esp::discover()
+            .into_iter()
+            .filter(|partition| partition.kind == crate::esp::PartitionKind::Esp)
+            .map(|partition| {
+                partition
+                    .mount_point
+                    .unwrap_or(partition.device)
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        if esps.len() > 1 {
+            return esps;
+        }
+    }
+    Vec::new()
+}
+
 /// Find mounted EFI partition path
 fn find_mounted_efi() -> Option<String> {
     #[cfg(target_os = "linux")]
     {
-        for mount_point in EFI_MOUNT_POINTS {
-            let path = Path::new(mount_point);
-            if !(path.exists() && path.is_dir()) { continue; }
-
-            // findmnt for robustness, can check filesystem type
-            let check_mount = Command::new("findmnt")
-                .arg("-n")
-                .arg("-o")
-                .arg("FSTYPE")
-                .arg(mount_point)
-                .output();
-
-            if let Ok(output) = check_mount {
-                let fstype = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                let efi_dir = path.join("EFI");
-
-                // Check if it's actually mounted and looks like EFI
-                if fstype == "vfat" && efi_dir.exists() && efi_dir.is_dir() {
-                    return Some(mount_point.to_string());
-                }
-            }
-        }
+        crate::esp::find_mounted().map(|path| path.to_string_lossy().into_owned())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Open a device or FAT32 image with fat32-raw, or fat32-raw's detected ESP if None
+fn open_volume(device: Option<&str>) -> Result<Fat32Volume, EfiError> {
+    match device {
+        Some(path) if image::is_image(path) => image::open(Path::new(path))
+            .map_err(|e| EfiError::ReadError(format!("Failed to open image {}: {}", path, e))),
+        Some(path) => Fat32Volume::open_esp(Some(path))
+            .map_err(|e| {
+                EfiError::ReadError(format!("Failed to open ESP partition {}: {}", path, e))
+            })?
+            .ok_or_else(|| EfiError::ReadError(format!("ESP partition {} not found", path))),
+        None => Fat32Volume::open_esp(None::<&str>)
+            .map_err(|e| EfiError::ReadError(format!("Failed to open ESP partition: {}", e)))?
+            .ok_or_else(|| EfiError::ReadError("ESP partition not found".to_string())),
+    }
+}
+
+/// Why others could read the file at `path`, if they could
+fn exposure(path: &Path) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        crate::esp::exposure(path)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Take the permissions of others away from `path` and its directories below `root`
+fn restrict(path: &Path, root: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        crate::esp::restrict(path, root)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (path, root);
+        Ok(())
+    }
+}
+
+/// Block device of the ESP found by partition type, if any
+fn discover_esp_device() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        crate::esp::find_device().map(|path| path.to_string_lossy().into_owned())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
     }
-    None
 }
 
 fn find_json_end(data: &[u8]) -> usize {
@@ -142,172 +367,1343 @@ fn find_json_end(data: &[u8]) -> usize {
     data.len()
 }
 
-/// Read BlueVein configuration from EFI partition using default device
-#[allow(dead_code)]
-pub fn read_config() -> Result<BlueVeinConfig, EfiError> {
-    read_config_with_device(None)
+/// Decrypt and verify stored bytes if they are an envelope
+///
+/// Plaintext is refused when encryption is configured (unless explicitly
+/// allowed for migration), so an attacker cannot downgrade the store by
+/// replacing the envelope with a plaintext file.
+fn open_data(data: &[u8], cipher: Option<&ConfigCipher>) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    if crypto::is_envelope(data) {
+        let cipher = cipher.ok_or_else(|| {
+            EfiError::EncryptionError(
+                "config is encrypted but no key is configured (set BLUEVEIN_PASSPHRASE or BLUEVEIN_KEY_FILE)"
+                    .to_string(),
+            )
+        })?;
+        return cipher.open(data);
+    }
+
+    if let Some(cipher) = cipher {
+        if !cipher.plaintext_allowed() {
+            return Err(EfiError::EncryptionError(
+                "config is not encrypted but a key is configured; refusing plaintext (set BLUEVEIN_ALLOW_PLAINTEXT=1 once to migrate)"
+                    .to_string(),
+            ));
+        }
+        log!("[BlueVein] Reading plaintext config, it will be encrypted on the next write");
+    }
+    Ok(Zeroizing::new(data.to_vec()))
 }
 
-/// Read BlueVein configuration from EFI partition
+/// Seal serialized JSON in an envelope if encryption is configured
+fn seal_data(json: &[u8], cipher: Option<&ConfigCipher>) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    match cipher {
+        Some(cipher) => Ok(Zeroizing::new(cipher.seal(json)?)),
+        None => Ok(Zeroizing::new(json.to_vec())),
+    }
+}
+
+/// Decode stored config bytes, decrypting and verifying the envelope if there is one
 ///
-/// # Arguments
-/// * `device` - If Some, use direct disk access with specified device
-///              If None, try mounted EFI first, then fallback to default device
-pub fn read_config_with_device(device: Option<&str>) -> Result<BlueVeinConfig, EfiError> {
-    // If device is explicitly specified, skip mounted filesystem check
-    if device.is_none() {
-        // Try mounted filesystem first (faster and no cache issues)
-        if let Some(mount_point) = find_mounted_efi() {
-            let config_path = Path::new(&mount_point).join(CONFIG_FILENAME);
-
-            if config_path.exists() {
-                match fs::read_to_string(&config_path) {
-                    Ok(json_str) => {
-                        return BlueVeinConfig::from_json(&json_str)
-                            .map_err(|e| EfiError::ParseError(e.to_string()));
-                    }
+/// Both the file and the compact encoding of the EFI variable are accepted.
+fn decode_config(data: &[u8], cipher: Option<&ConfigCipher>) -> Result<BlueVeinConfig, EfiError> {
+    let plaintext = open_data(data, cipher)?;
+    let plaintext = efivar::unpack(&plaintext).map_err(EfiError::ParseError)?;
+    let json_str = std::str::from_utf8(&plaintext)
+        .map_err(|e| EfiError::ParseError(format!("Invalid UTF-8 in config file: {}", e)))?;
+    BlueVeinConfig::from_json(json_str).map_err(|e| EfiError::ParseError(e.to_string()))
+}
+
+/// Serialize a config, sealing it in an envelope if encryption is configured
+///
+/// Refuses configs that this build is too old to write without losing data.
+fn encode_config(
+    config: &BlueVeinConfig,
+    cipher: Option<&ConfigCipher>,
+) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    config
+        .check_writable()
+        .map_err(EfiError::IncompatibleSchema)?;
+
+    // Serialized config contains key material, wiped when dropped
+    let json = Zeroizing::new(
+        config
+            .to_json()
+            .map_err(|e| EfiError::WriteError(format!("Failed to serialize config: {}", e)))?,
+    );
+    seal_data(json.as_bytes(), cipher)
+}
+
+/// Serialize a config compactly for the EFI variable, compressed if that is smaller
+fn encode_variable(
+    config: &BlueVeinConfig,
+    cipher: Option<&ConfigCipher>,
+) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    config
+        .check_writable()
+        .map_err(EfiError::IncompatibleSchema)?;
+
+    let json = Zeroizing::new(
+        serde_json::to_vec(config)
+            .map_err(|e| EfiError::WriteError(format!("Failed to serialize config: {}", e)))?,
+    );
+    seal_data(&efivar::pack(&json), cipher)
+}
+
+/// Where a session found the store
+enum StoreLocation {
+    /// Mounted ESP, files are accessed through the filesystem
+    Mounted(PathBuf),
+    /// Raw volume opened through fat32-raw, kept open between operations
+    Raw(Box<Fat32Volume>),
+    /// Unmounted partition, mounted privately for each operation
+    #[cfg(target_os = "linux")]
+    Private(crate::esp::PrivateMount),
+}
+
+/// Last config read or written by a session
+struct CachedConfig {
+    /// SHA-256 of the stored bytes
+    digest: [u8; 32],
+    config: BlueVeinConfig,
+}
+
+/// Long-lived connection to the store on the EFI partition
+///
+/// The location is resolved once and a raw volume stays open, so an event
+/// does not re-run mount detection or re-open the partition. The last config
+/// read or written is cached and only decoded again when the stored bytes
+/// change. Writes flush just the written file rather than every filesystem.
+///
+/// A session on the `efivar` target keeps the config in a UEFI variable and
+/// uses the file on the auto-detected ESP for the history and for configs
+/// too large for the variable.
+pub struct StoreSession {
+    /// If Some, use direct disk access with this device (empty for the default one)
+    device: Option<String>,
+    variable: Option<EfiVariable>,
+    paths: StorePaths,
+    exposure: ExposurePolicy,
+    location: Option<StoreLocation>,
+    cached: Option<CachedConfig>,
+    /// Set once a private mount failed, e.g. without CAP_SYS_ADMIN
+    #[cfg(target_os = "linux")]
+    private_mount_failed: bool,
+}
+
+impl StoreSession {
+    /// Start a session; nothing is opened until the first operation
+    ///
+    /// # Arguments
+    /// * `device` - If Some, use direct disk access with specified device,
+    ///   or the filesystem if it is a mount point, or the EFI variable for `efivar`.
+    ///   If None, try mounted EFI first, then fallback to default device
+    pub fn new(device: Option<&str>) -> Self {
+        let variable = device.and_then(efivar::parse_target);
+        Self {
+            device: match variable {
+                Some(_) => Some(String::new()),
+                None => device.map(str::to_string),
+            },
+            variable,
+            paths: StorePaths::default(),
+            exposure: ExposurePolicy::default(),
+            location: None,
+            cached: None,
+            #[cfg(target_os = "linux")]
+            private_mount_failed: false,
+        }
+    }
+
+    /// Keep the config and history at `paths` instead of the partition root
+    pub fn with_paths(mut self, paths: StorePaths) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Resolve the store location, reusing the one found earlier
+    fn location(&mut self) -> Result<&mut StoreLocation, EfiError> {
+        // A mount point that went away is resolved again
+        if let Some(StoreLocation::Mounted(mount_point)) = &self.location {
+            if !mount_point.join("EFI").is_dir() {
+                self.location = None;
+            }
+        }
+
+        if self.location.is_none() {
+            let mounted = match self.device.as_deref() {
+                None => find_mounted_efi(),
+                Some(path) if !path.is_empty() && Path::new(path).is_dir() => {
+                    Some(path.to_string())
+                }
+                Some(_) => None,
+            };
+            self.location = Some(match mounted {
+                Some(mount_point) => StoreLocation::Mounted(PathBuf::from(mount_point)),
+                None => self.open_device()?,
+            });
+        }
+        Ok(self.location.as_mut().expect("location resolved above"))
+    }
+
+    /// The configured device, or the discovered ESP without one
+    fn device_path(&self) -> Option<String> {
+        match self.device.as_deref() {
+            Some(device) if !device.is_empty() => Some(device.to_string()),
+            _ => discover_esp_device(),
+        }
+    }
+
+    /// Locate a store on a device rather than a known mount point
+    ///
+    /// A partition the kernel has mounted anywhere is used through that
+    /// mount, since raw writes would race the kernel's FAT cache. An
+    /// unmounted one is mounted privately, and opened raw only if that fails.
+    fn open_device(&self) -> Result<StoreLocation, EfiError> {
+        let device_path = self.device_path();
+        #[cfg(target_os = "linux")]
+        if let Some(device) = device_path.as_deref().map(Path::new) {
+            let mounts = crate::esp::mounts_of(device);
+            if let Some(mount) = mounts
+                .iter()
+                .find(|mount| !mount.read_only)
+                .or(mounts.first())
+            {
+                log!(
+                    "[BlueVein] {} is mounted at {}, using the mounted filesystem",
+                    device.display(),
+                    mount.mount_point.display()
+                );
+                return Ok(StoreLocation::Mounted(mount.mount_point.clone()));
+            }
+            if crate::esp::is_block_device(device) && !self.private_mount_failed {
+                return Ok(StoreLocation::Private(crate::esp::PrivateMount::new(
+                    device,
+                )));
+            }
+        }
+        Ok(StoreLocation::Raw(Box::new(open_volume(
+            device_path.as_deref(),
+        )?)))
+    }
+
+    /// Use direct disk access from now on after a private mount failed
+    #[cfg(target_os = "linux")]
+    fn disable_private_mount(&mut self, error: &std::io::Error) {
+        log!(
+            "[BlueVein] Warning: Cannot mount the ESP privately ({}), using direct disk access",
+            error
+        );
+        self.private_mount_failed = true;
+        self.location = None;
+    }
+
+    /// Read through fat32-raw after the mounted filesystem failed
+    ///
+    /// The volume is only opened for this read and the session stays on the
+    /// mount; writing raw to a mounted partition would corrupt it.
+    fn read_raw_fallback(&self, filename: &str) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        let mut volume = open_volume(self.device_path().as_deref())?;
+        read_raw(&mut volume, filename)
+    }
+
+    /// Refuse raw access to a partition the kernel has mounted since the
+    /// session opened it
+    ///
+    /// The session resolves the location again on the next operation, which
+    /// then goes through the mount.
+    fn check_not_mounted(&mut self) -> Result<(), EfiError> {
+        #[cfg(target_os = "linux")]
+        if let Some(device) = self.device_path() {
+            if let Some(mount) = crate::esp::mounts_of(Path::new(&device)).first() {
+                self.location = None;
+                return Err(EfiError::WriteError(format!(
+                    "{} is mounted at {}; refusing to write to it directly",
+                    device,
+                    mount.mount_point.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a file at `filename`, relative to the root of the partition
+    pub fn read_file(&mut self, filename: &str) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        let result = match self.location()? {
+            StoreLocation::Mounted(mount_point) => {
+                let path = mount_point.join(filename);
+                if !path.exists() {
+                    return Err(EfiError::NotFound);
+                }
+                match fs::read(&path) {
+                    Ok(data) => return Ok(Zeroizing::new(data)),
                     Err(e) => {
                         log!("[BlueVein] Warning: Failed to read from mounted EFI ({}), trying direct access", e);
-                        // Fall through to fat32-raw
+                        self.read_raw_fallback(filename)
+                    }
+                }
+            }
+            StoreLocation::Raw(volume) => read_raw(volume, filename),
+            #[cfg(target_os = "linux")]
+            StoreLocation::Private(mount) => match mount.read(filename) {
+                Ok(Some(data)) => return Ok(Zeroizing::new(data)),
+                Ok(None) => return Err(EfiError::NotFound),
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    self.disable_private_mount(&e);
+                    return self.read_file(filename);
+                }
+                Err(e) => Err(EfiError::ReadError(format!(
+                    "Failed to read {} from the ESP: {}",
+                    filename, e
+                ))),
+            },
+        };
+
+        // A volume that failed is opened again on the next operation
+        if matches!(result, Err(EfiError::ReadError(_))) {
+            self.location = None;
+        }
+        result
+    }
+
+    /// Write a file at `filename`, relative to the root of the partition,
+    /// replacing its previous contents and creating missing directories
+    pub fn write_file(&mut self, filename: &str, data: &[u8]) -> Result<(), EfiError> {
+        if matches!(self.location()?, StoreLocation::Raw(_)) {
+            self.check_not_mounted()?;
+        }
+        let result = match self.location()? {
+            StoreLocation::Mounted(mount_point) => {
+                let path = mount_point.join(filename);
+                match write_and_flush(&path, data) {
+                    Ok(()) => {
+                        log!("[BlueVein] Wrote {} via mounted filesystem", path.display());
+                        return Ok(());
                     }
+                    Err(e) if e.kind() == std::io::ErrorKind::ReadOnlyFilesystem => {
+                        Err(EfiError::WriteError(format!(
+                            "{} is mounted read-only; remount it read-write to sync",
+                            mount_point.display()
+                        )))
+                    }
+                    Err(e) => Err(EfiError::WriteError(format!(
+                        "Failed to write {}: {}",
+                        path.display(),
+                        e
+                    ))),
                 }
-            } else {
-                // File doesn't exist
-                return Err(EfiError::NotFound);
             }
+            StoreLocation::Raw(volume) => write_raw(volume, filename, data),
+            #[cfg(target_os = "linux")]
+            StoreLocation::Private(mount) => match mount.write(filename, data) {
+                Ok(()) => {
+                    log!("[BlueVein] Wrote {} via private mount", filename);
+                    return Ok(());
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    self.disable_private_mount(&e);
+                    return self.write_file(filename, data);
+                }
+                Err(e) => Err(EfiError::WriteError(format!(
+                    "Failed to write {} to the ESP: {}",
+                    filename, e
+                ))),
+            },
+        };
+
+        if result.is_err() {
+            self.location = None;
         }
+        result
+    }
+
+    /// Delete a file if it exists
+    fn remove_file(&mut self, filename: &str) -> Result<(), EfiError> {
+        let result = match self.location()? {
+            StoreLocation::Mounted(mount_point) => {
+                match fs::remove_file(mount_point.join(filename)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+                    _ => Ok(()),
+                }
+            }
+            StoreLocation::Raw(volume) => volume
+                .delete_file_lfn(filename)
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            #[cfg(target_os = "linux")]
+            StoreLocation::Private(mount) => match mount.remove(filename) {
+                Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                    self.disable_private_mount(&e);
+                    return self.remove_file(filename);
+                }
+                result => result.map_err(|e| e.to_string()),
+            },
+        };
+        result.map_err(|e| EfiError::WriteError(format!("Failed to delete {}: {}", filename, e)))
     }
 
-    // Use specified device or empty (will fail if not mounted and no device specified)
-    let device_path = device.unwrap_or("");
+    /// Apply the exposure policy before key material is written to
+    /// `filename` in plain text
+    ///
+    /// Only a mounted ESP can be exposed; a private mount is only visible to
+    /// the helper process and raw access bypasses the permissions entirely.
+    fn check_exposure(&mut self, filename: &str) -> Result<(), EfiError> {
+        let policy = self.exposure;
+        let StoreLocation::Mounted(mount_point) = self.location()? else {
+            return Ok(());
+        };
+        let path = mount_point.join(filename);
+        let Some(reason) = exposure(&path) else {
+            return Ok(());
+        };
 
-    // Fallback to direct disk access via fat32-raw
-    let mut volume = Fat32Volume::open_esp(if device_path.is_empty() {
-        None
-    } else {
-        Some(device_path)
-    })
-    .map_err(|e| EfiError::ReadError(format!("Failed to open ESP partition: {}", e)))?
-    .ok_or_else(|| EfiError::ReadError("ESP partition not found".to_string()))?;
-
-    match volume.read_file(CONFIG_FILENAME) {
-        Ok(Some(mut data)) => {
-            let end = find_json_end(&data);
-            if end < data.len() {
-                log!("[BlueVein] Truncated {} trailing bytes from config", data.len() - end);
-                data.truncate(end);
+        let refusal = |reason: &str| {
+            EfiError::WriteError(format!(
+                "{}; refusing to store Bluetooth keys in plain text. Enable encryption (BLUEVEIN_PASSPHRASE or BLUEVEIN_KEY_FILE), remount the ESP with fmask=0077 or set BLUEVEIN_EXPOSED_ESP=warn",
+                reason
+            ))
+        };
+        match policy {
+            ExposurePolicy::Warn => {
+                log!(
+                    "[BlueVein] Warning: {}; storing Bluetooth keys in plain text anyway",
+                    reason
+                );
+                Ok(())
+            }
+            ExposurePolicy::Refuse => Err(refusal(&reason)),
+            ExposurePolicy::Restrict => {
+                if let Err(e) = restrict(&path, mount_point) {
+                    log!(
+                        "[BlueVein] Cannot restrict permissions of {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                match exposure(&path) {
+                    Some(reason) => Err(refusal(&reason)),
+                    None => {
+                        log!(
+                            "[BlueVein] ✓ Restricted permissions of {} to its owner",
+                            path.display()
+                        );
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply the exposure policy before key material is written to the EFI
+    /// variable in plain text
+    ///
+    /// Permissions of a variable cannot be restricted for good, so `restrict`
+    /// refuses the write like `refuse`.
+    fn check_variable_exposure(&self) -> Result<(), EfiError> {
+        let Some(reason) = efivar::exposure() else {
+            return Ok(());
+        };
+        match self.exposure {
+            ExposurePolicy::Warn => {
+                log!(
+                    "[BlueVein] Warning: {}; storing Bluetooth keys in plain text anyway",
+                    reason
+                );
+                Ok(())
+            }
+            ExposurePolicy::Refuse | ExposurePolicy::Restrict => Err(EfiError::WriteError(format!(
+                "{}; refusing to store Bluetooth keys in plain text. Enable encryption (BLUEVEIN_PASSPHRASE or BLUEVEIN_KEY_FILE) or set BLUEVEIN_EXPOSED_ESP=warn",
+                reason
+            ))),
+        }
+    }
+
+    /// Move the config and history from the root of the partition to the
+    /// configured paths, returning the config
+    ///
+    /// If they cannot be moved, e.g. because the ESP is read-only, the old
+    /// config is still returned; the next write goes to the new path.
+    fn migrate_legacy_files(&mut self) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        let data = self.read_file(LEGACY_CONFIG_PATH)?;
+        let paths = self.paths.clone();
+        log!(
+            "[BlueVein] Moving /{} to /{}",
+            LEGACY_CONFIG_PATH,
+            paths.config
+        );
+
+        let moved = self.write_file(&paths.config, &data).and_then(|()| {
+            match self.read_file(LEGACY_HISTORY_PATH) {
+                Ok(history) => {
+                    self.write_file(&paths.history, &history)?;
+                    self.remove_file(LEGACY_HISTORY_PATH)?;
+                }
+                Err(EfiError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+            self.remove_file(LEGACY_CONFIG_PATH)
+        });
+        if let Err(e) = moved {
+            log!("[BlueVein] Warning: Cannot move the config: {}", e);
+        }
+        Ok(data)
+    }
+
+    /// Read the config, decoding it again only if the stored bytes changed
+    ///
+    /// * `cipher` - If Some, the config must be a valid encrypted envelope
+    pub fn read_config(
+        &mut self,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<BlueVeinConfig, EfiError> {
+        let data = match self.read_config_data() {
+            Ok(data) => data,
+            Err(e) => {
+                self.cached = None;
+                return Err(e);
+            }
+        };
+        let digest: [u8; 32] = Sha256::digest(&data[..]).into();
+        if let Some(cached) = self.cached.as_ref().filter(|c| c.digest == digest) {
+            return Ok(cached.config.clone());
+        }
+
+        let config = decode_config(&data, cipher)?;
+        self.cached = Some(CachedConfig {
+            digest,
+            config: config.clone(),
+        });
+        Ok(config)
+    }
+
+    /// Stored config bytes, from the EFI variable if the session uses one
+    /// and it exists, from the file otherwise
+    fn read_config_data(&mut self) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        if let Some(variable) = &self.variable {
+            match variable.read() {
+                Ok(Some(data)) => return Ok(data),
+                Ok(None) => {}
+                Err(e) => {
+                    return Err(EfiError::ReadError(format!(
+                        "Failed to read EFI variable: {}",
+                        e
+                    )))
+                }
             }
-            let json_str = String::from_utf8(data).map_err(|e| {
-                EfiError::ParseError(format!("Invalid UTF-8 in config file: {}", e))
+        }
+        let path = self.paths.config.clone();
+        match self.read_file(&path) {
+            Err(EfiError::NotFound) if !self.paths.is_legacy() => self.migrate_legacy_files(),
+            result => result,
+        }
+    }
+
+    /// Store the encoded config, returning the bytes stored
+    ///
+    /// A config too large for the EFI variable is written to the file, then
+    /// the variable is deleted so readers find the file.
+    fn write_config_data(
+        &mut self,
+        config: &BlueVeinConfig,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+        if self.variable.is_some() && cipher.is_none() {
+            self.check_variable_exposure()?;
+        }
+        if let Some(variable) = &self.variable {
+            let data = encode_variable(config, cipher)?;
+            match variable.write(&data) {
+                Ok(()) => {
+                    log!(
+                        "[BlueVein] Wrote config to EFI variable ({} bytes)",
+                        data.len()
+                    );
+                    return Ok(data);
+                }
+                Err(e) if efivar::is_too_large(&e) => {
+                    log!(
+                        "[BlueVein] Config does not fit in the EFI variable ({}), writing {} instead",
+                        e,
+                        self.paths.config
+                    );
+                }
+                Err(e) => {
+                    return Err(EfiError::WriteError(format!(
+                        "Failed to write EFI variable: {}",
+                        e
+                    )))
+                }
+            }
+        }
+
+        let data = encode_config(config, cipher)?;
+        let path = self.paths.config.clone();
+        if cipher.is_none() {
+            self.check_exposure(&path)?;
+        }
+        self.write_file(&path, &data)?;
+        if let Some(variable) = &self.variable {
+            // A stale variable would hide the file just written
+            variable.remove().map_err(|e| {
+                EfiError::WriteError(format!("Failed to delete EFI variable: {}", e))
             })?;
+        }
+        Ok(data)
+    }
+
+    /// Write the config, returning the number of bytes written
+    ///
+    /// * `cipher` - If Some, the config is written as an encrypted envelope
+    pub fn write_config(
+        &mut self,
+        config: &BlueVeinConfig,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<usize, EfiError> {
+        // Whatever is stored now is unknown until the write succeeds
+        self.cached = None;
+        let data = self.write_config_data(config, cipher)?;
+        self.cached = Some(CachedConfig {
+            digest: Sha256::digest(&data[..]).into(),
+            config: config.clone(),
+        });
+        Ok(data.len())
+    }
+
+    /// Config last read or written in this session, if it is at `generation`
+    pub fn cached_config(&self, generation: u64) -> Option<BlueVeinConfig> {
+        self.cached
+            .as_ref()
+            .filter(|cached| cached.config.generation == generation)
+            .map(|cached| cached.config.clone())
+    }
+
+    /// Read the config history stored next to the config (empty if there is none)
+    ///
+    /// The history is encrypted with the same key as the config.
+    pub fn read_history(&mut self, cipher: Option<&ConfigCipher>) -> Result<History, EfiError> {
+        let path = self.paths.history.clone();
+        let data = match self.read_file(&path) {
+            Ok(data) => data,
+            Err(EfiError::NotFound) => return Ok(History::default()),
+            Err(e) => return Err(e),
+        };
+        let plaintext = open_data(&data, cipher)?;
+        serde_json::from_slice(&plaintext).map_err(|e| EfiError::ParseError(e.to_string()))
+    }
 
-            BlueVeinConfig::from_json(&json_str).map_err(|e| EfiError::ParseError(e.to_string()))
+    /// Write the config history next to the config, returning the number of bytes written
+    pub fn write_history(
+        &mut self,
+        history: &History,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<usize, EfiError> {
+        // Serialized history contains key material, wiped when dropped
+        let json =
+            Zeroizing::new(serde_json::to_vec(history).map_err(|e| {
+                EfiError::WriteError(format!("Failed to serialize history: {}", e))
+            })?);
+        let data = seal_data(&json, cipher)?;
+        let path = self.paths.history.clone();
+        if cipher.is_none() {
+            self.check_exposure(&path)?;
         }
-        Ok(None) => Err(EfiError::NotFound),
-        Err(e) => Err(EfiError::ReadError(format!(
-            "Failed to read {}: {}",
-            CONFIG_FILENAME, e
-        ))),
+        self.write_file(&path, &data)?;
+        Ok(data.len())
     }
 }
 
-/// Write BlueVein configuration to EFI partition using default device
-#[allow(dead_code)]
-pub fn write_config(config: &BlueVeinConfig) -> Result<(), EfiError> {
-    write_config_with_device(config, None)
+/// Health of one ESP as of the last operation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetHealth {
+    /// Not accessed yet
+    Unknown,
+    /// Holds the newest generation
+    Current(u64),
+    /// Holds an older generation, updated on the next write
+    Behind(u64),
+    /// Reachable but has no config yet
+    Missing,
+    /// Unreachable or holds an invalid config
+    Failed(String),
 }
 
-/// Write BlueVein configuration to EFI partition
+/// One ESP of a mirrored store
+#[derive(Debug, Clone)]
+pub struct TargetStatus {
+    pub name: String,
+    pub health: TargetHealth,
+}
+
+impl fmt::Display for TargetStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.health {
+            TargetHealth::Unknown => write!(f, "○ {}: not checked", self.name),
+            TargetHealth::Current(generation) => {
+                write!(f, "✓ {}: generation {}", self.name, generation)
+            }
+            TargetHealth::Behind(generation) => {
+                write!(f, "○ {}: generation {} (behind)", self.name, generation)
+            }
+            TargetHealth::Missing => write!(f, "○ {}: no config yet", self.name),
+            TargetHealth::Failed(error) => write!(f, "✗ {}: {}", self.name, error),
+        }
+    }
+}
+
+/// Config mirrored across one or more ESPs
 ///
-/// # Arguments
-/// * `device` - If Some, use direct disk access with specified device
-///              If None, try mounted filesystem first, then fallback to default device
-pub fn write_config_with_device(
-    config: &BlueVeinConfig,
-    device: Option<&str>,
-) -> Result<(), EfiError> {
-    // Serialize config to JSON
-    let json = config
-        .to_json()
-        .map_err(|e| EfiError::WriteError(format!("Failed to serialize config: {}", e)))?;
-
-    // If device is not explicitly specified, try mounted filesystem first
-    if device.is_none() {
-        if let Some(mount_point) = find_mounted_efi() {
-            let config_path = Path::new(&mount_point).join(CONFIG_FILENAME);
-
-            match fs::write(&config_path, &json) {
-                Ok(_) => {
-                    // Sync to ensure data is flushed to disk
-                    #[cfg(target_os = "linux")]
+/// Reads return the newest valid copy; writes go to every reachable ESP and
+/// succeed if at least one of them was written. Copies that are missing or
+/// behind are brought up to date by [`MirroredStore::sync_mirrors`].
+pub struct MirroredStore {
+    targets: Vec<(TargetStatus, StoreSession)>,
+    /// Index of the target the newest config was read from
+    primary: usize,
+}
+
+impl MirroredStore {
+    /// Open sessions for the given targets (one auto-detected ESP if empty)
+    pub fn new(targets: &[String], paths: &StorePaths) -> Self {
+        let targets = match targets {
+            [] => vec![(
+                TargetStatus {
+                    name: "auto-detected".to_string(),
+                    health: TargetHealth::Unknown,
+                },
+                StoreSession::new(Some("")).with_paths(paths.clone()),
+            )],
+            targets => targets
+                .iter()
+                .map(|target| {
+                    (
+                        TargetStatus {
+                            name: target.clone(),
+                            health: TargetHealth::Unknown,
+                        },
+                        StoreSession::new(Some(target)).with_paths(paths.clone()),
+                    )
+                })
+                .collect(),
+        };
+        Self {
+            targets,
+            primary: 0,
+        }
+    }
+
+    /// Apply `policy` to plaintext writes on every target
+    pub fn with_exposure_policy(mut self, policy: ExposurePolicy) -> Self {
+        for (_, session) in &mut self.targets {
+            session.exposure = policy;
+        }
+        self
+    }
+
+    /// Health of every target as of the last operation
+    pub fn status(&self) -> impl Iterator<Item = &TargetStatus> {
+        self.targets.iter().map(|(status, _)| status)
+    }
+
+    /// Read the newest valid config of all targets
+    pub fn read_config(
+        &mut self,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<BlueVeinConfig, EfiError> {
+        let mirrored = self.targets.len() > 1;
+        let mut newest: Option<(usize, BlueVeinConfig)> = None;
+        let mut first_error = None;
+
+        for (index, (status, session)) in self.targets.iter_mut().enumerate() {
+            match session.read_config(cipher) {
+                Ok(config) => {
+                    status.health = TargetHealth::Current(config.generation);
+                    if newest
+                        .as_ref()
+                        .is_none_or(|(_, best)| config.generation > best.generation)
                     {
-                        unsafe {
-                            libc::sync();
-                        }
+                        newest = Some((index, config));
+                    }
+                }
+                Err(EfiError::NotFound) => status.health = TargetHealth::Missing,
+                Err(e) => {
+                    if mirrored {
+                        log!(
+                            "[BlueVein] ✗ Cannot read config from {}: {}",
+                            status.name,
+                            e
+                        );
                     }
+                    status.health = TargetHealth::Failed(e.to_string());
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
 
+        let Some((primary, config)) = newest else {
+            return Err(first_error.unwrap_or(EfiError::NotFound));
+        };
+        self.primary = primary;
+        for (status, _) in &mut self.targets {
+            if let TargetHealth::Current(generation) = status.health {
+                if generation < config.generation {
                     log!(
-                        "[BlueVein] Wrote config via mounted filesystem: {}",
-                        config_path.display()
+                        "[BlueVein] ○ {} is behind (generation {} < {})",
+                        status.name,
+                        generation,
+                        config.generation
                     );
-                    return Ok(());
+                    status.health = TargetHealth::Behind(generation);
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Write the config to every target, returning the bytes written to one
+    pub fn write_config(
+        &mut self,
+        config: &BlueVeinConfig,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<usize, EfiError> {
+        let mut written = None;
+        let mut last_error = None;
+        for (index, (status, session)) in self.targets.iter_mut().enumerate() {
+            match session.write_config(config, cipher) {
+                Ok(bytes) => {
+                    status.health = TargetHealth::Current(config.generation);
+                    if written.is_none() {
+                        self.primary = index;
+                    }
+                    written.get_or_insert(bytes);
                 }
                 Err(e) => {
+                    log!("[BlueVein] ✗ Cannot write config to {}: {}", status.name, e);
+                    status.health = TargetHealth::Failed(e.to_string());
+                    last_error = Some(e);
+                }
+            }
+        }
+        match (written, last_error) {
+            (Some(bytes), _) => Ok(bytes),
+            (None, Some(e)) => Err(e),
+            (None, None) => Err(EfiError::WriteError("no ESP configured".to_string())),
+        }
+    }
+
+    /// Copy `config` to targets that are missing it or hold an older generation
+    pub fn sync_mirrors(&mut self, config: &BlueVeinConfig, cipher: Option<&ConfigCipher>) {
+        for (status, session) in &mut self.targets {
+            if !matches!(
+                status.health,
+                TargetHealth::Missing | TargetHealth::Behind(_)
+            ) {
+                continue;
+            }
+            match session.write_config(config, cipher) {
+                Ok(_) => {
                     log!(
-                        "[BlueVein] Warning: Failed to write to mounted EFI ({}), trying direct access",
-                        e
+                        "[BlueVein] ✓ Brought {} up to generation {}",
+                        status.name,
+                        config.generation
                     );
-                    // Fall through to fat32-raw
+                    status.health = TargetHealth::Current(config.generation);
+                }
+                Err(e) => {
+                    log!("[BlueVein] ✗ Cannot update {}: {}", status.name, e);
+                    status.health = TargetHealth::Failed(e.to_string());
                 }
             }
         }
     }
 
-    // Use specified device or empty (will fail if not mounted and no device specified)
-    let device_path = device.unwrap_or("");
+    /// Config last read or written on the primary target, if it is at `generation`
+    pub fn cached_config(&self, generation: u64) -> Option<BlueVeinConfig> {
+        self.targets[self.primary].1.cached_config(generation)
+    }
 
-    // Fallback to direct disk access via fat32-raw
-    log!("[BlueVein] Using direct disk access via fat32-raw");
+    /// Read the history stored with the newest config
+    pub fn read_history(&mut self, cipher: Option<&ConfigCipher>) -> Result<History, EfiError> {
+        self.targets[self.primary].1.read_history(cipher)
+    }
 
-    let mut volume = Fat32Volume::open_esp(if device_path.is_empty() {
-        None
-    } else {
-        Some(device_path)
-    })
-    .map_err(|e| EfiError::WriteError(format!("Failed to open ESP partition: {}", e)))?
-    .ok_or_else(|| EfiError::WriteError("ESP partition not found".to_string()))?;
+    /// Write the history to every target that holds the newest config
+    pub fn write_history(
+        &mut self,
+        history: &History,
+        cipher: Option<&ConfigCipher>,
+    ) -> Result<usize, EfiError> {
+        let mut written = None;
+        let mut last_error = None;
+        for (status, session) in &mut self.targets {
+            if !matches!(status.health, TargetHealth::Current(_)) {
+                continue;
+            }
+            match session.write_history(history, cipher) {
+                Ok(bytes) => {
+                    written.get_or_insert(bytes);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match (written, last_error) {
+            (Some(bytes), _) => Ok(bytes),
+            (None, Some(e)) => Err(e),
+            (None, None) => Ok(0),
+        }
+    }
+}
+
+/// Write a file through the mounted filesystem and flush only that file
+fn write_and_flush(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // New files are private where the filesystem has permissions
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// Read a file through fat32-raw
+fn read_raw(volume: &mut Fat32Volume, filename: &str) -> Result<Zeroizing<Vec<u8>>, EfiError> {
+    // The file may have been written since the FAT was cached
+    volume
+        .refresh_fat_cache()
+        .map_err(|e| EfiError::ReadError(format!("Failed to refresh FAT: {}", e)))?;
+
+    match volume.read_file(filename) {
+        Ok(Some(data)) => {
+            let mut data = Zeroizing::new(data);
+            let end = find_json_end(&data);
+            if end < data.len() {
+                log!(
+                    "[BlueVein] Truncated {} trailing bytes from {}",
+                    data.len() - end,
+                    filename
+                );
+                data.truncate(end);
+            }
+            Ok(data)
+        }
+        Ok(None) => Err(EfiError::NotFound),
+        Err(e) => Err(EfiError::ReadError(format!(
+            "Failed to read {}: {}",
+            filename, e
+        ))),
+    }
+}
+
+/// Write a file through fat32-raw, which syncs the volume itself and
+/// creates missing directories
+fn write_raw(volume: &mut Fat32Volume, filename: &str, data: &[u8]) -> Result<(), EfiError> {
+    volume
+        .refresh_fat_cache()
+        .map_err(|e| EfiError::WriteError(format!("Failed to refresh FAT: {}", e)))?;
 
     // Check if file exists
-    match volume.read_file(CONFIG_FILENAME) {
-        Ok(Some(_)) => {
-            // File exists, overwrite it
-            volume
-                .write_file(CONFIG_FILENAME, json.as_bytes())
-                .map_err(|e| {
-                    EfiError::WriteError(format!("Failed to write {}: {}", CONFIG_FILENAME, e))
-                })?;
+    match volume.read_file(filename) {
+        Ok(Some(existing)) => {
+            // Wipe the previous contents, then overwrite the file
+            drop(Zeroizing::new(existing));
+            volume.write_file(filename, data).map_err(|e| {
+                EfiError::WriteError(format!("Failed to write {}: {}", filename, e))
+            })?;
         }
         Ok(None) | Err(_) => {
             // File doesn't exist, create it
-            volume.create_file_lfn(CONFIG_FILENAME).map_err(|e| {
-                EfiError::WriteError(format!("Failed to create {}: {}", CONFIG_FILENAME, e))
+            volume.create_file_lfn(filename).map_err(|e| {
+                EfiError::WriteError(format!("Failed to create {}: {}", filename, e))
             })?;
 
-            volume
-                .write_file(CONFIG_FILENAME, json.as_bytes())
-                .map_err(|e| {
-                    EfiError::WriteError(format!("Failed to write {}: {}", CONFIG_FILENAME, e))
-                })?;
+            volume.write_file(filename, data).map_err(|e| {
+                EfiError::WriteError(format!("Failed to write {}: {}", filename, e))
+            })?;
         }
     }
+    Ok(())
+}
+
+/// Read BlueVein configuration from EFI partition using default device
+#[allow(dead_code)]
+pub fn read_config() -> Result<BlueVeinConfig, EfiError> {
+    read_config_with_device(None, None)
+}
+
+/// Read BlueVein configuration from EFI partition
+///
+/// # Arguments
+/// * `device` - If Some, use direct disk access with specified device
+///   If None, try mounted EFI first, then fallback to default device
+/// * `cipher` - If Some, the config must be a valid encrypted envelope
+pub fn read_config_with_device(
+    device: Option<&str>,
+    cipher: Option<&ConfigCipher>,
+) -> Result<BlueVeinConfig, EfiError> {
+    StoreSession::new(device).read_config(cipher)
+}
+
+/// Write BlueVein configuration to EFI partition using default device
+#[allow(dead_code)]
+pub fn write_config(config: &BlueVeinConfig) -> Result<usize, EfiError> {
+    write_config_with_device(config, None, None)
+}
+
+/// Write BlueVein configuration to EFI partition
+///
+/// Returns the number of bytes written.
+///
+/// # Arguments
+/// * `device` - If Some, use direct disk access with specified device
+///   If None, try mounted filesystem first, then fallback to default device
+/// * `cipher` - If Some, the config is written as an encrypted envelope
+pub fn write_config_with_device(
+    config: &BlueVeinConfig,
+    device: Option<&str>,
+    cipher: Option<&ConfigCipher>,
+) -> Result<usize, EfiError> {
+    StoreSession::new(device).write_config(config, cipher)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::BluetoothDevice;
+    use crate::crypto::KeySource;
+    use tempfile::tempdir;
+
+    fn sample_config() -> BlueVeinConfig {
+        let mut config = BlueVeinConfig::new();
+        config.update_device(
+            "00:11:22:33:44:55".parse().unwrap(),
+            BluetoothDevice::classic(
+                "AA:BB:CC:DD:EE:FF".parse().unwrap(),
+                "0123456789ABCDEF0123456789ABCDEF".parse().unwrap(),
+            ),
+        );
+        config
+    }
+
+    fn cipher(dir: &Path) -> ConfigCipher {
+        let path = dir.join("bluevein.key");
+        fs::write(&path, [9u8; 32]).unwrap();
+        ConfigCipher::new(KeySource::KeyFile(path))
+    }
+
+    /// Session on a temporary directory standing in for a mounted ESP
+    fn mounted_session(dir: &Path) -> StoreSession {
+        fs::create_dir_all(dir.join("EFI")).unwrap();
+        let mut session = StoreSession::new(None);
+        session.location = Some(StoreLocation::Mounted(dir.to_path_buf()));
+        session
+    }
+
+    #[test]
+    fn test_session_caches_and_notices_external_changes() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let mut session = mounted_session(dir);
+        assert!(matches!(session.read_config(None), Err(EfiError::NotFound)));
+
+        let mut config = sample_config();
+        session.write_config(&config, None).unwrap();
+        assert_eq!(session.read_config(None).unwrap(), config);
+
+        // Written behind the session's back, e.g. by another process
+        config.generation = 7;
+        fs::write(dir.join(LEGACY_CONFIG_PATH), config.to_json().unwrap()).unwrap();
+        assert_eq!(session.read_config(None).unwrap().generation, 7);
+
+        assert!(session.read_history(None).unwrap().entries.is_empty());
+    }
+
+    /// FAT32 image standing in for an ESP, as a partition or whole-disk image
+    fn image_target(dir: &Path, disk: bool) -> String {
+        let path = dir.join("esp.img");
+        let partition = image::format_fat32(4096);
+        if disk {
+            fs::write(&path, image::gpt_disk(&partition)).unwrap();
+        } else {
+            fs::write(&path, partition).unwrap();
+        }
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_mounted_read_failure_never_writes_raw() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().join("esp");
+        let mut session = mounted_session(&dir);
+        let image = image_target(tmp.path(), false);
+        session.device = Some(image.clone());
+        write_raw(
+            &mut image::open(Path::new(&image)).unwrap(),
+            LEGACY_CONFIG_PATH,
+            b"{}",
+        )
+        .unwrap();
+        let raw = fs::read(&image).unwrap();
+
+        // A directory cannot be read as a file, so the read falls back to the raw volume
+        fs::create_dir(dir.join(LEGACY_CONFIG_PATH)).unwrap();
+        assert_eq!(&session.read_file(LEGACY_CONFIG_PATH).unwrap()[..], b"{}");
+        assert!(matches!(session.location, Some(StoreLocation::Mounted(_))));
+
+        session.write_file("EFI/written.json", b"{}").unwrap();
+        assert_eq!(fs::read(dir.join("EFI/written.json")).unwrap(), b"{}");
+        assert_eq!(fs::read(&image).unwrap(), raw);
+    }
+
+    #[test]
+    fn test_image_target_end_to_end() {
+        for disk in [false, true] {
+            let tmp = tempdir().unwrap();
+            let target = image_target(tmp.path(), disk);
+            let context = EfiContext::new(target.clone());
+            context.validate().unwrap();
+
+            let paths = StorePaths::new("/EFI/BlueVein/config.json").unwrap();
+            let mut store = MirroredStore::new(&context.targets, &paths);
+            assert!(matches!(store.read_config(None), Err(EfiError::NotFound)));
+
+            let mut config = sample_config();
+            for i in 0..20u32 {
+                config.update_device(
+                    "00:11:22:33:44:55".parse().unwrap(),
+                    BluetoothDevice::classic(
+                        format!("AA:BB:CC:DD:EE:{:02X}", i).parse().unwrap(),
+                        "0123456789ABCDEF0123456789ABCDEF".parse().unwrap(),
+                    ),
+                );
+            }
+            store.write_config(&config, None).unwrap();
+            store.write_history(&History::default(), None).unwrap();
+
+            // A shorter config replaces the longer one; fresh sessions read the image itself
+            let fresh = || StoreSession::new(Some(&target)).with_paths(paths.clone());
+            assert_eq!(fresh().read_config(None).unwrap(), config);
+            store.write_config(&sample_config(), None).unwrap();
+            assert_eq!(fresh().read_config(None).unwrap(), sample_config());
+            assert!(fresh().read_history(None).unwrap().entries.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_image_trailing_data_is_ignored() {
+        let tmp = tempdir().unwrap();
+        let target = image_target(tmp.path(), false);
+        let mut volume = image::open(Path::new(&target)).unwrap();
+        let mut data = sample_config().to_json().unwrap().into_bytes();
+        data.extend_from_slice(b"\0\0\0stale tail of a longer config\"}]}");
+        write_raw(&mut volume, LEGACY_CONFIG_PATH, &data).unwrap();
+
+        assert_eq!(
+            StoreSession::new(Some(&target)).read_config(None).unwrap(),
+            sample_config()
+        );
+    }
 
-    // Call sync to flush buffers
     #[cfg(target_os = "linux")]
-    {
-        unsafe {
-            libc::sync();
+    #[test]
+    fn test_exposure_policy() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let mut session = mounted_session(dir);
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = dir.join(LEGACY_CONFIG_PATH);
+        let expose = || fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let mode = || fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        let config = sample_config();
+
+        // New files are created private
+        session.write_config(&config, None).unwrap();
+        assert_eq!(mode(), 0o600);
+
+        expose();
+        session.exposure = ExposurePolicy::Refuse;
+        assert!(matches!(
+            session.write_config(&config, None),
+            Err(EfiError::WriteError(e)) if e.contains("refusing")
+        ));
+        // Encrypted configs are safe to store anywhere
+        session.write_config(&config, Some(&cipher(dir))).unwrap();
+
+        session.exposure = ExposurePolicy::Warn;
+        session.write_config(&config, None).unwrap();
+        assert_eq!(mode(), 0o644);
+
+        session.exposure = ExposurePolicy::Restrict;
+        session.write_config(&config, None).unwrap();
+        assert_eq!(mode(), 0o600);
+    }
+
+    #[test]
+    fn test_config_path_and_legacy_migration() {
+        let paths = StorePaths::new("\\EFI\\BlueVein\\config.json").unwrap();
+        assert_eq!(paths.config, "EFI/BlueVein/config.json");
+        assert_eq!(paths.history, "EFI/BlueVein/config.history.json");
+        assert_eq!(
+            StorePaths::new("/bluevein.json").unwrap(),
+            StorePaths::default()
+        );
+        assert!(StorePaths::new("/").is_err());
+        assert!(StorePaths::new("/EFI/../bluevein.json").is_err());
+
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let mut legacy = mounted_session(dir);
+        let config = sample_config();
+        legacy.write_config(&config, None).unwrap();
+        legacy.write_history(&History::default(), None).unwrap();
+
+        let mut session = StoreSession {
+            cached: None,
+            ..legacy
         }
+        .with_paths(paths);
+        assert_eq!(session.read_config(None).unwrap(), config);
+        assert!(dir.join("EFI/BlueVein/config.json").exists());
+        assert!(dir.join("EFI/BlueVein/config.history.json").exists());
+        assert!(!dir.join(LEGACY_CONFIG_PATH).exists());
+        assert!(!dir.join(LEGACY_HISTORY_PATH).exists());
+        assert_eq!(session.read_config(None).unwrap(), config);
     }
 
-    Ok(())
+    #[test]
+    fn test_mirrored_store_reads_newest_and_repairs() {
+        let dirs = [tempdir().unwrap(), tempdir().unwrap()];
+        let targets = dirs.each_ref().map(|dir| {
+            mounted_session(dir.path());
+            dir.path().to_string_lossy().into_owned()
+        });
+        let mut store = MirroredStore::new(&targets, &StorePaths::default());
+
+        let mut config = sample_config();
+        config.generation = 3;
+        StoreSession::new(Some(&targets[0]))
+            .write_config(&config, None)
+            .unwrap();
+
+        let read = store.read_config(None).unwrap();
+        assert_eq!(read.generation, 3);
+        let health: Vec<TargetHealth> = store.status().map(|t| t.health.clone()).collect();
+        assert_eq!(
+            health,
+            vec![TargetHealth::Current(3), TargetHealth::Missing]
+        );
+
+        store.sync_mirrors(&read, None);
+        config.generation = 5;
+        StoreSession::new(Some(&targets[1]))
+            .write_config(&config, None)
+            .unwrap();
+
+        assert_eq!(store.read_config(None).unwrap().generation, 5);
+        let health: Vec<TargetHealth> = store.status().map(|t| t.health.clone()).collect();
+        assert_eq!(
+            health,
+            vec![TargetHealth::Behind(3), TargetHealth::Current(5)]
+        );
+    }
+
+    #[test]
+    fn test_variable_store_falls_back_to_file() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path();
+        let mut session = mounted_session(dir);
+        fs::create_dir_all(dir.join("efivars")).unwrap();
+        session.variable =
+            efivar::parse_target(&format!("efivar:{}", dir.join("efivars").display()));
+        let cipher = cipher(dir);
+        let config = sample_config();
+
+        // Variables are readable by every user on Linux, so plain text needs `warn`
+        #[cfg(target_os = "linux")]
+        {
+            assert!(matches!(
+                session.write_config(&config, None),
+                Err(EfiError::WriteError(e)) if e.contains("refusing")
+            ));
+            session.exposure = ExposurePolicy::Warn;
+            session.write_config(&config, None).unwrap();
+            session.exposure = ExposurePolicy::default();
+        }
+
+        session.write_config(&config, Some(&cipher)).unwrap();
+        assert!(!dir.join(LEGACY_CONFIG_PATH).exists());
+        assert_eq!(session.read_config(Some(&cipher)).unwrap(), config);
+
+        // Random keys do not compress below the variable limit
+        let mut large = sample_config();
+        for i in 0..200u32 {
+            large.update_device(
+                "00:11:22:33:44:55".parse().unwrap(),
+                BluetoothDevice::classic(
+                    format!("AA:BB:CC:DD:{:02X}:{:02X}", i / 256, i % 256)
+                        .parse()
+                        .unwrap(),
+                    hex::encode_upper(&Sha256::digest(i.to_le_bytes())[..16])
+                        .parse()
+                        .unwrap(),
+                ),
+            );
+        }
+        session.write_config(&large, Some(&cipher)).unwrap();
+        assert!(dir.join(LEGACY_CONFIG_PATH).exists());
+        assert!(fs::read_dir(dir.join("efivars")).unwrap().next().is_none());
+        assert_eq!(
+            StoreSession {
+                cached: None,
+                ..session
+            }
+            .read_config(Some(&cipher))
+            .unwrap(),
+            large
+        );
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let tmp = tempdir().unwrap();
+        let cipher = cipher(tmp.path());
+        let data = encode_config(&sample_config(), Some(&cipher)).unwrap();
+
+        assert!(crypto::is_envelope(&data));
+        assert!(!String::from_utf8_lossy(&data).contains("0123456789ABCDEF"));
+        assert_eq!(
+            decode_config(&data, Some(&cipher)).unwrap(),
+            sample_config()
+        );
+    }
+
+    #[test]
+    fn test_encrypted_config_requires_key() {
+        let tmp = tempdir().unwrap();
+        let data = encode_config(&sample_config(), Some(&cipher(tmp.path()))).unwrap();
+        assert!(matches!(
+            decode_config(&data, None),
+            Err(EfiError::EncryptionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_refused_when_key_configured() {
+        let tmp = tempdir().unwrap();
+        let data = encode_config(&sample_config(), None).unwrap();
+
+        assert!(matches!(
+            decode_config(&data, Some(&cipher(tmp.path()))),
+            Err(EfiError::EncryptionError(_))
+        ));
+
+        let migrating = cipher(tmp.path()).allow_plaintext(true);
+        assert_eq!(
+            decode_config(&data, Some(&migrating)).unwrap(),
+            sample_config()
+        );
+    }
 }
@@ -0,0 +1,476 @@
+//! Shared config stored in a UEFI variable
+//!
+//! NVRAM is shared by every OS on the machine and survives ESP rebuilds, so
+//! it can hold the config where the ESP is read-only or managed by tooling
+//! that removes unknown files. The config is stored as compact JSON, deflated
+//! when that is smaller, in a non-volatile variable under
+//! BlueVein's vendor GUID. Firmware limits the size of a variable; a config
+//! that does not fit is kept in the file on the ESP instead.
+//!
+//! On Linux the variable is accessed through efivarfs, on Windows through
+//! the firmware environment API.
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use zeroize::Zeroizing;
+
+/// Vendor GUID of BlueVein's variables
+///
+/// Randomly generated for BlueVein, not assigned by anyone else, so its
+/// variables cannot collide with firmware or other vendors' ones. Changing it
+/// orphans the variables already written; the test below pins it.
+pub const VENDOR_GUID: &str = "e7b1a5c2-3f4d-4b6e-9a8c-b1e5ee1a5e01";
+const VARIABLE_NAME: &str = "BlueVeinConfig";
+
+/// Target name selecting the variable in `BLUEVEIN_EFI_DEVICE`
+pub const TARGET_NAME: &str = "efivar";
+
+/// Largest value written to the variable
+///
+/// The UEFI spec leaves the limit to the firmware; many refuse variables
+/// larger than 8 KiB.
+pub const MAX_VARIABLE_SIZE: usize = 8 * 1024;
+
+/// NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS
+const ATTRIBUTES: u32 = 0x7;
+
+/// Prefix of a deflate-compressed value, followed by the uncompressed length (u32 LE)
+const COMPRESSED_MAGIC: &[u8] = b"BVD1";
+
+/// Largest expansion deflate achieves, bounding the length a header may claim
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// Encode serialized JSON for the variable, deflated if that is smaller
+pub fn pack(json: &[u8]) -> Zeroizing<Vec<u8>> {
+    let header = COMPRESSED_MAGIC.len() + 4;
+    // Sized for the worst case, 9 bits per byte, so key material is never reallocated
+    let mut packed = Zeroizing::new(Vec::with_capacity(
+        header + json.len() + json.len() / 8 + 64,
+    ));
+    packed.extend_from_slice(COMPRESSED_MAGIC);
+    packed.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    let mut encoder = DeflateEncoder::new(&mut *packed, Compression::best());
+    let deflated = encoder.write_all(json).and_then(|()| encoder.try_finish());
+    drop(encoder);
+
+    if deflated.is_err() || packed.len() >= json.len() {
+        return Zeroizing::new(json.to_vec());
+    }
+    packed
+}
+
+/// Decode a value written by [`pack`]; anything else is returned as is
+pub fn unpack(data: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let Some(rest) = data.strip_prefix(COMPRESSED_MAGIC) else {
+        return Ok(Zeroizing::new(data.to_vec()));
+    };
+    let (size, compressed) = rest
+        .split_first_chunk::<4>()
+        .ok_or("truncated compressed config")?;
+    let size = u32::from_le_bytes(*size) as usize;
+    if size > compressed.len().saturating_mul(MAX_DEFLATE_RATIO) {
+        return Err("corrupt compressed config".to_string());
+    }
+
+    let mut json = Zeroizing::new(Vec::with_capacity(size + 1));
+    DeflateDecoder::new(compressed)
+        .take(size as u64 + 1)
+        .read_to_end(&mut json)
+        .map_err(|e| format!("corrupt compressed config: {}", e))?;
+    if json.len() != size {
+        return Err("corrupt compressed config".to_string());
+    }
+    Ok(json)
+}
+
+/// Whether a write failed because the value does not fit in NVRAM
+pub fn is_too_large(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::FileTooLarge | io::ErrorKind::StorageFull
+    )
+}
+
+/// Why plaintext keys in the variable would be readable by other users, if they would be
+///
+/// efivarfs presents every variable as mode 0644 after a reboot, whatever
+/// mode it was created with. On Windows only privileged processes can read
+/// firmware variables.
+pub fn exposure() -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        Some("EFI variables are readable by every user through efivarfs".to_string())
+    }
+    #[cfg(target_os = "windows")]
+    {
+        None
+    }
+}
+
+fn too_large(len: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        format!(
+            "{} bytes exceed the {} byte variable limit",
+            len, MAX_VARIABLE_SIZE
+        ),
+    )
+}
+
+/// BlueVein's config variable
+pub struct EfiVariable {
+    /// Where efivarfs is mounted
+    #[cfg(target_os = "linux")]
+    dir: std::path::PathBuf,
+}
+
+/// The variable selected by a store target: `efivar`, or on Linux
+/// `efivar:<dir>` for efivarfs mounted elsewhere
+pub fn parse_target(target: &str) -> Option<EfiVariable> {
+    #[cfg(target_os = "linux")]
+    {
+        if target == TARGET_NAME {
+            return Some(EfiVariable::new(linux::EFIVARFS_DIR));
+        }
+        target
+            .strip_prefix(TARGET_NAME)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .map(EfiVariable::new)
+    }
+    #[cfg(target_os = "windows")]
+    {
+        (target == TARGET_NAME).then_some(EfiVariable {})
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::{Path, PathBuf};
+
+    pub const EFIVARFS_DIR: &str = "/sys/firmware/efi/efivars";
+
+    /// FS_IMMUTABLE_FL from linux/fs.h
+    const IMMUTABLE_FLAG: libc::c_int = 0x10;
+
+    /// Set or clear the immutable attribute, returning whether it was set
+    ///
+    /// efivarfs marks variables immutable so they are not deleted by
+    /// accident; filesystems without attributes are treated as mutable.
+    fn set_immutable(path: &Path, immutable: bool) -> io::Result<bool> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut flags: libc::c_int = 0;
+        // SAFETY: FS_IOC_GETFLAGS writes an int to `flags`
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                Some(libc::ENOTTY | libc::EOPNOTSUPP | libc::EINVAL) => Ok(false),
+                _ => Err(e),
+            };
+        }
+
+        let was_immutable = flags & IMMUTABLE_FLAG != 0;
+        if was_immutable != immutable {
+            flags ^= IMMUTABLE_FLAG;
+            // SAFETY: FS_IOC_SETFLAGS reads an int from `flags`
+            if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(was_immutable)
+    }
+
+    impl EfiVariable {
+        /// The variable in efivarfs mounted at `dir`
+        pub fn new(dir: impl Into<PathBuf>) -> Self {
+            Self { dir: dir.into() }
+        }
+
+        fn path(&self) -> PathBuf {
+            self.dir.join(format!("{}-{}", VARIABLE_NAME, VENDOR_GUID))
+        }
+
+        /// Check that EFI variables can be accessed
+        pub fn check(&self) -> Result<(), String> {
+            if self.dir.is_dir() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "EFI variables are not available ({} does not exist)",
+                    self.dir.display()
+                ))
+            }
+        }
+
+        /// Read the value, `None` if the variable does not exist
+        pub fn read(&self) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+            let mut data = match fs::read(self.path()) {
+                Ok(data) => Zeroizing::new(data),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if data.len() < 4 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "variable is missing its attributes",
+                ));
+            }
+            // efivarfs prefixes the value with its attributes
+            data.drain(..4);
+            Ok(Some(data))
+        }
+
+        /// Create or replace the variable
+        pub fn write(&self, value: &[u8]) -> io::Result<()> {
+            if value.len() > MAX_VARIABLE_SIZE {
+                return Err(too_large(value.len()));
+            }
+            let path = self.path();
+            let was_immutable = set_immutable(&path, false)?;
+
+            // efivarfs needs attributes and value in a single write
+            let mut data = Zeroizing::new(Vec::with_capacity(4 + value.len()));
+            data.extend_from_slice(&ATTRIBUTES.to_le_bytes());
+            data.extend_from_slice(value);
+            let result = File::options()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o600)
+                .open(&path)
+                .and_then(|mut file| {
+                    if file.write(&data)? != data.len() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::WriteZero,
+                            "short write to efivarfs",
+                        ));
+                    }
+                    // efivarfs replaces the value; a plain directory keeps a longer old one
+                    if file.metadata()?.len() > data.len() as u64 {
+                        file.set_len(data.len() as u64)?;
+                    }
+                    Ok(())
+                });
+
+            if was_immutable {
+                let _ = set_immutable(&path, true);
+            }
+            result
+        }
+
+        /// Delete the variable if it exists
+        pub fn remove(&self) -> io::Result<()> {
+            let path = self.path();
+            set_immutable(&path, false)?;
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod windows {
+    use super::*;
+    use ::windows::core::{HSTRING, PCWSTR};
+    use ::windows::Win32::Foundation::{
+        CloseHandle, ERROR_ENVVAR_NOT_FOUND, HANDLE, LUID, WIN32_ERROR,
+    };
+    use ::windows::Win32::Security::{
+        AdjustTokenPrivileges, LookupPrivilegeValueW, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED,
+        SE_SYSTEM_ENVIRONMENT_NAME, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+    };
+    use ::windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+    use ::windows::Win32::System::WindowsProgramming::{
+        GetFirmwareEnvironmentVariableExW, SetFirmwareEnvironmentVariableExW,
+    };
+
+    /// The Win32 error behind a failed call, so error codes can be matched
+    fn os_error(e: ::windows::core::Error) -> io::Error {
+        match WIN32_ERROR::from_error(&e) {
+            Some(code) => io::Error::from_raw_os_error(code.0 as i32),
+            None => io::Error::other(e),
+        }
+    }
+
+    fn is_not_found(e: &io::Error) -> bool {
+        e.raw_os_error() == Some(ERROR_ENVVAR_NOT_FOUND.0 as i32)
+    }
+
+    fn names() -> (HSTRING, HSTRING) {
+        (
+            HSTRING::from(VARIABLE_NAME),
+            HSTRING::from(format!("{{{}}}", VENDOR_GUID)),
+        )
+    }
+
+    /// Enable SeSystemEnvironmentPrivilege, which the firmware API requires
+    fn enable_privilege() -> io::Result<()> {
+        let mut token = HANDLE::default();
+        // SAFETY: plain Win32 calls with valid pointers; the token is closed below
+        unsafe {
+            OpenProcessToken(
+                GetCurrentProcess(),
+                TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY,
+                &mut token,
+            )
+            .map_err(os_error)?;
+            let mut luid = LUID::default();
+            let result =
+                LookupPrivilegeValueW(PCWSTR::null(), SE_SYSTEM_ENVIRONMENT_NAME, &mut luid)
+                    .and_then(|()| {
+                        let privileges = TOKEN_PRIVILEGES {
+                            PrivilegeCount: 1,
+                            Privileges: [LUID_AND_ATTRIBUTES {
+                                Luid: luid,
+                                Attributes: SE_PRIVILEGE_ENABLED,
+                            }],
+                        };
+                        AdjustTokenPrivileges(token, false, Some(&privileges), 0, None, None)
+                    })
+                    .map_err(os_error);
+            let _ = CloseHandle(token);
+            result
+        }
+    }
+
+    impl EfiVariable {
+        fn set(&self, value: &[u8]) -> io::Result<()> {
+            enable_privilege()?;
+            let (name, guid) = names();
+            // SAFETY: `value` is valid for `value.len()` bytes
+            unsafe {
+                SetFirmwareEnvironmentVariableExW(
+                    &name,
+                    &guid,
+                    Some(value.as_ptr().cast()),
+                    value.len() as u32,
+                    ATTRIBUTES,
+                )
+            }
+            .map_err(os_error)
+        }
+
+        /// Check that EFI variables can be accessed
+        pub fn check(&self) -> Result<(), String> {
+            enable_privilege().map_err(|e| format!("EFI variables are not available: {}", e))?;
+            self.read()
+                .map(|_| ())
+                .map_err(|e| format!("EFI variables are not available: {}", e))
+        }
+
+        /// Read the value, `None` if the variable does not exist
+        pub fn read(&self) -> io::Result<Option<Zeroizing<Vec<u8>>>> {
+            enable_privilege()?;
+            let (name, guid) = names();
+            let mut buffer = Zeroizing::new(vec![0u8; MAX_VARIABLE_SIZE]);
+            let mut attributes = 0u32;
+            // SAFETY: `buffer` is valid for `buffer.len()` bytes
+            let len = unsafe {
+                GetFirmwareEnvironmentVariableExW(
+                    &name,
+                    &guid,
+                    Some(buffer.as_mut_ptr().cast()),
+                    buffer.len() as u32,
+                    Some(&mut attributes),
+                )
+            };
+            if len == 0 {
+                let e = io::Error::last_os_error();
+                return if is_not_found(&e) { Ok(None) } else { Err(e) };
+            }
+            buffer.truncate(len as usize);
+            Ok(Some(buffer))
+        }
+
+        /// Create or replace the variable
+        pub fn write(&self, value: &[u8]) -> io::Result<()> {
+            if value.len() > MAX_VARIABLE_SIZE {
+                return Err(too_large(value.len()));
+            }
+            self.set(value)
+        }
+
+        /// Delete the variable if it exists
+        pub fn remove(&self) -> io::Result<()> {
+            match self.set(&[]) {
+                Err(e) if is_not_found(&e) => Ok(()),
+                result => result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_vendor_guid_is_stable() {
+        assert_eq!(VENDOR_GUID, "e7b1a5c2-3f4d-4b6e-9a8c-b1e5ee1a5e01");
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let json = br#"{"adapters":{"00:11:22:33:44:55":{"AA:BB:CC:DD:EE:FF":{"link_key":"0123456789ABCDEF0123456789ABCDEF"},"AA:BB:CC:DD:EE:00":{"link_key":"0123456789ABCDEF0123456789ABCDEF"}}}}"#;
+        let packed = pack(json);
+        assert!(packed.starts_with(COMPRESSED_MAGIC));
+        assert!(packed.len() < json.len());
+        assert_eq!(&unpack(&packed).unwrap()[..], &json[..]);
+
+        // Too short to gain from compression
+        assert_eq!(&pack(b"{}")[..], b"{}");
+        assert_eq!(&unpack(b"{}").unwrap()[..], b"{}");
+
+        let mut corrupt = packed.to_vec();
+        corrupt.truncate(corrupt.len() - 3);
+        assert!(unpack(&corrupt).is_err());
+
+        // Lengths deflate cannot produce are refused before allocating
+        let mut oversized = packed.to_vec();
+        oversized[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(unpack(&oversized).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_variable_on_fake_efivarfs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let variable = parse_target(&format!("efivar:{}", dir.display())).unwrap();
+        variable.check().unwrap();
+        assert!(variable.read().unwrap().is_none());
+
+        variable.write(b"first value").unwrap();
+        variable.write(b"second").unwrap();
+        let raw = fs::read(dir.join(format!("BlueVeinConfig-{}", VENDOR_GUID))).unwrap();
+        assert_eq!(&raw[..4], &[0x07, 0, 0, 0]);
+        let mode = fs::metadata(dir.join(format!("BlueVeinConfig-{}", VENDOR_GUID)))
+            .unwrap()
+            .permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+        assert_eq!(&variable.read().unwrap().unwrap()[..], b"second");
+
+        let err = variable.write(&[0; MAX_VARIABLE_SIZE + 1]).unwrap_err();
+        assert!(is_too_large(&err));
+
+        variable.remove().unwrap();
+        assert!(variable.read().unwrap().is_none());
+        assert!(parse_target("/boot/efi").is_none());
+    }
+}
@@ -0,0 +1,161 @@
+//! Crate-wide error type
+//!
+//! Separates the failure classes callers need to react to differently:
+//! retry when a backend is temporarily unavailable, degrade when a device or
+//! the store is missing, alert on corrupt data or bad key material.
+
+use crate::efi::EfiError;
+use crate::types::MacAddress;
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BlueVeinError {
+    /// Insufficient privileges for the Bluetooth backend or the store
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// No configuration exists on the EFI partition yet
+    #[error("Configuration file not found on EFI partition")]
+    StoreNotFound,
+
+    /// Configuration exists but cannot be decoded
+    #[error("Configuration on EFI partition is corrupt: {0}")]
+    StoreCorrupt(String),
+
+    /// Encrypted configuration failed authentication (wrong key or modified file)
+    #[error("Configuration on EFI partition failed integrity check: {0}")]
+    StoreTampered(String),
+
+    /// Configuration was written by a newer BlueVein and must not be overwritten
+    #[error("Configuration on EFI partition needs a newer BlueVein: {0}")]
+    StoreIncompatible(String),
+
+    /// Another BlueVein process holds the store lock for too long
+    #[error("Configuration on EFI partition is busy: {0}")]
+    StoreBusy(String),
+
+    /// Requested generation or device keys are not in the config history
+    #[error("Cannot roll back: {0}")]
+    RollbackUnavailable(String),
+
+    /// No local backup with this id
+    #[error("Backup not found: {0}")]
+    BackupNotFound(String),
+
+    /// Config encryption is misconfigured
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    /// String is not a valid Bluetooth MAC address
+    #[error("Invalid MAC address: {0:?}")]
+    InvalidAddress(String),
+
+    /// Key material failed validation
+    #[error("Invalid {key_name}: {reason}")]
+    InvalidKey { key_name: String, reason: String },
+
+    /// Bluetooth stack, registry or EFI partition cannot be accessed right now
+    #[error("Backend unavailable: {0}")]
+    BackendUnavailable(String),
+
+    /// Device has no pairing on the given adapter
+    #[error("Device {device} not found on adapter {adapter}")]
+    DeviceNotFound { adapter: String, device: String },
+}
+
+impl BlueVeinError {
+    /// Classify an I/O error, prefixing the message with some context
+    pub fn io(context: impl AsRef<str>, err: io::Error) -> Self {
+        let message = format!("{}: {}", context.as_ref(), err);
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => BlueVeinError::PermissionDenied(message),
+            _ => BlueVeinError::BackendUnavailable(message),
+        }
+    }
+
+    /// Whether the operation may succeed if retried later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            BlueVeinError::BackendUnavailable(_) | BlueVeinError::StoreBusy(_)
+        )
+    }
+
+    /// Build an `InvalidKey` error
+    pub fn invalid_key(key_name: &str, reason: impl Into<String>) -> Self {
+        BlueVeinError::InvalidKey {
+            key_name: key_name.to_string(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Build a `DeviceNotFound` error
+    pub fn device_not_found(adapter_mac: &MacAddress, device_mac: &MacAddress) -> Self {
+        BlueVeinError::DeviceNotFound {
+            adapter: adapter_mac.to_string(),
+            device: device_mac.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for BlueVeinError {
+    fn from(err: io::Error) -> Self {
+        BlueVeinError::io("I/O error", err)
+    }
+}
+
+impl From<EfiError> for BlueVeinError {
+    fn from(err: EfiError) -> Self {
+        match err {
+            EfiError::NotFound => BlueVeinError::StoreNotFound,
+            EfiError::ParseError(msg) => BlueVeinError::StoreCorrupt(msg),
+            EfiError::Tampered(msg) => BlueVeinError::StoreTampered(msg),
+            EfiError::EncryptionError(msg) => BlueVeinError::Encryption(msg),
+            EfiError::IncompatibleSchema(msg) => BlueVeinError::StoreIncompatible(msg),
+            EfiError::ReadError(msg) | EfiError::WriteError(msg) => {
+                BlueVeinError::BackendUnavailable(format!("EFI partition: {}", msg))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_error_classification() {
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "EACCES");
+        assert!(matches!(
+            BlueVeinError::io("open info", denied),
+            BlueVeinError::PermissionDenied(msg) if msg == "open info: EACCES"
+        ));
+
+        let busy = io::Error::other("EBUSY");
+        assert!(matches!(
+            BlueVeinError::io("open info", busy),
+            BlueVeinError::BackendUnavailable(_)
+        ));
+    }
+
+    #[test]
+    fn test_efi_error_conversion() {
+        assert!(matches!(
+            BlueVeinError::from(EfiError::NotFound),
+            BlueVeinError::StoreNotFound
+        ));
+        assert!(matches!(
+            BlueVeinError::from(EfiError::ParseError("eof".to_string())),
+            BlueVeinError::StoreCorrupt(_)
+        ));
+        assert!(matches!(
+            BlueVeinError::from(EfiError::WriteError("busy".to_string())),
+            BlueVeinError::BackendUnavailable(_)
+        ));
+        assert!(matches!(
+            BlueVeinError::from(EfiError::Tampered("bad tag".to_string())),
+            BlueVeinError::StoreTampered(_)
+        ));
+    }
+}
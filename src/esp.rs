@@ -0,0 +1,614 @@
+//! Discovery of the EFI System Partition on Linux
+//!
+//! Partitions are identified by their GPT type GUID from the udev database
+//! rather than by guessing mount points. The partition named by the
+//! `LoaderDevicePartUUID` EFI variable (set by systemd-boot and other
+//! boot loaders implementing the Boot Loader Interface) is preferred, so the
+//! ESP the machine actually booted from is used on multi-disk systems.
+//!
+//! A partition the kernel has mounted is never written to raw, since the
+//! kernel's FAT cache and the raw writes would corrupt each other. An
+//! unmounted one is mounted for each operation in a private mount namespace
+//! of a short-lived helper process, invisible to the rest of the system.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// GPT type GUID of an EFI System Partition
+const ESP_TYPE_GUID: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+/// GPT type GUID of an Extended Boot Loader partition (XBOOTLDR)
+const XBOOTLDR_TYPE_GUID: &str = "bc13c2ff-59e6-4262-a352-b275fd6f7172";
+
+const LOADER_DEVICE_PART_UUID: &str =
+    "/sys/firmware/efi/efivars/LoaderDevicePartUUID-4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+
+/// Traditional ESP mount points, used when no partition can be identified
+const FALLBACK_MOUNT_POINTS: &[&str] = &["/boot/efi", "/efi", "/boot"];
+
+/// Kind of boot partition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PartitionKind {
+    Esp,
+    Xbootldr,
+}
+
+/// Boot partition found on this system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EspPartition {
+    /// Block device, e.g. `/dev/nvme0n1p1`
+    pub device: PathBuf,
+    pub kind: PartitionKind,
+    /// GPT partition UUID, lowercase
+    pub part_uuid: Option<String>,
+    /// Where the partition is mounted as vfat, if it is
+    pub mount_point: Option<PathBuf>,
+    /// Whether the boot loader reported booting from this partition
+    pub booted: bool,
+}
+
+/// One line of `/proc/self/mountinfo`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    /// `major:minor` of the mounted device
+    pub dev: String,
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    pub read_only: bool,
+    /// Filesystem options, e.g. `fmask=0022,dmask=0022` for vfat
+    pub super_options: String,
+}
+
+/// Parse the contents of `/proc/self/mountinfo`
+pub fn parse_mountinfo(contents: &str) -> Vec<MountEntry> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (fields, tail) = line.split_once(" - ")?;
+            let fields: Vec<&str> = fields.split(' ').collect();
+            let mut tail = tail.split(' ');
+            Some(MountEntry {
+                dev: fields.get(2)?.to_string(),
+                mount_point: PathBuf::from(unescape(fields.get(4)?)),
+                read_only: fields.get(5)?.split(',').any(|option| option == "ro"),
+                fstype: tail.next()?.to_string(),
+                source: unescape(tail.next()?),
+                super_options: tail.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+fn read_mountinfo() -> Vec<MountEntry> {
+    fs::read_to_string("/proc/self/mountinfo")
+        .map(|contents| parse_mountinfo(&contents))
+        .unwrap_or_default()
+}
+
+/// `major:minor` of a device number, as used in mountinfo
+fn device_id(rdev: u64) -> String {
+    format!("{}:{}", libc::major(rdev), libc::minor(rdev))
+}
+
+pub fn is_block_device(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_block_device())
+}
+
+/// Every mount of the block device at `device`, found by device number so
+/// that mounts through another device path are found too
+pub fn mounts_of(device: &Path) -> Vec<MountEntry> {
+    let Ok(metadata) = fs::metadata(device) else {
+        return Vec::new();
+    };
+    if !metadata.file_type().is_block_device() {
+        return Vec::new();
+    }
+    let dev = device_id(metadata.rdev());
+    read_mountinfo()
+        .into_iter()
+        .filter(|mount| mount.dev == dev)
+        .collect()
+}
+
+/// Decode the octal escapes (`\040` for a space) used in mountinfo
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 4).and_then(|digits| {
+            let digits = std::str::from_utf8(digits).ok()?;
+            u8::from_str_radix(digits, 8).ok()
+        });
+        match (bytes[i], escaped) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Parse the `LoaderDevicePartUUID` variable: 4 bytes of attributes followed
+/// by a UTF-16LE UUID string
+pub fn parse_loader_part_uuid(data: &[u8]) -> Option<String> {
+    let units: Vec<u16> = data
+        .get(4..)?
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|unit| *unit != 0)
+        .collect();
+    let uuid = String::from_utf16(&units).ok()?.trim().to_lowercase();
+    (!uuid.is_empty()).then_some(uuid)
+}
+
+/// Partition type and UUID from a udev database entry (`/run/udev/data/b<maj>:<min>`)
+fn parse_udev_data(contents: &str) -> (Option<String>, Option<String>) {
+    let property = |name: &str| {
+        contents.lines().find_map(|line| {
+            line.strip_prefix("E:")?
+                .strip_prefix(name)?
+                .strip_prefix('=')
+                .map(str::to_lowercase)
+        })
+    };
+    (
+        property("ID_PART_ENTRY_TYPE"),
+        property("ID_PART_ENTRY_UUID"),
+    )
+}
+
+/// Find ESP and XBOOTLDR partitions, most preferred first
+///
+/// The booted ESP comes first, then ESPs before XBOOTLDR partitions, then
+/// mounted partitions. Without udev data, the booted partition is still
+/// found through its `/dev/disk/by-partuuid` symlink.
+pub fn discover() -> Vec<EspPartition> {
+    let mounts = fs::read_to_string("/proc/self/mountinfo")
+        .map(|contents| parse_mountinfo(&contents))
+        .unwrap_or_default();
+    let booted = fs::read(LOADER_DEVICE_PART_UUID)
+        .ok()
+        .and_then(|data| parse_loader_part_uuid(&data));
+
+    let Ok(entries) = fs::read_dir("/sys/class/block") else {
+        return Vec::new();
+    };
+    let mount_point_of = |dev: &str| {
+        mounts
+            .iter()
+            .find(|mount| mount.dev == dev && mount.fstype == "vfat")
+            .map(|mount| mount.mount_point.clone())
+    };
+
+    let mut partitions: Vec<EspPartition> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("partition").exists())
+        .filter_map(|entry| {
+            let dev = fs::read_to_string(entry.path().join("dev")).ok()?;
+            let dev = dev.trim();
+            let udev = fs::read_to_string(format!("/run/udev/data/b{}", dev)).ok()?;
+            let (part_type, part_uuid) = parse_udev_data(&udev);
+            let kind = match part_type.as_deref() {
+                Some(ESP_TYPE_GUID) => PartitionKind::Esp,
+                Some(XBOOTLDR_TYPE_GUID) => PartitionKind::Xbootldr,
+                _ => return None,
+            };
+
+            Some(EspPartition {
+                device: Path::new("/dev").join(entry.file_name()),
+                kind,
+                booted: booted.is_some() && part_uuid == booted,
+                part_uuid,
+                mount_point: mount_point_of(dev),
+            })
+        })
+        .collect();
+
+    if let Some(uuid) = booted.filter(|_| !partitions.iter().any(|p| p.booted)) {
+        let link = Path::new("/dev/disk/by-partuuid").join(&uuid);
+        if let Ok(device) = fs::canonicalize(link) {
+            let name = device.file_name().unwrap_or_default().to_owned();
+            let dev = fs::read_to_string(Path::new("/sys/class/block").join(name).join("dev"))
+                .unwrap_or_default();
+            partitions.push(EspPartition {
+                mount_point: mount_point_of(dev.trim()),
+                device,
+                kind: PartitionKind::Esp,
+                part_uuid: Some(uuid),
+                booted: true,
+            });
+        }
+    }
+
+    partitions.sort_by_key(|partition| {
+        (
+            !partition.booted,
+            partition.kind,
+            partition.mount_point.is_none(),
+            partition.device.clone(),
+        )
+    });
+    partitions
+}
+
+/// Mount point of the ESP to use, if it is mounted
+///
+/// Without udev data, falls back to the traditional mount points when they
+/// are mounted as vfat and contain an `EFI` directory.
+pub fn find_mounted() -> Option<PathBuf> {
+    let partitions = discover();
+    if let Some(partition) = partitions.first() {
+        return partition.mount_point.clone();
+    }
+
+    let mounts = read_mountinfo();
+    FALLBACK_MOUNT_POINTS.iter().find_map(|candidate| {
+        mounts
+            .iter()
+            .find(|mount| {
+                mount.mount_point == Path::new(candidate)
+                    && mount.fstype == "vfat"
+                    && mount.mount_point.join("EFI").is_dir()
+            })
+            .map(|mount| mount.mount_point.clone())
+    })
+}
+
+/// Block device of the ESP to open for direct access
+pub fn find_device() -> Option<PathBuf> {
+    discover()
+        .into_iter()
+        .find(|partition| partition.kind == PartitionKind::Esp)
+        .map(|partition| partition.device)
+}
+
+/// Mode of files on a vfat mount, from its `fmask` (or `umask`) option
+fn vfat_file_mode(super_options: &str) -> Option<u32> {
+    let mask = |name: &str| {
+        super_options
+            .split(',')
+            .find_map(|option| option.strip_prefix(name)?.strip_prefix('='))
+            .and_then(|value| u32::from_str_radix(value, 8).ok())
+    };
+    mask("fmask")
+        .or_else(|| mask("umask"))
+        .map(|mask| 0o777 & !mask)
+}
+
+/// Why users other than the owner could read the file at `path`, if they could
+///
+/// An existing file is judged by its mode and the modes of its directories.
+/// A file yet to be created is only exposed on vfat, which has no per-file
+/// permissions and gives every file the mode set by the mount's `fmask`.
+pub fn exposure(path: &Path) -> Option<String> {
+    // A directory others cannot enter hides everything below it
+    let hidden = path
+        .ancestors()
+        .skip(1)
+        .filter_map(|dir| fs::metadata(dir).ok())
+        .any(|metadata| metadata.mode() & 0o001 == 0);
+    if hidden {
+        return None;
+    }
+
+    if let Ok(metadata) = fs::metadata(path) {
+        let mode = metadata.mode() & 0o777;
+        return (mode & 0o004 != 0).then(|| {
+            format!(
+                "{} is readable by every user (mode {:04o})",
+                path.display(),
+                mode
+            )
+        });
+    }
+    let mount = read_mountinfo()
+        .into_iter()
+        .filter(|mount| path.starts_with(&mount.mount_point))
+        .max_by_key(|mount| mount.mount_point.components().count())?;
+    if mount.fstype != "vfat" {
+        return None;
+    }
+    let mode = vfat_file_mode(&mount.super_options)?;
+    (mode & 0o004 != 0).then(|| {
+        format!(
+            "{} is mounted with fmask={:04o}, so every user can read its files",
+            mount.mount_point.display(),
+            0o777 & !mode
+        )
+    })
+}
+
+/// Take the permissions of others away from the file at `path` and from
+/// its directories below `root`
+///
+/// Filesystems without Unix permissions ignore or reject this, so the result
+/// is checked again with [`exposure`].
+pub fn restrict(path: &Path, root: &Path) -> io::Result<()> {
+    if path.exists() {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        if let Ok(metadata) = fs::metadata(dir) {
+            let mode = metadata.mode() & 0o7777 & !0o007;
+            fs::set_permissions(dir, fs::Permissions::from_mode(mode))?;
+        }
+    }
+    Ok(())
+}
+
+/// Hidden command running one file operation on a privately mounted partition
+pub const PRIVATE_IO_COMMAND: &str = "esp-io";
+
+/// Exit codes of the helper besides 0 and 1
+const EXIT_MOUNT_FAILED: i32 = 3;
+const EXIT_NOT_FOUND: i32 = 4;
+
+/// Unmounted partition accessed through a private mount
+pub struct PrivateMount {
+    device: PathBuf,
+}
+
+impl PrivateMount {
+    pub fn new(device: impl Into<PathBuf>) -> Self {
+        Self {
+            device: device.into(),
+        }
+    }
+
+    /// Run the helper for `operation` on `path` relative to the partition root
+    ///
+    /// Fails with `ErrorKind::Unsupported` if the partition cannot be mounted.
+    fn run(&self, operation: &str, path: &str, input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        let mut child = Command::new(env::current_exe()?)
+            .args([PRIVATE_IO_COMMAND, operation])
+            .arg(&self.device)
+            .arg(path)
+            .stdin(if input.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input)?;
+        }
+
+        let output = child.wait_with_output()?;
+        let message = || String::from_utf8_lossy(&output.stderr).trim().to_string();
+        match output.status.code() {
+            Some(0) => Ok(output.stdout),
+            Some(EXIT_NOT_FOUND) => Err(io::ErrorKind::NotFound.into()),
+            Some(EXIT_MOUNT_FAILED) => Err(io::Error::new(io::ErrorKind::Unsupported, message())),
+            _ => Err(io::Error::other(message())),
+        }
+    }
+
+    /// Read a file, `None` if it does not exist
+    pub fn read(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.run("read", path, None) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replace a file, creating missing directories
+    pub fn write(&self, path: &str, data: &[u8]) -> io::Result<()> {
+        self.run("write", path, Some(data)).map(|_| ())
+    }
+
+    /// Delete a file if it exists
+    pub fn remove(&self, path: &str) -> io::Result<()> {
+        self.run("remove", path, None).map(|_| ())
+    }
+}
+
+/// Mount `device` at `dir` in a new mount namespace of this process
+///
+/// Must run before any other thread is started.
+fn mount_private(device: &Path, dir: &Path) -> Result<(), String> {
+    use nix::mount::{mount, MsFlags};
+    use nix::sched::{unshare, CloneFlags};
+
+    if !is_block_device(device) {
+        return Err(format!("{} is not a block device", device.display()));
+    }
+    if let Some(mount) = mounts_of(device).first() {
+        return Err(format!(
+            "{} is mounted at {}",
+            device.display(),
+            mount.mount_point.display()
+        ));
+    }
+
+    unshare(CloneFlags::CLONE_NEWNS).map_err(|e| format!("unshare: {}", e))?;
+    // Keep the mount from propagating back to the system's namespace
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )
+    .map_err(|e| format!("making mounts private: {}", e))?;
+    fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    mount(
+        Some(device),
+        dir,
+        Some("vfat"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        Some("umask=0077"),
+    )
+    .map_err(|e| format!("mounting {}: {}", device.display(), e))
+}
+
+/// Entry point of the helper process started by [`PrivateMount`], returning its exit code
+///
+/// Arguments are the operation, the device and the path on the partition.
+/// The file is read to stdout or written from stdin; the partition is
+/// unmounted, flushing it, before the helper exits.
+pub fn run_private_io(args: &[String]) -> i32 {
+    let [operation, device, path] = args else {
+        eprintln!(
+            "usage: {} <read|write|remove> <device> <path>",
+            PRIVATE_IO_COMMAND
+        );
+        return 1;
+    };
+    let dir = env::temp_dir().join(format!("bluevein-esp-{}", std::process::id()));
+    if let Err(e) = mount_private(Path::new(device), &dir) {
+        eprintln!("{}", e);
+        let _ = fs::remove_dir(&dir);
+        return EXIT_MOUNT_FAILED;
+    }
+
+    let target = dir.join(path);
+    let result = match operation.as_str() {
+        "read" => fs::read(&target).and_then(|data| {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()
+        }),
+        "write" => (|| {
+            let mut data = Vec::new();
+            io::stdin().read_to_end(&mut data)?;
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = fs::File::create(&target)?;
+            file.write_all(&data)?;
+            file.sync_all()
+        })(),
+        "remove" => match fs::remove_file(&target) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unknown operation {}", operation),
+        )),
+    };
+
+    let unmounted = nix::mount::umount(&dir);
+    let _ = fs::remove_dir(&dir);
+    match (result, unmounted) {
+        (Err(e), _) if e.kind() == io::ErrorKind::NotFound => EXIT_NOT_FOUND,
+        (Err(e), _) => {
+            eprintln!("{}: {}", path, e);
+            1
+        }
+        (Ok(()), Err(e)) => {
+            eprintln!("unmounting {}: {}", device, e);
+            1
+        }
+        (Ok(()), Ok(())) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let contents = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+40 22 259:1 / /boot/my\\040efi rw,relatime shared:20 - vfat /dev/nvme0n1p1 rw,fmask=0077
+";
+        let mounts = parse_mountinfo(contents);
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[1].dev, "259:1");
+        assert_eq!(mounts[1].mount_point, PathBuf::from("/boot/my efi"));
+        assert_eq!(mounts[1].fstype, "vfat");
+        assert_eq!(mounts[1].source, "/dev/nvme0n1p1");
+        assert!(!mounts[1].read_only);
+        assert_eq!(mounts[1].super_options, "rw,fmask=0077");
+        assert_eq!(vfat_file_mode(&mounts[1].super_options), Some(0o700));
+        assert_eq!(vfat_file_mode("rw,umask=0022"), Some(0o755));
+        assert_eq!(vfat_file_mode("rw,fmask=0022,umask=0077"), Some(0o755));
+        assert!(
+            parse_mountinfo("41 22 259:1 / /efi ro,relatime - vfat /dev/nvme0n1p1 rw\n")[0]
+                .read_only
+        );
+        assert_eq!(device_id(libc::makedev(259, 1)), "259:1");
+    }
+
+    #[test]
+    fn test_parse_loader_variable_and_udev_data() {
+        let uuid = "6A4E0C5D-1F2B-4C3D-9E8F-0A1B2C3D4E5F";
+        let mut data = vec![0x06, 0, 0, 0];
+        for unit in uuid.encode_utf16().chain([0]) {
+            data.extend_from_slice(&unit.to_le_bytes());
+        }
+        assert_eq!(
+            parse_loader_part_uuid(&data).as_deref(),
+            Some("6a4e0c5d-1f2b-4c3d-9e8f-0a1b2c3d4e5f")
+        );
+        assert_eq!(parse_loader_part_uuid(&[0, 0, 0, 0]), None);
+
+        let udev = "S:disk/by-partuuid/6a4e0c5d\n\
+E:ID_PART_ENTRY_TYPE=C12A7328-F81F-11D2-BA4B-00A0C93EC93B\n\
+E:ID_PART_ENTRY_UUID=6a4e0c5d-1f2b-4c3d-9e8f-0a1b2c3d4e5f\n";
+        let (part_type, part_uuid) = parse_udev_data(udev);
+        assert_eq!(part_type.as_deref(), Some(ESP_TYPE_GUID));
+        assert_eq!(
+            part_uuid.as_deref(),
+            Some("6a4e0c5d-1f2b-4c3d-9e8f-0a1b2c3d4e5f")
+        );
+    }
+
+    #[test]
+    fn test_private_io_refuses_non_block_devices() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("esp");
+        fs::write(&path, b"not a partition").unwrap();
+        assert!(mounts_of(&path).is_empty());
+
+        let args = |operation: &str| {
+            [operation, &path.to_string_lossy(), "EFI/bluevein.json"].map(str::to_string)
+        };
+        assert_eq!(run_private_io(&args("read")), EXIT_MOUNT_FAILED);
+        assert_eq!(run_private_io(&args("write")[..2]), 1);
+    }
+
+    #[test]
+    fn test_exposure_and_restrict() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("esp");
+        let path = root.join("EFI/BlueVein/config.json");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        for dir in [&root, &root.join("EFI"), &root.join("EFI/BlueVein")] {
+            fs::set_permissions(dir, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::write(&path, b"{}").unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(exposure(&path).unwrap().contains("mode 0644"));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(exposure(&path), None);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        restrict(&path, &root).unwrap();
+        assert_eq!(exposure(&path), None);
+        let mode = |path: &Path| fs::metadata(path).unwrap().mode() & 0o777;
+        assert_eq!(mode(&path), 0o600);
+        assert_eq!(mode(&root.join("EFI/BlueVein")), 0o750);
+        assert_eq!(mode(&root.join("EFI")), 0o750);
+        assert_eq!(mode(&root), 0o755);
+    }
+}
@@ -0,0 +1,434 @@
+//! Per-device sync scope
+//!
+//! Rules from the `[[filter]]` tables of the settings file decide whether a
+//! device takes part in the sync and which of its key families are shared.
+//! Rules are tried in order and the first one matching a device applies;
+//! devices no rule matches sync all their keys.
+
+use crate::bluetooth::{BluetoothDevice, LeKeys};
+use crate::types::MacAddress;
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// Key families a device can sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyFamilies {
+    /// Classic link key
+    pub classic: bool,
+    /// LE long term keys
+    pub le: bool,
+    /// LE signature keys (local and remote CSRK)
+    pub csrk: bool,
+    /// LE identity resolving key
+    pub irk: bool,
+}
+
+impl Default for KeyFamilies {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyFamilies {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = Vec::<String>::deserialize(deserializer)?;
+        Self::from_names(names.iter().map(String::as_str)).map_err(de::Error::custom)
+    }
+}
+
+impl KeyFamilies {
+    pub const ALL: Self = Self {
+        classic: true,
+        le: true,
+        csrk: true,
+        irk: true,
+    };
+
+    const NONE: Self = Self {
+        classic: false,
+        le: false,
+        csrk: false,
+        irk: false,
+    };
+
+    /// Families named in a settings list, e.g. `["classic", "irk"]`
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut families = Self::NONE;
+        for name in names {
+            match name {
+                "classic" => families.classic = true,
+                "le" => families.le = true,
+                "csrk" => families.csrk = true,
+                "irk" => families.irk = true,
+                _ => {
+                    return Err(format!(
+                        "unknown key family {}, expected classic, le, csrk or irk",
+                        name
+                    ))
+                }
+            }
+        }
+        if families == Self::NONE {
+            return Err("no key families given, use sync = false to exclude devices".to_string());
+        }
+        Ok(families)
+    }
+
+    /// The keys of `device` in these families, `None` if none are left
+    pub fn select(&self, device: &BluetoothDevice) -> Option<BluetoothDevice> {
+        if *self == Self::ALL {
+            return Some(device.clone());
+        }
+
+        let le = device.le.as_ref().and_then(|le| {
+            let keys = LeKeys {
+                ltk: le.ltk.clone().filter(|_| self.le),
+                peripheral_ltk: le.peripheral_ltk.clone().filter(|_| self.le),
+                irk: le.irk.clone().filter(|_| self.irk),
+                csrk_local: le.csrk_local.clone().filter(|_| self.csrk),
+                csrk_remote: le.csrk_remote.clone().filter(|_| self.csrk),
+                address_type: le.address_type.clone(),
+                extra: le.extra.clone(),
+            };
+            let has_keys = keys.ltk.is_some()
+                || keys.peripheral_ltk.is_some()
+                || keys.irk.is_some()
+                || keys.csrk_local.is_some()
+                || keys.csrk_remote.is_some();
+            has_keys.then_some(keys)
+        });
+        let selected = BluetoothDevice {
+            classic: device.classic.clone().filter(|_| self.classic),
+            le,
+            ..device.clone()
+        };
+        selected.has_keys().then_some(selected)
+    }
+}
+
+/// Class of Device a rule matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    /// Major device class, e.g. `audio` (bits 8-12 of the Class of Device)
+    Major(u32),
+    /// Exact Class of Device, e.g. `0x240404`
+    Exact(u32),
+}
+
+/// Major device classes from the Bluetooth Assigned Numbers
+const MAJOR_CLASSES: [(&str, u32); 10] = [
+    ("uncategorized", 0x1f),
+    ("computer", 0x01),
+    ("phone", 0x02),
+    ("network", 0x03),
+    ("audio", 0x04),
+    ("peripheral", 0x05),
+    ("imaging", 0x06),
+    ("wearable", 0x07),
+    ("toy", 0x08),
+    ("health", 0x09),
+];
+
+impl DeviceClass {
+    fn matches(&self, class: u32) -> bool {
+        match self {
+            DeviceClass::Major(major) => (class >> 8) & 0x1f == *major,
+            DeviceClass::Exact(exact) => class & 0xff_ffff == *exact,
+        }
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MAJOR_CLASSES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, major)| DeviceClass::Major(*major))
+            .ok_or_else(|| {
+                let names: Vec<&str> = MAJOR_CLASSES.iter().map(|(name, _)| *name).collect();
+                format!(
+                    "unknown device class {}, expected one of {} or a number",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// A major class name, or an exact Class of Device as a number
+impl<'de> Deserialize<'de> for DeviceClass {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ClassVisitor;
+
+        impl Visitor<'_> for ClassVisitor {
+            type Value = DeviceClass;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a major class name or a number")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                match u32::try_from(value) {
+                    Ok(class) if class <= 0xff_ffff => Ok(DeviceClass::Exact(class)),
+                    _ => Err(E::invalid_value(de::Unexpected::Unsigned(value), &self)),
+                }
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                u64::try_from(value)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+                    .and_then(|value| self.visit_u64(value))
+            }
+        }
+
+        deserializer.deserialize_any(ClassVisitor)
+    }
+}
+
+/// One filter rule; criteria left unset match every device
+///
+/// Read from a `[[filter]]` table of the settings file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterRule {
+    pub adapter: Option<MacAddress>,
+    pub device: Option<MacAddress>,
+    /// Case-insensitive name pattern, `*` and `?` are wildcards
+    pub name: Option<String>,
+    pub class: Option<DeviceClass>,
+    /// Whether matching devices take part in the sync at all
+    pub sync: bool,
+    /// Key families matching devices sync
+    pub keys: KeyFamilies,
+}
+
+impl Default for FilterRule {
+    fn default() -> Self {
+        Self {
+            adapter: None,
+            device: None,
+            name: None,
+            class: None,
+            sync: true,
+            keys: KeyFamilies::ALL,
+        }
+    }
+}
+
+impl FilterRule {
+    fn matches(&self, adapter_mac: &MacAddress, device: &BluetoothDevice) -> bool {
+        self.adapter.is_none_or(|adapter| adapter == *adapter_mac)
+            && self.device.is_none_or(|mac| mac == device.mac_address)
+            && self.name.as_ref().is_none_or(|pattern| {
+                device
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| matches_pattern(pattern, name))
+            })
+            && self
+                .class
+                .is_none_or(|class| device.class.is_some_and(|value| class.matches(value)))
+    }
+}
+
+/// Whether `name` matches a pattern with `*` and `?` wildcards, ignoring case
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    // Greedy matching that backtracks to the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Ordered filter rules of this installation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filters {
+    rules: Vec<FilterRule>,
+}
+
+impl Filters {
+    pub fn new(rules: Vec<FilterRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Key families `device` syncs on `adapter_mac`, `None` if it is excluded
+    pub fn scope(&self, adapter_mac: &MacAddress, device: &BluetoothDevice) -> Option<KeyFamilies> {
+        match self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(adapter_mac, device))
+        {
+            Some(rule) if !rule.sync => None,
+            Some(rule) => Some(rule.keys),
+            None => Some(KeyFamilies::ALL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::LeLongTermKey;
+    use crate::config::UnknownFields;
+    use crate::types::Key128;
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    fn key(s: &str) -> Key128 {
+        s.parse().unwrap()
+    }
+
+    /// The part of `device` that is synced on `adapter`
+    fn select(
+        filters: &Filters,
+        adapter: &MacAddress,
+        device: &BluetoothDevice,
+    ) -> Option<BluetoothDevice> {
+        filters.scope(adapter, device)?.select(device)
+    }
+
+    /// Dual-mode device with a link key, an LTK and an IRK
+    fn dual_device(name: &str, class: u32) -> BluetoothDevice {
+        let mut device = BluetoothDevice::le_with_ltk(
+            mac("AA:BB:CC:DD:EE:FF"),
+            LeLongTermKey {
+                key: key("FEDCBA9876543210FEDCBA9876543210"),
+                authenticated: Some(1),
+                enc_size: Some(16),
+                ediv: Some(100),
+                rand: Some(12345),
+                extra: UnknownFields::default(),
+            },
+        );
+        device.le.as_mut().unwrap().irk = Some(key("00112233445566778899AABBCCDDEEFF"));
+        device.classic =
+            BluetoothDevice::classic(device.mac_address, key("0123456789ABCDEF0123456789ABCDEF"))
+                .classic;
+        device.name = Some(name.to_string());
+        device.class = Some(class);
+        device
+    }
+
+    #[test]
+    fn test_first_matching_rule_applies() {
+        let adapter = mac("00:11:22:33:44:55");
+        let headset = dual_device("Lab Headset 2", 0x240404);
+        let filters = Filters::new(vec![
+            FilterRule {
+                name: Some("lab *".to_string()),
+                sync: false,
+                ..Default::default()
+            },
+            FilterRule {
+                class: Some(DeviceClass::Major(0x04)),
+                keys: KeyFamilies::from_names(["classic"]).unwrap(),
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(filters.scope(&adapter, &headset), None);
+        assert_eq!(select(&filters, &adapter, &headset), None);
+
+        let headset = dual_device("Headset", 0x240404);
+        let selected = select(&filters, &adapter, &headset).unwrap();
+        assert!(selected.classic.is_some());
+        assert!(selected.le.is_none());
+        assert_eq!(selected.name, headset.name);
+
+        // No rule matches a phone, all keys sync
+        let phone = dual_device("Phone", 0x5a020c);
+        assert_eq!(select(&filters, &adapter, &phone), Some(phone.clone()));
+
+        // A device without a name or class never matches such criteria
+        let mut unnamed = dual_device("", 0);
+        unnamed.name = None;
+        unnamed.class = None;
+        assert_eq!(filters.scope(&adapter, &unnamed), Some(KeyFamilies::ALL));
+    }
+
+    #[test]
+    fn test_adapter_and_device_rules() {
+        let test_adapter = mac("00:11:22:33:44:55");
+        let other_adapter = mac("66:77:88:99:AA:BB");
+        let device = dual_device("Mouse", 0x002580);
+        let filters = Filters::new(vec![
+            FilterRule {
+                adapter: Some(test_adapter),
+                sync: false,
+                ..Default::default()
+            },
+            FilterRule {
+                device: Some(device.mac_address),
+                keys: KeyFamilies::from_names(["le", "csrk"]).unwrap(),
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(filters.scope(&test_adapter, &device), None);
+
+        let selected = select(&filters, &other_adapter, &device).unwrap();
+        assert!(selected.classic.is_none());
+        let le = selected.le.unwrap();
+        assert!(le.ltk.is_some());
+        assert!(le.irk.is_none());
+
+        // Nothing left to sync for a Classic-only device
+        let classic =
+            BluetoothDevice::classic(device.mac_address, key("0123456789ABCDEF0123456789ABCDEF"));
+        assert_eq!(select(&filters, &other_adapter, &classic), None);
+    }
+
+    #[test]
+    fn test_device_classes_and_key_families() {
+        assert_eq!("audio".parse(), Ok(DeviceClass::Major(0x04)));
+        assert!("speaker".parse::<DeviceClass>().is_err());
+        assert!(DeviceClass::Major(0x05).matches(0x002580));
+        assert!(!DeviceClass::Major(0x04).matches(0x002580));
+        assert!(DeviceClass::Exact(0x240404).matches(0x240404));
+        assert!(!DeviceClass::Exact(0x240404).matches(0x240408));
+
+        assert_eq!(
+            KeyFamilies::from_names(["classic", "le", "csrk", "irk"]),
+            Ok(KeyFamilies::ALL)
+        );
+        assert!(KeyFamilies::from_names(["ltk"]).is_err());
+        assert!(KeyFamilies::from_names([]).is_err());
+    }
+
+    #[test]
+    fn test_name_patterns() {
+        assert!(matches_pattern("lab *", "Lab Headset"));
+        assert!(matches_pattern("*phone*", "My Phone 12"));
+        assert!(matches_pattern("MX ?", "mx 3"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*b*c", "aXbYbc"));
+        assert!(!matches_pattern("lab *", "Lab"));
+        assert!(!matches_pattern("MX ?", "MX 10"));
+        assert!(!matches_pattern("phone", "Phone 2"));
+    }
+}
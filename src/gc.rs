@@ -0,0 +1,242 @@
+//! Retention policy for the shared config
+//!
+//! Removals only propagate through `forget`, so without collection
+//! bluevein.json keeps the keys of every device ever paired on any OS. Every
+//! installation stamps the adapters and devices it has paired whenever it
+//! writes the store; entries no installation has seen within the retention
+//! period are collected.
+
+use crate::config::BlueVeinConfig;
+use crate::types::MacAddress;
+use std::env;
+use std::fmt;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Default retention for devices and adapters, in days
+const DEFAULT_MAX_AGE_DAYS: u64 = 180;
+
+/// When entries of the shared config are collected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcPolicy {
+    /// Devices not seen on any installation for this many days are removed
+    pub device_max_age_days: u64,
+    /// Adapters not seen on any installation for this many days are removed
+    pub adapter_max_age_days: u64,
+    /// Whether the daemon prunes automatically on every write
+    pub automatic: bool,
+}
+
+impl Default for GcPolicy {
+    fn default() -> Self {
+        Self {
+            device_max_age_days: DEFAULT_MAX_AGE_DAYS,
+            adapter_max_age_days: DEFAULT_MAX_AGE_DAYS,
+            automatic: false,
+        }
+    }
+}
+
+impl GcPolicy {
+    /// Build the policy from the environment, keeping `base` for unset variables
+    ///
+    /// * `BLUEVEIN_GC_DEVICE_DAYS` - device retention (default 180)
+    /// * `BLUEVEIN_GC_ADAPTER_DAYS` - adapter retention (default 180)
+    /// * `BLUEVEIN_GC_AUTO` - set to `1` to prune automatically
+    pub fn from_env(base: Self) -> Self {
+        let days = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|days| *days > 0)
+                .unwrap_or(default)
+        };
+
+        Self {
+            device_max_age_days: days("BLUEVEIN_GC_DEVICE_DAYS", base.device_max_age_days),
+            adapter_max_age_days: days("BLUEVEIN_GC_ADAPTER_DAYS", base.adapter_max_age_days),
+            automatic: env::var("BLUEVEIN_GC_AUTO")
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(base.automatic),
+        }
+    }
+}
+
+/// Entry selected for collection with the time it was last seen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaleEntry {
+    pub adapter: MacAddress,
+    /// `None` for the adapter itself
+    pub device: Option<MacAddress>,
+    /// Days since an installation last saw the entry
+    pub age_days: u64,
+}
+
+/// Entries of the shared config a policy would remove
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcPlan {
+    /// Sorted by adapter, with the adapter itself before its devices
+    pub entries: Vec<StaleEntry>,
+}
+
+impl GcPlan {
+    /// Select the entries of `config` that `policy` collects at time `now`
+    ///
+    /// An adapter is only collected together with all of its devices, and
+    /// entries without a stamp are never collected.
+    pub fn compute(config: &BlueVeinConfig, policy: &GcPolicy, now: u64) -> Self {
+        let age_days = |seen: u64| now.saturating_sub(seen) / SECS_PER_DAY;
+        let mut entries = Vec::new();
+
+        for (adapter_mac, adapter) in &config.adapters {
+            let mut stale_devices: Vec<StaleEntry> = adapter
+                .devices
+                .keys()
+                .filter_map(|device_mac| {
+                    let seen = *adapter.devices_last_seen.get(device_mac)?;
+                    Some(StaleEntry {
+                        adapter: *adapter_mac,
+                        device: Some(*device_mac),
+                        age_days: age_days(seen),
+                    })
+                })
+                .filter(|entry| entry.age_days >= policy.device_max_age_days)
+                .collect();
+            stale_devices.sort_by_key(|entry| entry.device);
+
+            let adapter_stale = adapter
+                .last_seen
+                .map(|seen| age_days(seen) >= policy.adapter_max_age_days)
+                .unwrap_or(false);
+            if adapter_stale && stale_devices.len() == adapter.devices.len() {
+                entries.push(StaleEntry {
+                    adapter: *adapter_mac,
+                    device: None,
+                    age_days: adapter.last_seen.map(age_days).unwrap_or(0),
+                });
+            }
+            entries.extend(stale_devices);
+        }
+
+        entries.sort_by_key(|entry| (entry.adapter, entry.device));
+        Self { entries }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove the selected entries from `config`
+    pub fn apply(&self, config: &mut BlueVeinConfig) {
+        for entry in &self.entries {
+            match entry.device {
+                None => {
+                    config.adapters.remove(&entry.adapter);
+                }
+                Some(device_mac) => {
+                    if let Some(adapter) = config.adapters.get_mut(&entry.adapter) {
+                        adapter.devices.remove(&device_mac);
+                        adapter.devices_last_seen.remove(&device_mac);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for GcPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.entries.is_empty() {
+            return write!(f, "Nothing to collect");
+        }
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match entry.device {
+                None => write!(
+                    f,
+                    "  - adapter {} (last seen {} days ago)",
+                    entry.adapter, entry.age_days
+                )?,
+                Some(device) => write!(
+                    f,
+                    "  - device {} on adapter {} (last seen {} days ago)",
+                    device, entry.adapter, entry.age_days
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::BluetoothDevice;
+
+    const KEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+    const NOW: u64 = 1000 * SECS_PER_DAY;
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    /// Add a device to `config` last seen `days` ago on its adapter
+    fn add(config: &mut BlueVeinConfig, adapter: &str, device: &str, days: u64) {
+        config.update_device(
+            mac(adapter),
+            BluetoothDevice::classic(mac(device), KEY.parse().unwrap()),
+        );
+        let entry = config.adapters.get_mut(&mac(adapter)).unwrap();
+        entry
+            .devices_last_seen
+            .insert(mac(device), NOW - days * SECS_PER_DAY);
+        entry.last_seen = Some(entry.last_seen.unwrap_or(0).max(NOW - days * SECS_PER_DAY));
+    }
+
+    #[test]
+    fn test_collects_stale_devices_and_adapters() {
+        let mut config = BlueVeinConfig::new();
+        add(&mut config, "00:00:00:00:00:01", "AA:AA:AA:AA:AA:AA", 10);
+        add(&mut config, "00:00:00:00:00:01", "BB:BB:BB:BB:BB:BB", 400);
+        add(&mut config, "00:00:00:00:00:02", "CC:CC:CC:CC:CC:CC", 400);
+
+        let plan = GcPlan::compute(&config, &GcPolicy::default(), NOW);
+        let removed: Vec<_> = plan.entries.iter().map(|e| (e.adapter, e.device)).collect();
+        assert_eq!(
+            removed,
+            vec![
+                (mac("00:00:00:00:00:01"), Some(mac("BB:BB:BB:BB:BB:BB"))),
+                (mac("00:00:00:00:00:02"), None),
+                (mac("00:00:00:00:00:02"), Some(mac("CC:CC:CC:CC:CC:CC"))),
+            ]
+        );
+
+        plan.apply(&mut config);
+        assert_eq!(config.adapters.len(), 1);
+        assert!(config
+            .get_device(&mac("00:00:00:00:00:01"), &mac("AA:AA:AA:AA:AA:AA"))
+            .is_some());
+        assert!(GcPlan::compute(&config, &GcPolicy::default(), NOW).is_empty());
+    }
+
+    #[test]
+    fn test_keeps_unstamped_and_recently_seen_entries() {
+        let mut config = BlueVeinConfig::new();
+        add(&mut config, "00:00:00:00:00:01", "AA:AA:AA:AA:AA:AA", 400);
+        // Adapter seen recently through another device keeps the adapter
+        add(&mut config, "00:00:00:00:00:01", "BB:BB:BB:BB:BB:BB", 1);
+        // Never stamped, e.g. written by an older BlueVein
+        config.update_device(
+            mac("00:00:00:00:00:02"),
+            BluetoothDevice::classic(mac("CC:CC:CC:CC:CC:CC"), KEY.parse().unwrap()),
+        );
+
+        let plan = GcPlan::compute(&config, &GcPolicy::default(), NOW);
+        assert_eq!(plan.entries.len(), 1);
+        assert_eq!(plan.entries[0].device, Some(mac("AA:AA:AA:AA:AA:AA")));
+        assert_eq!(plan.entries[0].age_days, 400);
+    }
+}
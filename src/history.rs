@@ -0,0 +1,338 @@
+//! Bounded history of config generations
+//!
+//! Every write of bluevein.json also appends the new generation to
+//! `bluevein.history.json` next to it, so a bad key propagated by a sync can
+//! be rolled back to an earlier one.
+
+use crate::bluetooth::BluetoothDevice;
+use crate::config::BlueVeinConfig;
+use crate::error::BlueVeinError;
+use crate::inventory;
+use crate::types::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+/// Default number of generations kept
+pub const DEFAULT_HISTORY_SIZE: usize = 10;
+
+/// One stored generation of the shared config
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    pub generation: u64,
+    /// When the generation was written, seconds since the Unix epoch
+    pub written_at: u64,
+    /// Id of the installation that wrote it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written_by: Option<String>,
+    pub config: BlueVeinConfig,
+}
+
+/// Change of one device between two generations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceChange {
+    Added,
+    KeysChanged,
+    Removed,
+}
+
+/// What `bluevein rollback` restores
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollbackTarget {
+    /// The devices of every adapter as of a generation
+    Generation(u64),
+    /// The most recent earlier keys of one device
+    Device(MacAddress),
+}
+
+impl FromStr for RollbackTarget {
+    type Err = BlueVeinError;
+
+    /// Parse a generation number or a device MAC address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<u64>() {
+            Ok(generation) => Ok(RollbackTarget::Generation(generation)),
+            Err(_) => s.parse().map(RollbackTarget::Device),
+        }
+    }
+}
+
+/// Stored generations, oldest first
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+/// Number of generations to keep, from `BLUEVEIN_HISTORY_SIZE` (0 disables
+/// history), `default` if unset
+pub fn history_size_from_env(default: usize) -> usize {
+    env::var("BLUEVEIN_HISTORY_SIZE")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+impl History {
+    /// Append a generation, dropping the oldest ones beyond `limit`
+    pub fn push(&mut self, entry: HistoryEntry, limit: usize) {
+        self.entries
+            .retain(|existing| existing.generation != entry.generation);
+        self.entries.push(entry);
+        if self.entries.len() > limit {
+            let excess = self.entries.len() - limit;
+            self.entries.drain(..excess);
+        }
+    }
+
+    /// Find a stored generation
+    pub fn get(&self, generation: u64) -> Option<&HistoryEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.generation == generation)
+    }
+
+    /// Most recent stored version of a device whose keys differ from `current`
+    ///
+    /// Returns the generation and the device on each adapter it was paired
+    /// with. `None` if no stored generation has different keys.
+    pub fn previous_device(
+        &self,
+        current: &BlueVeinConfig,
+        device_mac: &MacAddress,
+    ) -> Option<(u64, Vec<(MacAddress, BluetoothDevice)>)> {
+        let current_devices = devices_of(current, device_mac);
+
+        self.entries.iter().rev().find_map(|entry| {
+            let devices = devices_of(&entry.config, device_mac);
+            let differs = !devices.is_empty()
+                && devices.iter().any(|(adapter, device)| {
+                    current_devices
+                        .iter()
+                        .find(|(a, _)| a == adapter)
+                        .map(|(_, current)| keys_differ(current, device))
+                        .unwrap_or(true)
+                });
+            differs.then_some((entry.generation, devices))
+        })
+    }
+
+    /// Changes between a generation and the one stored before it
+    pub fn changes(&self, index: usize) -> Vec<(MacAddress, MacAddress, DeviceChange)> {
+        let Some(entry) = self.entries.get(index) else {
+            return Vec::new();
+        };
+        let empty = BlueVeinConfig::new();
+        let previous = match index {
+            0 => &empty,
+            _ => &self.entries[index - 1].config,
+        };
+
+        let mut changes = Vec::new();
+        for (adapter_mac, adapter) in &entry.config.adapters {
+            for (device_mac, device) in &adapter.devices {
+                match previous.get_device(adapter_mac, device_mac) {
+                    None => changes.push((*adapter_mac, *device_mac, DeviceChange::Added)),
+                    Some(old) if keys_differ(old, device) => {
+                        changes.push((*adapter_mac, *device_mac, DeviceChange::KeysChanged))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        for (adapter_mac, adapter) in &previous.adapters {
+            for device_mac in adapter.devices.keys() {
+                if entry.config.get_device(adapter_mac, device_mac).is_none() {
+                    changes.push((*adapter_mac, *device_mac, DeviceChange::Removed));
+                }
+            }
+        }
+        changes.sort_by_key(|(adapter, device, _)| (*adapter, *device));
+        changes
+    }
+
+    /// Remove every stored key of a device, e.g. after it was forgotten
+    pub fn purge_device(&mut self, device_mac: &MacAddress) {
+        for entry in &mut self.entries {
+            for adapter in entry.config.adapters.values_mut() {
+                adapter.devices.remove(device_mac);
+                adapter.devices_last_seen.remove(device_mac);
+            }
+        }
+    }
+}
+
+impl fmt::Display for History {
+    /// Generations newest first, with the devices each one changed
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.entries.is_empty() {
+            return write!(f, "No config history stored yet");
+        }
+
+        let now = inventory::unix_now();
+        for (index, entry) in self.entries.iter().enumerate().rev() {
+            let writer = entry
+                .written_by
+                .as_ref()
+                .and_then(|id| entry.config.installations.get(id))
+                .map(|installation| installation.hostname.as_str())
+                .unwrap_or("unknown");
+            let devices: usize = entry
+                .config
+                .adapters
+                .values()
+                .map(|adapter| adapter.devices.len())
+                .sum();
+            writeln!(
+                f,
+                "generation {}: {} ago by {}, {} device(s){}",
+                entry.generation,
+                inventory::format_age(now.saturating_sub(entry.written_at)),
+                writer,
+                devices,
+                if index == 0 { " (oldest stored)" } else { "" }
+            )?;
+
+            // The oldest stored generation has nothing to compare against
+            if index == 0 {
+                continue;
+            }
+            for (adapter, device, change) in self.changes(index) {
+                let glyph = match change {
+                    DeviceChange::Added => "+",
+                    DeviceChange::KeysChanged => "○",
+                    DeviceChange::Removed => "-",
+                };
+                writeln!(f, "    {} {} on adapter {}", glyph, device, adapter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The device on every adapter of `config` it is paired with
+fn devices_of(
+    config: &BlueVeinConfig,
+    device_mac: &MacAddress,
+) -> Vec<(MacAddress, BluetoothDevice)> {
+    let mut devices: Vec<_> = config
+        .adapters
+        .iter()
+        .filter_map(|(adapter_mac, adapter)| {
+            adapter
+                .devices
+                .get(device_mac)
+                .map(|device| (*adapter_mac, device.clone()))
+        })
+        .collect();
+    devices.sort_by_key(|(adapter, _)| *adapter);
+    devices
+}
+
+fn keys_differ(a: &BluetoothDevice, b: &BluetoothDevice) -> bool {
+    a.classic != b.classic || a.le != b.le
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADAPTER: &str = "00:11:22:33:44:55";
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    fn generation(generation: u64, key: &str) -> HistoryEntry {
+        let mut config = BlueVeinConfig::new();
+        config.generation = generation;
+        config.update_device(
+            mac(ADAPTER),
+            BluetoothDevice::classic(mac(DEVICE), key.parse().unwrap()),
+        );
+        HistoryEntry {
+            generation,
+            written_at: generation * 100,
+            written_by: None,
+            config,
+        }
+    }
+
+    #[test]
+    fn test_push_keeps_last_generations() {
+        let mut history = History::default();
+        for n in 1..=5 {
+            history.push(generation(n, "0123456789ABCDEF0123456789ABCDEF"), 3);
+        }
+
+        let generations: Vec<u64> = history.entries.iter().map(|e| e.generation).collect();
+        assert_eq!(generations, vec![3, 4, 5]);
+        assert!(history.get(2).is_none());
+        assert!(history.get(4).is_some());
+    }
+
+    #[test]
+    fn test_previous_device_and_changes() {
+        let good = "0123456789ABCDEF0123456789ABCDEF";
+        let bad = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF";
+        let mut history = History::default();
+        history.push(generation(1, good), 10);
+        history.push(generation(2, good), 10);
+        history.push(generation(3, bad), 10);
+
+        let current = &history.entries[2].config;
+        let (found, devices) = history.previous_device(current, &mac(DEVICE)).unwrap();
+        assert_eq!(found, 2);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(
+            devices[0]
+                .1
+                .classic
+                .as_ref()
+                .unwrap()
+                .link_key
+                .to_hex()
+                .as_str(),
+            good
+        );
+
+        assert_eq!(
+            history.changes(0),
+            vec![(mac(ADAPTER), mac(DEVICE), DeviceChange::Added)]
+        );
+        assert!(history.changes(1).is_empty());
+        assert_eq!(
+            history.changes(2),
+            vec![(mac(ADAPTER), mac(DEVICE), DeviceChange::KeysChanged)]
+        );
+    }
+
+    #[test]
+    fn test_parse_rollback_target() {
+        assert_eq!(
+            "12".parse::<RollbackTarget>().unwrap(),
+            RollbackTarget::Generation(12)
+        );
+        assert_eq!(
+            DEVICE.parse::<RollbackTarget>().unwrap(),
+            RollbackTarget::Device(mac(DEVICE))
+        );
+        assert!("latest".parse::<RollbackTarget>().is_err());
+    }
+
+    #[test]
+    fn test_purge_device() {
+        let mut history = History::default();
+        history.push(generation(1, "0123456789ABCDEF0123456789ABCDEF"), 10);
+        history.purge_device(&mac(DEVICE));
+
+        assert!(history.entries[0]
+            .config
+            .get_device(&mac(ADAPTER), &mac(DEVICE))
+            .is_none());
+        assert!(history
+            .previous_device(&BlueVeinConfig::new(), &mac(DEVICE))
+            .is_none());
+    }
+}
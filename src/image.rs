@@ -0,0 +1,220 @@
+//! FAT32 image files used as the store
+//!
+//! A target naming a regular file is opened as an image instead of a block
+//! device, for VM-based dual-boot setups and for reproducing problems from a
+//! dump of someone's ESP. The image is either the ESP partition itself or,
+//! outside Windows, a whole-disk image whose GPT contains an ESP.
+
+#[cfg(not(target_os = "windows"))]
+use fat32_raw::fat32::volume::read_bpb;
+use fat32_raw::Fat32Volume;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const SECTOR_SIZE: u64 = 512;
+
+/// GPT type GUID of an EFI System Partition, in on-disk byte order
+const ESP_TYPE_GUID: [u8; 16] = [
+    0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b,
+];
+
+/// What an image file contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageLayout {
+    /// A FAT32 filesystem starting at the first byte
+    Partition,
+    /// A GPT disk whose ESP starts at this sector
+    Disk { esp_start_lba: u64 },
+}
+
+/// Whether a target names an image file rather than a device or mount point
+pub fn is_image(target: &str) -> bool {
+    fs::metadata(target).is_ok_and(|metadata| metadata.is_file())
+}
+
+fn read_sector(file: &mut File, lba: u64) -> std::io::Result<[u8; SECTOR_SIZE as usize]> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    file.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+    file.read_exact(&mut sector)?;
+    Ok(sector)
+}
+
+fn is_fat32_boot_sector(sector: &[u8]) -> bool {
+    sector[510..512] == [0x55, 0xaa] && &sector[0x52..0x5a] == b"FAT32   "
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().expect("4 bytes"))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().expect("8 bytes"))
+}
+
+/// Find where the filesystem starts in an image
+pub fn detect_layout(file: &mut File) -> Result<ImageLayout, String> {
+    let read_error = |e: std::io::Error| format!("cannot read image: {}", e);
+    if is_fat32_boot_sector(&read_sector(file, 0).map_err(read_error)?) {
+        return Ok(ImageLayout::Partition);
+    }
+
+    let header = read_sector(file, 1).map_err(read_error)?;
+    if &header[..8] != b"EFI PART" {
+        return Err("not a FAT32 image or GPT disk image".to_string());
+    }
+    let entries_lba = u64_at(&header, 72);
+    let entry_count = u32_at(&header, 80) as u64;
+    let entry_size = u32_at(&header, 84) as u64;
+    if !(128..=SECTOR_SIZE).contains(&entry_size) || entry_count > 1024 {
+        return Err("GPT header has an invalid partition table".to_string());
+    }
+
+    let mut entries = vec![0u8; (entry_count * entry_size) as usize];
+    file.seek(SeekFrom::Start(entries_lba * SECTOR_SIZE))
+        .and_then(|_| file.read_exact(&mut entries))
+        .map_err(read_error)?;
+    let esp_start_lba = entries
+        .chunks_exact(entry_size as usize)
+        .find(|entry| entry[..16] == ESP_TYPE_GUID)
+        .map(|entry| u64_at(entry, 32))
+        .ok_or("disk image has no EFI System Partition")?;
+
+    if !is_fat32_boot_sector(&read_sector(file, esp_start_lba).map_err(read_error)?) {
+        return Err("EFI System Partition in the disk image is not FAT32".to_string());
+    }
+    Ok(ImageLayout::Disk { esp_start_lba })
+}
+
+/// Open the FAT32 filesystem of an image file
+pub fn open(path: &Path) -> Result<Fat32Volume, String> {
+    let mut file = File::open(path).map_err(|e| format!("cannot open image: {}", e))?;
+    match detect_layout(&mut file)? {
+        ImageLayout::Partition => Fat32Volume::open_esp(path.to_str())
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| "image could not be opened".to_string()),
+        ImageLayout::Disk { esp_start_lba } => open_disk(path, &mut file, esp_start_lba),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn open_disk(path: &Path, file: &mut File, esp_start_lba: u64) -> Result<Fat32Volume, String> {
+    let params = read_bpb(file, esp_start_lba * SECTOR_SIZE).map_err(|e| e.to_string())?;
+    if params.bytes_per_sector as u64 != SECTOR_SIZE {
+        return Err(format!(
+            "unsupported sector size {} in disk image",
+            params.bytes_per_sector
+        ));
+    }
+    Fat32Volume::open(
+        false,
+        &path.to_string_lossy(),
+        esp_start_lba,
+        params.bytes_per_sector,
+        params.sectors_per_cluster as u32,
+        params.reserved_sectors as u32,
+        params.num_fats as u32,
+        params.sectors_per_fat,
+        params.root_cluster,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// fat32-raw routes writes to a volume it did not open itself through the
+/// system's ESP, so disk images are refused rather than risking it
+#[cfg(target_os = "windows")]
+fn open_disk(_path: &Path, _file: &mut File, esp_start_lba: u64) -> Result<Fat32Volume, String> {
+    Err(format!(
+        "whole-disk images are not supported on Windows; extract the ESP starting at sector {} into its own image",
+        esp_start_lba
+    ))
+}
+
+/// Empty FAT32 filesystem of `sectors` 512-byte sectors, one sector per cluster
+#[cfg(test)]
+pub fn format_fat32(sectors: u32) -> Vec<u8> {
+    const RESERVED: u32 = 32;
+    let sectors_per_fat = (sectors * 4).div_ceil(SECTOR_SIZE as u32);
+    let mut image = vec![0u8; sectors as usize * SECTOR_SIZE as usize];
+
+    let boot = &mut image[..SECTOR_SIZE as usize];
+    boot[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[0x0b..0x0d].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[0x0d] = 1;
+    boot[0x0e..0x10].copy_from_slice(&(RESERVED as u16).to_le_bytes());
+    boot[0x10] = 2;
+    boot[0x15] = 0xf8;
+    boot[0x20..0x24].copy_from_slice(&sectors.to_le_bytes());
+    boot[0x24..0x28].copy_from_slice(&sectors_per_fat.to_le_bytes());
+    boot[0x2c..0x30].copy_from_slice(&2u32.to_le_bytes());
+    boot[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+    boot[0x42] = 0x29;
+    boot[0x52..0x5a].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+    // Media descriptor, reserved entry and end of the root directory chain
+    for fat in 0..2 {
+        let start = ((RESERVED + fat * sectors_per_fat) as u64 * SECTOR_SIZE) as usize;
+        for (index, entry) in [0x0fff_fff8u32, 0x0fff_ffff, 0x0fff_ffff]
+            .iter()
+            .enumerate()
+        {
+            image[start + index * 4..start + index * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+    image
+}
+
+/// GPT disk image with `partition` as its ESP, starting at sector 2048
+#[cfg(test)]
+pub fn gpt_disk(partition: &[u8]) -> Vec<u8> {
+    const ESP_START: u64 = 2048;
+    let mut disk = vec![0u8; (ESP_START * SECTOR_SIZE) as usize];
+    let header = &mut disk[SECTOR_SIZE as usize..2 * SECTOR_SIZE as usize];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+
+    let entry = &mut disk[2 * SECTOR_SIZE as usize + 128..2 * SECTOR_SIZE as usize + 256];
+    entry[..16].copy_from_slice(&ESP_TYPE_GUID);
+    entry[32..40].copy_from_slice(&ESP_START.to_le_bytes());
+    let last_lba = ESP_START + partition.len() as u64 / SECTOR_SIZE - 1;
+    entry[40..48].copy_from_slice(&last_lba.to_le_bytes());
+
+    disk.extend_from_slice(partition);
+    disk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_layout() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let partition = format_fat32(4096);
+
+        let path = dir.join("esp.img");
+        fs::write(&path, &partition).unwrap();
+        assert!(is_image(&path.to_string_lossy()));
+        assert!(!is_image(&dir.to_string_lossy()));
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(detect_layout(&mut file), Ok(ImageLayout::Partition));
+
+        fs::write(&path, gpt_disk(&partition)).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert_eq!(
+            detect_layout(&mut file),
+            Ok(ImageLayout::Disk {
+                esp_start_lba: 2048
+            })
+        );
+
+        fs::write(&path, vec![0u8; 4096]).unwrap();
+        let mut file = File::open(&path).unwrap();
+        assert!(detect_layout(&mut file).is_err());
+    }
+}
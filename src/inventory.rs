@@ -0,0 +1,330 @@
+//! Inventory of the installations taking part in the sync
+//!
+//! Every BlueVein install records itself in the `installations` section of
+//! bluevein.json whenever it writes the store, so any side can tell which
+//! operating systems participate, what version they run, when they last
+//! synced and which shared devices they are missing.
+
+use crate::config::{BlueVeinConfig, KnownFields, UnknownFields};
+use crate::error::BlueVeinError;
+use crate::snapshot::Snapshot;
+use crate::types::MacAddress;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Installs that have not synced for this long are reported as stale
+pub const STALE_AFTER_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// What one installation last reported about itself
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Installation {
+    /// Operating system ("linux", "windows")
+    pub os: String,
+    pub hostname: String,
+    /// BlueVein version that last wrote this entry
+    pub version: String,
+    /// Last write to the store, seconds since the Unix epoch
+    ///
+    /// Only advanced every [`STAMP_RESOLUTION_SECS`](crate::config::STAMP_RESOLUTION_SECS)
+    /// while nothing else changes.
+    pub last_sync: u64,
+    /// Adapters seen by this install and the devices paired on each
+    #[serde(default)]
+    pub adapters: BTreeMap<MacAddress, Vec<MacAddress>>,
+    #[serde(flatten)]
+    pub extra: UnknownFields,
+}
+
+impl KnownFields for Installation {
+    fn clear_unknown_fields(&mut self) {
+        self.extra.clear();
+    }
+}
+
+/// Identity of the installation this process runs on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalInstallation {
+    /// Stable per-install id derived from the OS machine id
+    pub id: String,
+    pub hostname: String,
+}
+
+impl LocalInstallation {
+    /// Detect the identity of this installation
+    ///
+    /// The id is derived from `/etc/machine-id` on Linux and `MachineGuid` on
+    /// Windows. It is hashed, since the raw machine id should not be exposed
+    /// on a partition every OS can read.
+    pub fn detect() -> Result<Self, BlueVeinError> {
+        let machine_id = machine_id()?;
+        let digest = Sha256::new()
+            .chain_update(b"bluevein-installation:")
+            .chain_update(machine_id.trim().as_bytes())
+            .finalize();
+
+        Ok(Self {
+            id: hex::encode(&digest[..16]),
+            hostname: hostname().unwrap_or_else(|| "unknown".to_string()),
+        })
+    }
+
+    /// Build the inventory entry for this install from its current Bluetooth state
+    pub fn record(&self, snapshot: &Snapshot, now: u64) -> Installation {
+        Installation {
+            os: std::env::consts::OS.to_string(),
+            hostname: self.hostname.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            last_sync: now,
+            adapters: snapshot
+                .adapters()
+                .map(|(adapter, devices)| (*adapter, devices.copied().collect()))
+                .collect(),
+            extra: UnknownFields::default(),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn machine_id() -> Result<String, BlueVeinError> {
+    std::fs::read_to_string("/etc/machine-id")
+        .or_else(|_| std::fs::read_to_string("/var/lib/dbus/machine-id"))
+        .map_err(|e| BlueVeinError::io("Failed to read machine-id", e))
+}
+
+#[cfg(target_os = "windows")]
+fn machine_id() -> Result<String, BlueVeinError> {
+    use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_64KEY};
+    use winreg::RegKey;
+
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(
+            "SOFTWARE\\Microsoft\\Cryptography",
+            KEY_READ | KEY_WOW64_64KEY,
+        )
+        .and_then(|key| key.get_value::<String, _>("MachineGuid"))
+        .map_err(|e| BlueVeinError::io("Failed to read MachineGuid", e))
+}
+
+#[cfg(target_os = "linux")]
+fn hostname() -> Option<String> {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(target_os = "windows")]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// Current time as seconds since the Unix epoch
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Status of one installation relative to the shared config
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstallationStatus {
+    pub id: String,
+    pub installation: Installation,
+    /// Seconds since the install last synced
+    pub age_secs: u64,
+    pub stale: bool,
+    /// Whether this is the install the status was computed on
+    pub is_local: bool,
+    /// Devices in the shared config that are not paired on this install,
+    /// as (adapter, device), for adapters the install has seen
+    pub missing: Vec<(MacAddress, MacAddress)>,
+}
+
+/// Status of every installation recorded in the shared config
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryStatus {
+    /// Sorted by hostname, then id
+    pub installations: Vec<InstallationStatus>,
+}
+
+impl InventoryStatus {
+    /// Compute the status of all installations at time `now`
+    pub fn from_config(config: &BlueVeinConfig, local_id: Option<&str>, now: u64) -> Self {
+        let mut installations: Vec<InstallationStatus> = config
+            .installations
+            .iter()
+            .map(|(id, installation)| {
+                let age_secs = now.saturating_sub(installation.last_sync);
+                let mut missing = Vec::new();
+                for (adapter, paired) in &installation.adapters {
+                    if let Some(devices) = config.get_adapter_devices(adapter) {
+                        for device in devices.keys() {
+                            if !paired.contains(device) {
+                                missing.push((*adapter, *device));
+                            }
+                        }
+                    }
+                }
+                missing.sort();
+
+                InstallationStatus {
+                    id: id.clone(),
+                    installation: installation.clone(),
+                    age_secs,
+                    stale: age_secs > STALE_AFTER_SECS,
+                    is_local: local_id == Some(id.as_str()),
+                    missing,
+                }
+            })
+            .collect();
+
+        installations.sort_by(|a, b| {
+            (&a.installation.hostname, &a.id).cmp(&(&b.installation.hostname, &b.id))
+        });
+        Self { installations }
+    }
+
+    /// Installations that have not synced within [`STALE_AFTER_SECS`]
+    #[allow(dead_code)]
+    pub fn stale(&self) -> impl Iterator<Item = &InstallationStatus> {
+        self.installations.iter().filter(|status| status.stale)
+    }
+}
+
+/// Format a duration in seconds as a short human-readable age
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / (60 * 60)),
+        s => format!("{}d", s / (24 * 60 * 60)),
+    }
+}
+
+impl fmt::Display for InventoryStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.installations.is_empty() {
+            return write!(f, "No installations recorded in bluevein.json yet");
+        }
+
+        writeln!(f, "Installations ({}):", self.installations.len())?;
+        for status in &self.installations {
+            let installation = &status.installation;
+            writeln!(
+                f,
+                "  {} {} ({}, {}) v{}, last sync {} ago, {} adapter(s){}{}",
+                if status.stale { "✗" } else { "✓" },
+                installation.hostname,
+                installation.os,
+                &status.id[..status.id.len().min(8)],
+                installation.version,
+                format_age(status.age_secs),
+                installation.adapters.len(),
+                if status.is_local {
+                    " [this system]"
+                } else {
+                    ""
+                },
+                if status.stale { " - STALE" } else { "" },
+            )?;
+            for (adapter, device) in &status.missing {
+                writeln!(
+                    f,
+                    "      ○ missing device {} on adapter {}",
+                    device, adapter
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::BluetoothDevice;
+
+    const ADAPTER: &str = "00:11:22:33:44:55";
+    const KEY: &str = "0123456789ABCDEF0123456789ABCDEF";
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    fn installation(hostname: &str, last_sync: u64, devices: &[&str]) -> Installation {
+        Installation {
+            os: "linux".to_string(),
+            hostname: hostname.to_string(),
+            version: "0.1.0".to_string(),
+            last_sync,
+            adapters: [(mac(ADAPTER), devices.iter().map(|d| mac(d)).collect())]
+                .into_iter()
+                .collect(),
+            extra: UnknownFields::default(),
+        }
+    }
+
+    #[test]
+    fn test_status_reports_stale_and_missing() {
+        let now = 100 * 24 * 60 * 60;
+        let mut config = BlueVeinConfig::new();
+        for device in ["AA:AA:AA:AA:AA:AA", "BB:BB:BB:BB:BB:BB"] {
+            config.update_device(
+                mac(ADAPTER),
+                BluetoothDevice::classic(mac(device), KEY.parse().unwrap()),
+            );
+        }
+        config.record_installation(
+            "linux-id".to_string(),
+            installation(
+                "arch",
+                now - 60,
+                &["AA:AA:AA:AA:AA:AA", "BB:BB:BB:BB:BB:BB"],
+            ),
+        );
+        config.record_installation(
+            "windows-id".to_string(),
+            installation(
+                "desktop",
+                now - STALE_AFTER_SECS - 1,
+                &["AA:AA:AA:AA:AA:AA"],
+            ),
+        );
+
+        let status = InventoryStatus::from_config(&config, Some("linux-id"), now);
+        let [arch, desktop] = &status.installations[..] else {
+            panic!("expected two installations");
+        };
+
+        assert!(arch.is_local && !arch.stale && arch.missing.is_empty());
+        assert!(!desktop.is_local && desktop.stale);
+        assert_eq!(
+            desktop.missing,
+            vec![(mac(ADAPTER), mac("BB:BB:BB:BB:BB:BB"))]
+        );
+        assert_eq!(status.stale().count(), 1);
+
+        let text = status.to_string();
+        assert!(text.contains("[this system]"));
+        assert!(text.contains("STALE"));
+        assert!(text.contains("missing device BB:BB:BB:BB:BB:BB"));
+    }
+
+    #[test]
+    fn test_installations_round_trip() {
+        let mut config = BlueVeinConfig::new();
+        config.record_installation(
+            "id".to_string(),
+            installation("arch", 42, &["AA:AA:AA:AA:AA:AA"]),
+        );
+
+        let parsed = BlueVeinConfig::from_json(&config.to_json().unwrap()).unwrap();
+        assert_eq!(parsed.installations, config.installations);
+    }
+}
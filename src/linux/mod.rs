@@ -2,11 +2,23 @@ mod bluetooth;
 mod monitor;
 
 use crate::efi::EfiContext;
+use crate::history::RollbackTarget;
 use crate::log;
+use crate::settings::{self, ReloadRequests, Settings};
 use crate::sync::SyncManager;
+use crate::types::MacAddress;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 pub fn run() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    // Helper started by the service itself, its stdout carries file data
+    if args.get(1).map(String::as_str) == Some(crate::esp::PRIVATE_IO_COMMAND) {
+        std::process::exit(crate::esp::run_private_io(&args[2..]));
+    }
+
     log!("[BlueVein] Starting Linux service...");
 
     // Check if we have root permissions
@@ -16,26 +28,170 @@ pub fn run() -> Result<(), Box<dyn Error>> {
         return Err("Requires root privileges".into());
     }
 
+    if let Some(command) = args.get(1) {
+        return match command.as_str() {
+            "status" => run_status(),
+            "forget" if args.len() == 3 => run_forget(&args[2]),
+            "gc" if args.len() == 2 => run_gc(false),
+            "gc" if args.len() == 3 && args[2] == "--dry-run" => run_gc(true),
+            "history" => run_history(),
+            "rollback" if args.len() == 3 => run_rollback(&args[2]),
+            "backups" if args.len() == 2 || args[2] == "list" => run_backups(),
+            "restore" if args.len() == 3 => run_restore(&args[2]),
+            _ => {
+                log!("BlueVein - Bluetooth Synchronization Service");
+                log!("\nUsage:");
+                log!("  bluevein         - Run the sync service");
+                log!("  bluevein status  - Show installations taking part in the sync");
+                log!("  bluevein forget <mac> - Remove a device from every installation");
+                log!("  bluevein gc [--dry-run] - Remove devices and adapters not seen for a long time");
+                log!("  bluevein history - Show stored config generations");
+                log!("  bluevein rollback <generation|mac> - Restore an earlier config or device keys");
+                log!("  bluevein backups list - Show local backups of Bluetooth keys");
+                log!("  bluevein restore <id> - Restore local Bluetooth keys from a backup");
+                Ok(())
+            }
+        };
+    }
+
     // Create tokio runtime and run async code
     let runtime = tokio::runtime::Runtime::new()?;
     runtime.block_on(run_service())
 }
 
 async fn run_service() -> Result<(), Box<dyn Error>> {
-    let bt_manager = Box::new(bluetooth::LinuxBluetoothManager::new()?);
+    // Signal handlers go first: until they are installed, SIGHUP terminates
+    // the daemon, and the initial sync can take a while
+    let (running, reload) = install_signal_handlers();
 
-    let efi_context = EfiContext::from_env();
-    efi_context.validate()?;
-
-    let mut sync_manager = SyncManager::new(bt_manager, efi_context);
+    let mut sync_manager = create_sync_manager()?;
 
     log!("[BlueVein] Performing initial bidirectional sync...");
     // Use bidirectional sync to properly merge EFI and system state
-    if let Err(e) = sync_manager.sync_bidirectional() {
-        log!("[BlueVein] Warning: Initial sync failed: {}", e);
+    match sync_manager.sync_bidirectional() {
+        Ok(report) if report.has_failures() => {
+            log!(
+                "[BlueVein] Warning: Initial sync completed with failures: {}",
+                report
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log!("[BlueVein] Warning: Initial sync failed: {}", e);
+        }
     }
 
     // Start monitoring Bluetooth changes
     log!("[BlueVein] Starting Bluetooth monitoring...");
-    monitor::monitor_bluetooth_changes(sync_manager).await
+    monitor::monitor_bluetooth_changes(sync_manager, running, reload).await
+}
+
+/// Stop on Ctrl+C and SIGTERM and reload the settings on SIGHUP
+///
+/// Returns the flag cleared on shutdown and the reload requests seen from
+/// now on, so a reload requested during the initial sync is not lost.
+fn install_signal_handlers() -> (Arc<AtomicBool>, ReloadRequests) {
+    // Stop on Ctrl+C and SIGTERM, writing queued changes before exiting
+    let running = Arc::new(AtomicBool::new(true));
+    let running_clone = running.clone();
+    ctrlc::set_handler(move || {
+        log!("\n[BlueVein] Shutting down...");
+        running_clone.store(false, Ordering::Relaxed);
+    })
+    .ok();
+
+    // Reload the settings file on SIGHUP, installed after ctrlc which would otherwise stop on it
+    let reload = SigAction::new(
+        SigHandler::Handler(on_sighup),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only increments an atomic counter
+    if let Err(e) = unsafe { sigaction(Signal::SIGHUP, &reload) } {
+        log!(
+            "[BlueVein] Warning: Cannot reload settings on SIGHUP: {}",
+            e
+        );
+    }
+
+    (running, ReloadRequests::new())
+}
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    settings::request_reload();
+}
+
+fn create_sync_manager() -> Result<SyncManager, Box<dyn Error>> {
+    let settings = Settings::load()?;
+    let bt_manager = Box::new(bluetooth::LinuxBluetoothManager::new()?);
+
+    let efi_context = EfiContext::from_settings(&settings.store);
+    efi_context.validate()?;
+
+    Ok(SyncManager::new(bt_manager, efi_context, &settings))
+}
+
+/// Print the health of every ESP and the installation inventory
+fn run_status() -> Result<(), Box<dyn Error>> {
+    let sync_manager = create_sync_manager()?;
+    println!("ESPs:");
+    for target in sync_manager.store_status() {
+        println!("  {}", target);
+    }
+    println!("{}", sync_manager.inventory_status()?);
+    Ok(())
+}
+
+/// Collect stale devices and adapters from the shared config
+fn run_gc(dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let plan = create_sync_manager()?.collect_garbage(dry_run)?;
+    if plan.is_empty() {
+        println!("{}", plan);
+    } else if dry_run {
+        println!("Would remove:\n{}", plan);
+    } else {
+        println!("Removed:\n{}", plan);
+    }
+    Ok(())
+}
+
+/// Print the stored config generations
+fn run_history() -> Result<(), Box<dyn Error>> {
+    print!("{}", create_sync_manager()?.history()?);
+    Ok(())
+}
+
+/// Restore an earlier generation or device keys and apply them to this system
+fn run_rollback(target: &str) -> Result<(), Box<dyn Error>> {
+    let target: RollbackTarget = target.parse()?;
+    let report = create_sync_manager()?.rollback(target)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
+}
+
+/// Print local backups of Bluetooth keys
+fn run_backups() -> Result<(), Box<dyn Error>> {
+    let backups = create_sync_manager()?.backups()?;
+    if backups.is_empty() {
+        println!("No local backups");
+    }
+    for backup in backups {
+        println!("{}", backup);
+    }
+    Ok(())
+}
+
+/// Restore local Bluetooth keys from a backup
+fn run_restore(id: &str) -> Result<(), Box<dyn Error>> {
+    let report = create_sync_manager()?.restore_backup(id)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
+}
+
+/// Forget a device on every installation
+fn run_forget(device: &str) -> Result<(), Box<dyn Error>> {
+    let device_mac: MacAddress = device.parse()?;
+    let report = create_sync_manager()?.forget_device(&device_mac)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
 }
@@ -1,15 +1,28 @@
 use crate::log;
+use crate::settings::ReloadRequests;
+use crate::snapshot::Snapshot;
 use crate::sync::SyncManager;
+use crate::types::MacAddress;
 use inotify::{Inotify, WatchMask};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
-use std::path::PathBuf;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 const BLUETOOTH_LIB_PATH: &str = "/var/lib/bluetooth";
 
+/// How long to wait for events before retrying queued changes and checking for shutdown
+const TICK_MS: u16 = 1000;
+
 pub async fn monitor_bluetooth_changes(
     mut sync_manager: SyncManager,
+    running: Arc<AtomicBool>,
+    mut reload: ReloadRequests,
 ) -> Result<(), Box<dyn Error>> {
     let mut inotify = Inotify::init()?;
     let mut watches = HashMap::new();
@@ -25,142 +38,134 @@ pub async fn monitor_bluetooth_changes(
     if let Ok(entries) = fs::read_dir(BLUETOOTH_LIB_PATH) {
         for entry in entries.flatten() {
             if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
-                let path = entry.path();
                 let name = entry.file_name().to_string_lossy().to_string();
 
                 // Check if it looks like an adapter (MAC address)
-                if name.contains(':') && name.len() == 17 {
-                    // Watch adapter directory
-                    if let Ok(watch) = inotify.watches().add(
-                        &path,
-                        WatchMask::CREATE
-                            | WatchMask::DELETE
-                            | WatchMask::MODIFY
-                            | WatchMask::MOVED_TO
-                            | WatchMask::MOVED_FROM,
-                    ) {
-                        watches.insert(watch, path.clone());
-                        log!("[BlueVein] Watching adapter: {}", name);
-                    }
-
-                    // Watch device directories inside adapter
-                    add_device_watches(&mut inotify, &mut watches, &path);
+                if looks_like_mac(&name) {
+                    add_adapter_watch(&mut inotify, &mut watches, &entry.path());
+                    log!("[BlueVein] Watching adapter: {}", name);
                 }
             }
         }
     }
 
+    // Read initial state
+    // A failure here is not fatal: starting from an empty state, the
+    // devices found once reading works again are handled as new pairings
+    let mut previous_state = sync_manager.snapshot().unwrap_or_else(|e| {
+        log!("[BlueVein] Error reading initial state: {}", e);
+        Snapshot::default()
+    });
     log!(
-        "[BlueVein] Monitoring {} for Bluetooth changes...",
-        BLUETOOTH_LIB_PATH
+        "[BlueVein] Monitoring {} for Bluetooth changes ({} adapters, {} devices)...",
+        BLUETOOTH_LIB_PATH,
+        previous_state.adapter_count(),
+        previous_state.device_count()
     );
 
     let mut buffer = [0; 4096];
-    loop {
-        let events = inotify.read_events_blocking(&mut buffer)?;
+    while running.load(Ordering::Relaxed) {
+        if reload.take() {
+            sync_manager.reload_settings();
+        }
+        sync_manager.retry_pending(false);
+
+        let mut fds = [PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::from(TICK_MS)) {
+            Ok(0) | Err(Errno::EINTR) => continue,
+            Ok(_) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let events = match inotify.read_events(&mut buffer) {
+            Ok(events) => events,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut state_touched = false;
 
         for event in events {
-            if let Some(name) = event.name {
-                let name_str = name.to_string_lossy().to_string();
-
-                // Get the base path for this watch (clone to avoid borrow issues)
-                let base_path = watches.get(&event.wd).cloned();
-
-                if let Some(base_path) = base_path {
-                    let full_path = base_path.join(&name_str);
-
-                    // Check if this is an adapter directory in main path
-                    if base_path.to_str() == Some(BLUETOOTH_LIB_PATH) {
-                        if name_str.contains(':') && name_str.len() == 17 {
-                            if event.mask.contains(inotify::EventMask::CREATE)
-                                || event.mask.contains(inotify::EventMask::MOVED_TO)
-                            {
-                                // New adapter detected, add watch
-                                if let Ok(watch) = inotify.watches().add(
-                                    &full_path,
-                                    WatchMask::CREATE
-                                        | WatchMask::DELETE
-                                        | WatchMask::MODIFY
-                                        | WatchMask::MOVED_TO
-                                        | WatchMask::MOVED_FROM,
-                                ) {
-                                    watches.insert(watch, full_path.clone());
-                                    log!("[BlueVein] New adapter detected: {}", name_str);
-
-                                    // Watch devices in new adapter
-                                    add_device_watches(&mut inotify, &mut watches, &full_path);
-                                }
-                            }
-                        }
-                    } else if name_str == "info" {
-                        // This is an info file change - extract device and adapter MAC
-                        if let Some(device_mac) = base_path.file_name().and_then(|n| n.to_str()) {
-                            if let Some(adapter_path) = base_path.parent() {
-                                if let Some(adapter_mac) =
-                                    adapter_path.file_name().and_then(|n| n.to_str())
-                                {
-                                    if event.mask.contains(inotify::EventMask::MODIFY)
-                                        || event.mask.contains(inotify::EventMask::CLOSE_WRITE)
-                                    {
-                                        log!("[BlueVein] Info file updated for device {} on adapter {}", device_mac, adapter_mac);
-
-                                        // Check if pairing keys (Classic or LE) exist now
-                                        if has_pairing_keys(&full_path) {
-                                            log!("[BlueVein] Pairing keys detected, syncing...");
-                                            if let Err(e) = sync_manager
-                                                .handle_device_change(adapter_mac, device_mac)
-                                            {
-                                                log!("[BlueVein] Failed to sync device: {}", e);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        // This is a device change within an adapter directory
-                        if name_str.contains(':') && name_str.len() == 17 {
-                            let adapter_mac =
-                                base_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
-
-                            if event.mask.contains(inotify::EventMask::DELETE)
-                                || event.mask.contains(inotify::EventMask::MOVED_FROM)
-                            {
-                                // Device removed
-                                log!(
-                                    "[BlueVein] Device removal detected: {} on adapter {}",
-                                    name_str,
-                                    adapter_mac
-                                );
-                                if let Err(e) =
-                                    sync_manager.handle_device_removal(adapter_mac, &name_str)
-                                {
-                                    log!("[BlueVein] Failed to handle device removal: {}", e);
-                                }
-                            } else if event.mask.contains(inotify::EventMask::CREATE)
-                                || event.mask.contains(inotify::EventMask::MOVED_TO)
-                            {
-                                // New device directory created - add watch for info file
-                                log!(
-                                    "[BlueVein] New device directory detected: {} on adapter {}",
-                                    name_str,
-                                    adapter_mac
-                                );
-                                add_device_watches(&mut inotify, &mut watches, &base_path);
-                            }
-                        }
+            let Some(name) = event.name else { continue };
+            let name_str = name.to_string_lossy().to_string();
+
+            // Get the base path for this watch (clone to avoid borrow issues)
+            let Some(base_path) = watches.get(&event.wd).cloned() else {
+                continue;
+            };
+            let full_path = base_path.join(&name_str);
+            let created = event.mask.contains(inotify::EventMask::CREATE)
+                || event.mask.contains(inotify::EventMask::MOVED_TO);
+
+            if base_path.to_str() == Some(BLUETOOTH_LIB_PATH) {
+                // Adapter directory in main path
+                if looks_like_mac(&name_str) {
+                    if created {
+                        add_adapter_watch(&mut inotify, &mut watches, &full_path);
                     }
+                    state_touched = true;
+                }
+            } else if name_str == "info" {
+                // Info file of a device was written
+                state_touched = true;
+            } else if looks_like_mac(&name_str) {
+                // Device directory within an adapter directory
+                if created {
+                    add_device_watches(&mut inotify, &mut watches, &base_path);
                 }
+                state_touched = true;
+            }
+        }
+
+        if !state_touched {
+            continue;
+        }
+
+        // Diff against the previous snapshot, exactly like the Windows monitor
+        match sync_manager.snapshot() {
+            Ok(new_state) => {
+                let changes = previous_state.diff(&new_state);
+                sync_manager.handle_changes(&changes);
+                previous_state = new_state;
             }
+            Err(e) => log!("[BlueVein] Error reading new state: {}", e),
         }
     }
+
+    log!("[BlueVein] Monitoring stopped");
+    sync_manager.retry_pending(true);
+    Ok(())
+}
+
+/// Check if a directory name is a MAC address (XX:XX:XX:XX:XX:XX)
+fn looks_like_mac(name: &str) -> bool {
+    name.parse::<MacAddress>().is_ok()
+}
+
+/// Add a watch for an adapter directory and all of its device directories
+fn add_adapter_watch(
+    inotify: &mut Inotify,
+    watches: &mut HashMap<inotify::WatchDescriptor, PathBuf>,
+    adapter_path: &Path,
+) {
+    if let Ok(watch) = inotify.watches().add(
+        adapter_path,
+        WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MODIFY
+            | WatchMask::MOVED_TO
+            | WatchMask::MOVED_FROM,
+    ) {
+        watches.insert(watch, adapter_path.to_path_buf());
+    }
+
+    add_device_watches(inotify, watches, adapter_path);
 }
 
 /// Add watches for device directories and their info files
 fn add_device_watches(
     inotify: &mut Inotify,
     watches: &mut HashMap<inotify::WatchDescriptor, PathBuf>,
-    adapter_path: &PathBuf,
+    adapter_path: &Path,
 ) {
     if let Ok(entries) = fs::read_dir(adapter_path) {
         for entry in entries.flatten() {
@@ -169,7 +174,7 @@ fn add_device_watches(
                 let device_name = entry.file_name().to_string_lossy().to_string();
 
                 // Check if it looks like a device (MAC address)
-                if device_name.contains(':') && device_name.len() == 17 {
+                if looks_like_mac(&device_name) {
                     // Watch device directory for info file changes
                     if let Ok(watch) = inotify.watches().add(
                         &device_path,
@@ -182,44 +187,3 @@ fn add_device_watches(
         }
     }
 }
-
-/// Check if info file contains pairing keys (Classic LinkKey or LE keys)
-///
-/// This function detects both:
-/// - Classic Bluetooth: [LinkKey] section with Key=
-/// - Bluetooth LE: [LongTermKey], [PeripheralLongTermKey], or [IdentityResolvingKey]
-///
-/// Returns true if ANY pairing key is found, indicating the device has been paired.
-fn has_pairing_keys(info_path: &PathBuf) -> bool {
-    if let Ok(content) = fs::read_to_string(info_path) {
-        let lines: Vec<&str> = content.lines().collect();
-        let mut current_section = String::new();
-
-        for line in lines {
-            let trimmed = line.trim();
-
-            // Track current section
-            if trimmed.starts_with('[') && trimmed.ends_with(']') {
-                current_section = trimmed.to_string();
-            } else if trimmed.starts_with("Key=") {
-                // Check if we're in a pairing key section
-                match current_section.as_str() {
-                    "[LinkKey]"
-                    | "[LongTermKey]"
-                    | "[PeripheralLongTermKey]"
-                    | "[IdentityResolvingKey]"
-                    | "[SlaveLongTermKey]" => {
-                        let key_value = trimmed.strip_prefix("Key=").unwrap_or("");
-                        // Validate key is not empty and has valid hex format (32 chars = 128-bit)
-                        if key_value.len() == 32 && key_value.chars().all(|c| c.is_ascii_hexdigit())
-                        {
-                            return true;
-                        }
-                    }
-                    _ => {}
-                }
-            }
-        }
-    }
-    false
-}
@@ -0,0 +1,94 @@
+//! Process-level lock around writes to the shared config
+//!
+//! The daemon, its helper threads and CLI commands all read, modify and
+//! write bluevein.json. Writes are serialized through an exclusive lock on a
+//! local file; a write whose read went stale in the meantime is re-merged by
+//! the caller instead of overwriting the newer generation.
+
+use crate::error::BlueVeinError;
+use std::fs::{self, File, TryLockError};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+pub fn default_lock_path() -> PathBuf {
+    PathBuf::from("/run/bluevein.lock")
+}
+#[cfg(target_os = "windows")]
+pub fn default_lock_path() -> PathBuf {
+    crate::paths::data_file("bluevein.lock")
+}
+
+/// How long to wait for another writer before giving up
+pub const LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+const RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Exclusive lock on the store, released when dropped
+pub struct StoreLock {
+    file: File,
+}
+
+impl StoreLock {
+    /// Take the lock on `path`, waiting up to `timeout` for another holder
+    pub fn acquire_at(path: &Path, timeout: Duration) -> Result<Self, BlueVeinError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| BlueVeinError::io("Failed to create lock directory", e))?;
+        }
+        let file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|e| BlueVeinError::io(format!("Failed to open {}", path.display()), e))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Self { file }),
+                Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                    thread::sleep(RETRY_INTERVAL);
+                }
+                Err(TryLockError::WouldBlock) => {
+                    return Err(BlueVeinError::StoreBusy(format!(
+                        "{} is held by another BlueVein process",
+                        path.display()
+                    )));
+                }
+                Err(TryLockError::Error(e)) => {
+                    return Err(BlueVeinError::io(
+                        format!("Failed to lock {}", path.display()),
+                        e,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bluevein.lock");
+
+        let held = StoreLock::acquire_at(&path, Duration::ZERO).unwrap();
+        assert!(matches!(
+            StoreLock::acquire_at(&path, Duration::from_millis(100)),
+            Err(BlueVeinError::StoreBusy(_))
+        ));
+
+        drop(held);
+        assert!(StoreLock::acquire_at(&path, Duration::ZERO).is_ok());
+    }
+}
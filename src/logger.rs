@@ -1,37 +1,48 @@
 //! Cross-platform logging module
 //!
-//! On Windows: logs to C:\ProgramData\BlueVein\bluevein.log
+//! On Windows: logs to %ProgramData%\BlueVein\bluevein.log
 //! On Linux: logs to stdout (captured by systemd)
+//!
+//! The `[log] file` setting selects another log file, or adds one on Linux.
 
-#[cfg(target_os = "windows")]
-use std::fs::{create_dir_all, OpenOptions};
-#[cfg(target_os = "windows")]
+use std::fs::{create_dir_all, File, OpenOptions};
 use std::io::Write;
-#[cfg(target_os = "windows")]
-use std::path::PathBuf;
-#[cfg(target_os = "windows")]
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
 
+/// Log file used when the settings do not name one
 #[cfg(target_os = "windows")]
-static LOGGER: once_cell::sync::Lazy<Mutex<Option<std::fs::File>>> =
-    once_cell::sync::Lazy::new(|| Mutex::new(init_logger()));
+fn default_log_file() -> Option<PathBuf> {
+    Some(crate::paths::data_file("bluevein.log"))
+}
+#[cfg(target_os = "linux")]
+fn default_log_file() -> Option<PathBuf> {
+    None
+}
 
-#[cfg(target_os = "windows")]
-fn init_logger() -> Option<std::fs::File> {
-    // Use C:\ProgramData\BlueVein for logs (writable by SYSTEM)
-    let log_dir = PathBuf::from("C:\\ProgramData\\BlueVein");
+struct LogFile {
+    path: PathBuf,
+    file: File,
+}
 
-    if let Err(e) = create_dir_all(&log_dir) {
-        eprintln!("[BlueVein] Failed to create log directory: {}", e);
-        return None;
-    }
+static LOGGER: once_cell::sync::Lazy<Mutex<Option<LogFile>>> =
+    once_cell::sync::Lazy::new(|| Mutex::new(default_log_file().and_then(|path| open(&path))));
 
-    let log_path = log_dir.join("bluevein.log");
+fn open(path: &Path) -> Option<LogFile> {
+    if let Some(log_dir) = path.parent() {
+        if let Err(e) = create_dir_all(log_dir) {
+            eprintln!("[BlueVein] Failed to create log directory: {}", e);
+            return None;
+        }
+    }
 
-    match OpenOptions::new().create(true).append(true).open(&log_path) {
+    match OpenOptions::new().create(true).append(true).open(path) {
         Ok(file) => {
-            eprintln!("[BlueVein] Logging to: {:?}", log_path);
-            Some(file)
+            eprintln!("[BlueVein] Logging to: {:?}", path);
+            Some(LogFile {
+                path: path.to_path_buf(),
+                file,
+            })
         }
         Err(e) => {
             eprintln!("[BlueVein] Failed to open log file: {}", e);
@@ -40,6 +51,25 @@ fn init_logger() -> Option<std::fs::File> {
     }
 }
 
+/// Log to `path` from now on, or to the platform default if None
+pub fn set_file(path: Option<&Path>) {
+    let path = path.map(Path::to_path_buf).or_else(default_log_file);
+    if let Ok(mut logger) = LOGGER.lock() {
+        if logger.as_ref().map(|log_file| &log_file.path) != path.as_ref() {
+            *logger = path.as_deref().and_then(open);
+        }
+    }
+}
+
+fn write_to_file(log_line: &str) {
+    if let Ok(mut logger) = LOGGER.lock() {
+        if let Some(ref mut log_file) = *logger {
+            let _ = writeln!(log_file.file, "{}", log_line);
+            let _ = log_file.file.flush();
+        }
+    }
+}
+
 /// Log a message (cross-platform)
 #[cfg(target_os = "windows")]
 pub fn log(msg: &str) {
@@ -49,19 +79,36 @@ pub fn log(msg: &str) {
     // Print to console (for standalone mode)
     println!("{}", log_line);
 
-    // Write to file
-    if let Ok(mut logger) = LOGGER.lock() {
-        if let Some(ref mut file) = *logger {
-            let _ = writeln!(file, "{}", log_line);
-            let _ = file.flush();
-        }
-    }
+    write_to_file(&log_line);
 }
 
 #[cfg(target_os = "linux")]
 pub fn log(msg: &str) {
     // On Linux, just print to stdout (systemd will capture it)
     println!("{}", msg);
+
+    write_to_file(&format!("[{}] {}", local_time(), msg));
+}
+
+/// Current local time as `YYYY-MM-DD HH:MM:SS`
+#[cfg(target_os = "linux")]
+fn local_time() -> String {
+    // SAFETY: `time` accepts a null pointer and `localtime_r` only writes to `tm`
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
 }
 
 /// Convenience macro for formatted logging
@@ -1,12 +1,31 @@
+mod backup;
 mod bluetooth;
 mod config;
+mod crypto;
 mod efi;
+mod efivar;
+mod error;
+mod filter;
+mod gc;
+mod history;
+mod image;
+mod inventory;
+mod lock;
 mod logger;
+mod outbox;
+#[cfg(target_os = "windows")]
+mod paths;
+mod report;
+mod settings;
+mod snapshot;
 mod sync;
+mod types;
 
 #[cfg(target_os = "windows")]
 mod windows;
 
+#[cfg(target_os = "linux")]
+mod esp;
 #[cfg(target_os = "linux")]
 mod linux;
 
@@ -0,0 +1,207 @@
+//! Durable queue of device changes not yet written to the ESP
+//!
+//! When a pairing cannot be written because the ESP is unmounted, busy or
+//! unreadable, the affected device is queued in a local file. The daemon
+//! retries with exponential backoff and once more on shutdown. Only MAC
+//! addresses are stored; the keys are read from the system on retry, so the
+//! latest pairing is what reaches the other OS.
+
+use crate::error::BlueVeinError;
+use crate::lock::{StoreLock, LOCK_TIMEOUT};
+use crate::log;
+use crate::types::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+#[cfg(target_os = "linux")]
+fn default_outbox_path() -> PathBuf {
+    PathBuf::from("/var/lib/bluevein/outbox.json")
+}
+#[cfg(target_os = "windows")]
+fn default_outbox_path() -> PathBuf {
+    crate::paths::data_file("outbox.json")
+}
+
+/// First retry delay, doubled after every failed attempt
+const INITIAL_BACKOFF_SECS: u64 = 5;
+const MAX_BACKOFF_SECS: u64 = 15 * 60;
+
+/// Device whose keys still have to be written to the ESP
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PendingChange {
+    pub adapter: MacAddress,
+    pub device: MacAddress,
+    /// When the change was first queued, seconds since the Unix epoch
+    pub queued_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+struct OutboxState {
+    entries: Vec<PendingChange>,
+    /// Failed attempts since the queue was last emptied
+    failures: u32,
+    /// Earliest time of the next retry, seconds since the Unix epoch
+    next_attempt: u64,
+}
+
+/// Delay before the next retry after `failures` failed attempts
+fn backoff_secs(failures: u32) -> u64 {
+    INITIAL_BACKOFF_SECS
+        .saturating_mul(1u64 << failures.min(16))
+        .min(MAX_BACKOFF_SECS)
+}
+
+/// Local queue file shared by every `SyncManager` on this system
+pub struct Outbox {
+    path: PathBuf,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new(default_outbox_path())
+    }
+}
+
+impl Outbox {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn load(&self) -> OutboxState {
+        match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+                log!(
+                    "[BlueVein] Warning: Discarding unreadable outbox {}: {}",
+                    self.path.display(),
+                    e
+                );
+                OutboxState::default()
+            }),
+            Err(_) => OutboxState::default(),
+        }
+    }
+
+    fn save(&self, state: &OutboxState) -> Result<(), BlueVeinError> {
+        if state.entries.is_empty() {
+            return match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(BlueVeinError::io("Failed to clear outbox", e))
+                }
+                _ => Ok(()),
+            };
+        }
+
+        let json = serde_json::to_vec_pretty(state).map_err(|e| {
+            BlueVeinError::BackendUnavailable(format!("Failed to serialize outbox: {}", e))
+        })?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| BlueVeinError::io("Failed to create outbox directory", e))?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, json)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| BlueVeinError::io("Failed to write outbox", e))
+    }
+
+    /// Load, modify and save the queue while holding its lock
+    fn update<T>(&self, f: impl FnOnce(&mut OutboxState) -> T) -> Result<T, BlueVeinError> {
+        let _lock = StoreLock::acquire_at(&self.path.with_extension("lock"), LOCK_TIMEOUT)?;
+        let mut state = self.load();
+        let result = f(&mut state);
+        self.save(&state)?;
+        Ok(result)
+    }
+
+    /// Queue a device, keeping the original time if it is already queued
+    pub fn push(
+        &self,
+        adapter: MacAddress,
+        device: MacAddress,
+        now: u64,
+    ) -> Result<(), BlueVeinError> {
+        self.update(|state| {
+            if state.entries.is_empty() {
+                state.failures = 0;
+                state.next_attempt = now + backoff_secs(0);
+            }
+            if !state
+                .entries
+                .iter()
+                .any(|entry| entry.adapter == adapter && entry.device == device)
+            {
+                state.entries.push(PendingChange {
+                    adapter,
+                    device,
+                    queued_at: now,
+                });
+            }
+        })
+    }
+
+    /// Queued changes if a retry is due at `now` (always with `force`)
+    pub fn due(&self, now: u64, force: bool) -> Vec<PendingChange> {
+        let state = self.load();
+        if force || now >= state.next_attempt {
+            state.entries
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Drop a change that was written or no longer applies
+    pub fn remove(&self, change: &PendingChange) -> Result<(), BlueVeinError> {
+        self.update(|state| {
+            state
+                .entries
+                .retain(|entry| entry.adapter != change.adapter || entry.device != change.device);
+        })
+    }
+
+    /// Push the next retry back after a failed attempt
+    pub fn record_failure(&self, now: u64) -> Result<(), BlueVeinError> {
+        self.update(|state| {
+            state.failures = state.failures.saturating_add(1);
+            state.next_attempt = now + backoff_secs(state.failures);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_queue_and_backoff() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let outbox = Outbox::new(dir.join("outbox.json"));
+        let (adapter, device) = (mac("00:11:22:33:44:55"), mac("AA:BB:CC:DD:EE:FF"));
+
+        outbox.push(adapter, device, 100).unwrap();
+        outbox.push(adapter, device, 200).unwrap();
+        assert!(outbox.due(100, false).is_empty());
+        assert_eq!(outbox.due(100, true).len(), 1);
+        assert_eq!(outbox.due(105, false)[0].queued_at, 100);
+
+        outbox.record_failure(105).unwrap();
+        assert!(outbox.due(114, false).is_empty());
+        assert_eq!(outbox.due(115, false).len(), 1);
+
+        outbox.remove(&outbox.due(115, false)[0]).unwrap();
+        assert!(outbox.due(u64::MAX, true).is_empty());
+        assert!(!dir.join("outbox.json").exists());
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff_secs(0), INITIAL_BACKOFF_SECS);
+        assert_eq!(backoff_secs(2), 4 * INITIAL_BACKOFF_SECS);
+        assert_eq!(backoff_secs(40), MAX_BACKOFF_SECS);
+    }
+}
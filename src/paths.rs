@@ -0,0 +1,41 @@
+//! Location of BlueVein's local files on Windows
+//!
+//! BlueVein's own files live in a BlueVein directory under the ProgramData
+//! known folder. The folder can be moved off `C:`, so it is resolved through
+//! the shell once instead of being hard-coded.
+
+use once_cell::sync::Lazy;
+use std::path::PathBuf;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::{FOLDERID_ProgramData, SHGetKnownFolderPath, KF_FLAG_DEFAULT};
+
+/// Used only if neither the known folder nor `%ProgramData%` can be resolved
+const FALLBACK_PROGRAM_DATA: &str = "C:\\ProgramData";
+
+static DATA_DIR: Lazy<PathBuf> = Lazy::new(|| {
+    known_program_data()
+        .or_else(|| {
+            std::env::var_os("ProgramData")
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_PROGRAM_DATA))
+        .join("BlueVein")
+});
+
+/// Path of `name` in BlueVein's data directory
+pub fn data_file(name: &str) -> PathBuf {
+    DATA_DIR.join(name)
+}
+
+fn known_program_data() -> Option<PathBuf> {
+    unsafe {
+        let path: PWSTR =
+            SHGetKnownFolderPath(&FOLDERID_ProgramData, KF_FLAG_DEFAULT, HANDLE::default()).ok()?;
+        let dir = path.to_string().ok().map(PathBuf::from);
+        CoTaskMemFree(Some(path.0 as *const _));
+        dir.filter(|dir| !dir.as_os_str().is_empty())
+    }
+}
@@ -0,0 +1,301 @@
+//! Structured outcome of a synchronization operation
+//!
+//! Every `SyncManager` operation returns a [`SyncReport`] listing what was
+//! done to each device, so the CLI, status files and hooks can consume real
+//! data instead of scraping log lines.
+
+use crate::types::MacAddress;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Kind of synchronization operation that produced a report
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Bidirectional,
+    FromEfi,
+    ToEfi,
+    DeviceChange,
+    DeviceRemoval,
+    EfiCheck,
+    Forget,
+    Rollback,
+    Restore,
+}
+
+impl Operation {
+    /// Short machine-readable name, as serialized
+    pub fn slug(&self) -> &'static str {
+        match self {
+            Operation::Bidirectional => "bidirectional",
+            Operation::FromEfi => "from_efi",
+            Operation::ToEfi => "to_efi",
+            Operation::DeviceChange => "device_change",
+            Operation::DeviceRemoval => "device_removal",
+            Operation::EfiCheck => "efi_check",
+            Operation::Forget => "forget",
+            Operation::Rollback => "rollback",
+            Operation::Restore => "restore",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Operation::Bidirectional => "bidirectional sync",
+            Operation::FromEfi => "sync from EFI",
+            Operation::ToEfi => "sync to EFI",
+            Operation::DeviceChange => "device change",
+            Operation::DeviceRemoval => "device removal",
+            Operation::EfiCheck => "EFI check",
+            Operation::Forget => "forget device",
+            Operation::Rollback => "rollback",
+            Operation::Restore => "restore from backup",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Why a device was left untouched
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Device is in the EFI config but not paired on this system
+    NotPairedLocally,
+    /// Removal is local only, the EFI entry is kept for the other OS
+    RemovalNotPropagated,
+    /// A filter rule keeps the device, or all of its keys, out of the sync
+    Filtered,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            SkipReason::NotPairedLocally => "not paired on this system",
+            SkipReason::RemovalNotPropagated => "removal is not propagated to EFI",
+            SkipReason::Filtered => "excluded by a filter rule",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Action taken for a single device
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum DeviceAction {
+    /// System keys were updated from the EFI config
+    Updated,
+    /// Device keys were added to (or refreshed in) the EFI config
+    Added,
+    /// Device was removed from this system because it was forgotten everywhere
+    Removed,
+    /// System and EFI already agreed
+    Unchanged,
+    /// Device was deliberately left untouched
+    Skipped { reason: SkipReason },
+    /// Applying the device failed
+    Failed { error: String },
+}
+
+/// Outcome for one device on one adapter
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct DeviceReport {
+    pub device: MacAddress,
+    #[serde(flatten)]
+    pub action: DeviceAction,
+}
+
+/// Outcomes for all devices touched on one adapter
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct AdapterReport {
+    pub adapter: MacAddress,
+    pub devices: Vec<DeviceReport>,
+}
+
+/// Structured result of a `SyncManager` operation
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub operation: Operation,
+    /// Per-adapter device outcomes, in the order they were recorded
+    pub adapters: Vec<AdapterReport>,
+    /// Bytes written to the EFI store (0 if nothing was written)
+    pub bytes_written: usize,
+    /// Id of the local backup taken before changing system keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    /// Start time as seconds since the Unix epoch
+    pub started_at: u64,
+    /// Wall-clock duration of the operation in milliseconds
+    pub duration_ms: u64,
+    #[serde(skip)]
+    started: Instant,
+}
+
+impl SyncReport {
+    /// Start a new report; the timer runs until [`SyncReport::finish`]
+    pub fn new(operation: Operation) -> Self {
+        Self {
+            operation,
+            adapters: Vec::new(),
+            bytes_written: 0,
+            backup: None,
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            duration_ms: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Record the action taken for a device
+    pub fn record(
+        &mut self,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+        action: DeviceAction,
+    ) {
+        let device = DeviceReport {
+            device: *device_mac,
+            action,
+        };
+
+        match self.adapters.iter_mut().find(|a| a.adapter == *adapter_mac) {
+            Some(adapter) => adapter.devices.push(device),
+            None => self.adapters.push(AdapterReport {
+                adapter: *adapter_mac,
+                devices: vec![device],
+            }),
+        }
+    }
+
+    /// Stop the timer and return the completed report
+    pub fn finish(mut self) -> Self {
+        self.duration_ms = self.started.elapsed().as_millis() as u64;
+        self
+    }
+
+    /// Iterate over all device outcomes as (adapter, device report) pairs
+    pub fn devices(&self) -> impl Iterator<Item = (&MacAddress, &DeviceReport)> {
+        self.adapters.iter().flat_map(|adapter| {
+            adapter
+                .devices
+                .iter()
+                .map(move |device| (&adapter.adapter, device))
+        })
+    }
+
+    /// Count device outcomes matching a predicate
+    fn count(&self, predicate: impl Fn(&DeviceAction) -> bool) -> usize {
+        self.devices().filter(|(_, d)| predicate(&d.action)).count()
+    }
+
+    /// Check whether any device failed
+    pub fn has_failures(&self) -> bool {
+        self.count(|a| matches!(a, DeviceAction::Failed { .. })) > 0
+    }
+
+    /// Serialize the report as JSON (for status files and hooks)
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} updated, {} added, {} removed, {} unchanged, {} skipped, {} failed, {} bytes written in {} ms",
+            self.operation,
+            self.count(|a| *a == DeviceAction::Updated),
+            self.count(|a| *a == DeviceAction::Added),
+            self.count(|a| *a == DeviceAction::Removed),
+            self.count(|a| *a == DeviceAction::Unchanged),
+            self.count(|a| matches!(a, DeviceAction::Skipped { .. })),
+            self.count(|a| matches!(a, DeviceAction::Failed { .. })),
+            self.bytes_written,
+            self.duration_ms
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_record_groups_by_adapter() {
+        let mut report = SyncReport::new(Operation::Bidirectional);
+        report.record(
+            &mac("00:11:22:33:44:55"),
+            &mac("AA:AA:AA:AA:AA:AA"),
+            DeviceAction::Updated,
+        );
+        report.record(
+            &mac("00:11:22:33:44:66"),
+            &mac("BB:BB:BB:BB:BB:BB"),
+            DeviceAction::Added,
+        );
+        report.record(
+            &mac("00:11:22:33:44:55"),
+            &mac("CC:CC:CC:CC:CC:CC"),
+            DeviceAction::Unchanged,
+        );
+
+        assert_eq!(report.adapters.len(), 2);
+        assert_eq!(report.adapters[0].devices.len(), 2);
+        assert_eq!(report.devices().count(), 3);
+        assert!(!report.has_failures());
+    }
+
+    #[test]
+    fn test_failures_and_summary() {
+        let mut report = SyncReport::new(Operation::DeviceChange);
+        report.record(
+            &mac("00:11:22:33:44:55"),
+            &mac("AA:AA:AA:AA:AA:AA"),
+            DeviceAction::Failed {
+                error: "permission denied".to_string(),
+            },
+        );
+        report.record(
+            &mac("00:11:22:33:44:55"),
+            &mac("BB:BB:BB:BB:BB:BB"),
+            DeviceAction::Skipped {
+                reason: SkipReason::NotPairedLocally,
+            },
+        );
+        report.bytes_written = 42;
+        let report = report.finish();
+
+        assert!(report.has_failures());
+        let summary = report.to_string();
+        assert!(summary.starts_with("device change:"));
+        assert!(summary.contains("1 skipped, 1 failed, 42 bytes written"));
+    }
+
+    #[test]
+    fn test_json_shape() {
+        let mut report = SyncReport::new(Operation::EfiCheck);
+        report.record(
+            &mac("00:11:22:33:44:55"),
+            &mac("AA:AA:AA:AA:AA:AA"),
+            DeviceAction::Skipped {
+                reason: SkipReason::NotPairedLocally,
+            },
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["operation"], "efi_check");
+        let device = &json["adapters"][0]["devices"][0];
+        assert_eq!(device["device"], "AA:AA:AA:AA:AA:AA");
+        assert_eq!(device["action"], "skipped");
+        assert_eq!(device["reason"], "not_paired_locally");
+    }
+}
@@ -0,0 +1,486 @@
+//! Daemon settings file
+//!
+//! `/etc/bluevein/config.toml` on Linux and
+//! `%ProgramData%\BlueVein\config.toml` on Windows, or the path in
+//! `BLUEVEIN_SETTINGS_FILE`. Every setting is optional and the environment
+//! variables that predate the file still take precedence over it. The file
+//! is validated at startup and read again on SIGHUP (Linux) or the service's
+//! `paramchange` control (Windows); an invalid file keeps the daemon on its
+//! current settings.
+
+use crate::efi::{ExposurePolicy, StorePaths};
+use crate::filter::{FilterRule, Filters};
+use crate::gc::GcPolicy;
+use crate::history::DEFAULT_HISTORY_SIZE;
+use crate::log;
+use crate::report::SyncReport;
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Settings file used when `BLUEVEIN_SETTINGS_FILE` is not set
+#[cfg(target_os = "linux")]
+fn default_settings_file() -> PathBuf {
+    PathBuf::from("/etc/bluevein/config.toml")
+}
+#[cfg(target_os = "windows")]
+fn default_settings_file() -> PathBuf {
+    crate::paths::data_file("config.toml")
+}
+
+/// How often Windows checks the ESP for changes made by another OS
+const DEFAULT_EFI_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where the config is stored
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StoreSettings {
+    /// Devices, mount points or `efivar`, empty to auto-detect
+    #[serde(deserialize_with = "one_or_many")]
+    pub targets: Vec<String>,
+    #[serde(rename = "config_path", deserialize_with = "store_paths")]
+    pub paths: StorePaths,
+    #[serde(rename = "exposed_esp", deserialize_with = "from_str")]
+    pub exposure: ExposurePolicy,
+}
+
+/// How the daemon synchronizes
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncSettings {
+    /// Interval of the Windows check for changes made by another OS
+    #[serde(deserialize_with = "seconds")]
+    pub efi_check_interval: Duration,
+    /// Leave devices that are in the config but not paired locally alone,
+    /// instead of pairing them from the stored keys
+    pub skip_missing_devices: bool,
+    /// Restart bluetoothd after changing keys so it picks them up (Linux)
+    pub restart_bluetooth: bool,
+    /// Number of config generations kept in the history (0 disables it)
+    pub history_size: usize,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            efi_check_interval: DEFAULT_EFI_CHECK_INTERVAL,
+            skip_missing_devices: true,
+            restart_bluetooth: true,
+            history_size: DEFAULT_HISTORY_SIZE,
+        }
+    }
+}
+
+/// Where the log goes besides stdout
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// Log file, the platform default (none on Linux) if unset
+    pub file: Option<PathBuf>,
+}
+
+/// Commands run after daemon operations
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    /// Run when an operation touched a device
+    pub on_sync: Option<String>,
+    /// Run when an operation failed for a device
+    pub on_failure: Option<String>,
+}
+
+/// Contents of the settings file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub store: StoreSettings,
+    pub sync: SyncSettings,
+    #[serde(deserialize_with = "gc_policy")]
+    pub gc: GcPolicy,
+    pub log: LogSettings,
+    pub hooks: Hooks,
+    /// Rules of the `[[filter]]` tables, in order
+    #[serde(rename = "filter", deserialize_with = "filters")]
+    pub filters: Filters,
+}
+
+impl Settings {
+    /// Path of the settings file
+    pub fn path() -> PathBuf {
+        env::var_os("BLUEVEIN_SETTINGS_FILE")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(default_settings_file)
+    }
+
+    /// Load the settings file, the defaults if it does not exist
+    pub fn load() -> Result<Self, String> {
+        Self::load_from(&Self::path())
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Parse and validate the contents of a settings file
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e: toml::de::Error| {
+            let message = e.message().trim_end();
+            match e.span() {
+                Some(span) => {
+                    let line = text[..span.start].matches('\n').count() + 1;
+                    format!("line {}: {}", line, message)
+                }
+                None => message.to_string(),
+            }
+        })
+    }
+}
+
+/// A string setting parsed with `FromStr`
+fn from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+fn store_paths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StorePaths, D::Error> {
+    StorePaths::new(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+/// A string, or an array of strings
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    struct OneOrMany;
+
+    impl<'de> Visitor<'de> for OneOrMany {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a string or an array of strings")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(vec![value.to_string()])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany)
+}
+
+/// A count of days or seconds that must be at least 1
+fn at_least_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match u64::deserialize(deserializer)? {
+        0 => Err(de::Error::invalid_value(
+            Unexpected::Unsigned(0),
+            &"an integer of at least 1",
+        )),
+        value => Ok(value),
+    }
+}
+
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    at_least_one(deserializer).map(Duration::from_secs)
+}
+
+/// The `[gc]` table
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GcTable {
+    #[serde(deserialize_with = "at_least_one")]
+    device_days: u64,
+    #[serde(deserialize_with = "at_least_one")]
+    adapter_days: u64,
+    auto: bool,
+}
+
+impl Default for GcTable {
+    fn default() -> Self {
+        let policy = GcPolicy::default();
+        Self {
+            device_days: policy.device_max_age_days,
+            adapter_days: policy.adapter_max_age_days,
+            auto: policy.automatic,
+        }
+    }
+}
+
+fn gc_policy<'de, D: Deserializer<'de>>(deserializer: D) -> Result<GcPolicy, D::Error> {
+    let table = GcTable::deserialize(deserializer)?;
+    Ok(GcPolicy {
+        device_max_age_days: table.device_days,
+        adapter_max_age_days: table.adapter_days,
+        automatic: table.auto,
+    })
+}
+
+fn filters<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Filters, D::Error> {
+    Vec::<FilterRule>::deserialize(deserializer).map(Filters::new)
+}
+
+impl Hooks {
+    /// Run the hooks matching the outcome of a daemon operation
+    ///
+    /// The report is passed as JSON on stdin and the operation in
+    /// `BLUEVEIN_OPERATION`. Hooks run in the background through the shell;
+    /// a failing hook is only logged.
+    pub fn run(&self, report: &SyncReport) {
+        if report.devices().next().is_none() {
+            return;
+        }
+        let failure = self.on_failure.as_ref().filter(|_| report.has_failures());
+        let commands: Vec<&String> = self.on_sync.iter().chain(failure).collect();
+        if commands.is_empty() {
+            return;
+        }
+
+        let json = match report.to_json() {
+            Ok(json) => json,
+            Err(e) => {
+                log!("[BlueVein] ✗ Cannot serialize report for hooks: {}", e);
+                return;
+            }
+        };
+        for command in commands {
+            spawn_hook(command, report.operation.slug(), &json);
+        }
+    }
+}
+
+fn spawn_hook(command: &str, operation: &str, json: &str) {
+    #[cfg(target_os = "windows")]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    };
+    #[cfg(not(target_os = "windows"))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    };
+
+    let mut child = match shell
+        .env("BLUEVEIN_OPERATION", operation)
+        .stdin(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            log!("[BlueVein] ✗ Cannot run hook {:?}: {}", command, e);
+            return;
+        }
+    };
+    let command = command.to_string();
+    let json = json.to_string();
+    thread::spawn(move || {
+        if let Some(mut stdin) = child.stdin.take() {
+            // A hook that does not read its input is fine
+            let _ = stdin.write_all(json.as_bytes());
+        }
+        match child.wait() {
+            Ok(status) if !status.success() => {
+                log!("[BlueVein] ✗ Hook {:?} failed: {}", command, status)
+            }
+            Ok(_) => {}
+            Err(e) => log!("[BlueVein] ✗ Hook {:?} failed: {}", command, e),
+        }
+    });
+}
+
+/// Number of reload requests since the start
+static RELOAD_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Ask every [`ReloadRequests`] to reload the settings; safe in a signal handler
+pub fn request_reload() {
+    RELOAD_REQUESTS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Reload requests seen by one consumer, e.g. a monitor loop
+pub struct ReloadRequests {
+    seen: usize,
+}
+
+impl ReloadRequests {
+    pub fn new() -> Self {
+        Self {
+            seen: RELOAD_REQUESTS.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Whether a reload was requested since the last call
+    pub fn take(&mut self) -> bool {
+        let requests = RELOAD_REQUESTS.load(Ordering::SeqCst);
+        let requested = requests != self.seen;
+        self.seen = requests;
+        requested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{DeviceClass, KeyFamilies};
+
+    #[test]
+    fn test_parse_settings() {
+        let settings = Settings::parse(
+            "\
+# BlueVein settings
+[store]
+targets = [
+    \"/boot/efi\",  # primary
+    'efivar',
+]
+config_path = \"/EFI/BlueVein/config.json\"
+exposed_esp = \"warn\"
+
+[sync]
+efi_check_interval = 1_0
+skip_missing_devices = false
+history_size = 0
+
+[gc]
+auto = true
+
+[log]
+file = 'C:\\ProgramData\\BlueVein\\sync.log'
+
+[hooks]
+on_failure = \"notify-send \\\"BlueVein\\\" failed\"
+",
+        )
+        .unwrap();
+
+        assert_eq!(settings.store.targets, ["/boot/efi", "efivar"]);
+        assert_eq!(settings.store.paths.config, "EFI/BlueVein/config.json");
+        assert_eq!(settings.store.exposure, ExposurePolicy::Warn);
+        assert_eq!(settings.sync.efi_check_interval, Duration::from_secs(10));
+        assert!(!settings.sync.skip_missing_devices);
+        assert!(settings.sync.restart_bluetooth);
+        assert_eq!(settings.sync.history_size, 0);
+        assert!(settings.gc.automatic);
+        assert_eq!(
+            settings.gc.device_max_age_days,
+            GcPolicy::default().device_max_age_days
+        );
+        assert_eq!(
+            settings.log.file,
+            Some(PathBuf::from("C:\\ProgramData\\BlueVein\\sync.log"))
+        );
+        assert_eq!(settings.hooks.on_sync, None);
+        assert_eq!(
+            settings.hooks.on_failure.as_deref(),
+            Some("notify-send \"BlueVein\" failed")
+        );
+
+        assert_eq!(Settings::parse("").unwrap(), Settings::default());
+        assert_eq!(
+            Settings::parse("\r\n[sync]\r\n").unwrap(),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn test_invalid_settings_name_the_line() {
+        let error = |text: &str| Settings::parse(text).unwrap_err();
+        assert!(error("[sync]\n\nintervall = 5\n")
+            .starts_with("line 3: unknown field `intervall`, expected one of"));
+        assert_eq!(
+            error("[sync]\nefi_check_interval = 0\n"),
+            "line 2: invalid value: integer `0`, expected an integer of at least 1"
+        );
+        assert!(error("[gc]\nauto = \"yes\"\n").starts_with("line 2: invalid type: string"));
+        assert!(error("[store]\nexposed_esp = \"sometimes\"\n").starts_with("line 2: "));
+        assert!(error("[store]\nconfig_path = \"../x.json\"\n").starts_with("line 2: "));
+        assert!(error("[store]\ntargets = [\"/efi\", 1]\n").starts_with("line 2: "));
+        assert!(error("[log]\nfile = /var/log/bluevein.log\n").starts_with("line 2: "));
+        assert!(error("[log]\nfile = \"C:\\data\"\n").starts_with("line 2: "));
+        assert!(error("[sync\n").starts_with("line 1: "));
+        assert!(error("[gc]\nauto = true\nauto = false\n").starts_with("line 3: "));
+        assert!(error("[gc]\n[gc]\n").starts_with("line 2: "));
+    }
+
+    #[test]
+    fn test_parse_filters() {
+        let settings = Settings::parse(
+            "[sync]\nskip_missing_devices = false\n\n\
+             [[filter]]\nname = 'Lab *'\nsync = false\n\n\
+             [[filter]]\nadapter = \"00:11:22:33:44:55\"\nclass = 0x240404\n\
+             keys = [\"classic\", \"irk\"]\n\n\
+             [[filter]]\ndevice = \"AA-BB-CC-DD-EE-FF\"\nclass = \"audio\"\n",
+        )
+        .unwrap();
+        assert!(!settings.sync.skip_missing_devices);
+        assert_eq!(
+            settings.filters,
+            Filters::new(vec![
+                FilterRule {
+                    name: Some("Lab *".to_string()),
+                    sync: false,
+                    ..Default::default()
+                },
+                FilterRule {
+                    adapter: Some("00:11:22:33:44:55".parse().unwrap()),
+                    class: Some(DeviceClass::Exact(0x240404)),
+                    keys: KeyFamilies {
+                        classic: true,
+                        le: false,
+                        csrk: false,
+                        irk: true,
+                    },
+                    ..Default::default()
+                },
+                FilterRule {
+                    device: Some("AA:BB:CC:DD:EE:FF".parse().unwrap()),
+                    class: Some(DeviceClass::Major(0x04)),
+                    ..Default::default()
+                },
+            ])
+        );
+
+        let error = |text: &str| Settings::parse(text).unwrap_err();
+        assert!(error("[[filter]]\nsync = false\ncolour = 1\n")
+            .starts_with("line 3: unknown field `colour`, expected one of"));
+        assert!(error("[[filter]]\ndevice = \"AA:BB\"\n").starts_with("line 2: "));
+        assert!(error("[[filter]]\nclass = \"speaker\"\n").starts_with("line 2: "));
+        assert!(error("[[filter]]\nkeys = [\"ltk\"]\n").starts_with("line 2: "));
+        assert!(error("[[filter]]\nkeys = []\n").starts_with("line 2: "));
+        assert!(error("[filter]\n[[filter]]\n").starts_with("line 2: "));
+        assert!(error("[[filter]\n").starts_with("line 1: "));
+    }
+
+    #[test]
+    fn test_reload_requests() {
+        let mut first = ReloadRequests::new();
+        let mut second = ReloadRequests::new();
+        assert!(!first.take());
+
+        request_reload();
+        assert!(first.take());
+        assert!(!first.take());
+        assert!(second.take());
+    }
+}
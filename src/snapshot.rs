@@ -0,0 +1,395 @@
+//! Platform-independent Bluetooth state snapshots
+//!
+//! Both monitors capture a [`Snapshot`] through the [`BluetoothManager`] trait
+//! and diff consecutive snapshots into typed [`Change`]s, so Linux and Windows
+//! detect exactly the same set of events (Classic and LE alike).
+
+use crate::bluetooth::{BluetoothDevice, BluetoothManager};
+use crate::error::BlueVeinError;
+use crate::types::MacAddress;
+use std::collections::BTreeMap;
+
+/// A single difference between two snapshots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Adapter appeared (its devices are reported as `DeviceAdded` as well)
+    AdapterAdded { adapter: MacAddress },
+    /// Adapter disappeared
+    AdapterRemoved { adapter: MacAddress },
+    /// Device with pairing keys appeared on an adapter
+    DeviceAdded {
+        adapter: MacAddress,
+        device: MacAddress,
+    },
+    /// Classic or LE keys of an existing device changed
+    KeysChanged {
+        adapter: MacAddress,
+        device: MacAddress,
+    },
+    /// Device (or all of its keys) disappeared from an adapter
+    DeviceRemoved {
+        adapter: MacAddress,
+        device: MacAddress,
+    },
+}
+
+/// Point-in-time view of all adapters and their paired devices
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    /// Adapter MAC -> (Device MAC -> Device)
+    adapters: BTreeMap<MacAddress, BTreeMap<MacAddress, BluetoothDevice>>,
+}
+
+impl Snapshot {
+    /// Capture the current state through the platform Bluetooth manager
+    ///
+    /// Fails if any adapter cannot be read, so that a transient read error is
+    /// never mistaken for every device of that adapter being removed.
+    pub fn capture(bt_manager: &dyn BluetoothManager) -> Result<Self, BlueVeinError> {
+        let mut snapshot = Self::default();
+
+        for adapter_mac in bt_manager.get_adapters()? {
+            let devices = bt_manager
+                .get_devices(&adapter_mac)?
+                .into_iter()
+                .map(|device| (device.mac_address, device))
+                .collect();
+            snapshot.adapters.insert(adapter_mac, devices);
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Adapters in the snapshot with the MACs of their paired devices
+    pub fn adapters(
+        &self,
+    ) -> impl Iterator<Item = (&MacAddress, impl Iterator<Item = &MacAddress>)> {
+        self.adapters
+            .iter()
+            .map(|(adapter, devices)| (adapter, devices.keys()))
+    }
+
+    /// Number of adapters in the snapshot
+    pub fn adapter_count(&self) -> usize {
+        self.adapters.len()
+    }
+
+    /// Total number of paired devices across all adapters
+    pub fn device_count(&self) -> usize {
+        self.adapters.values().map(|devices| devices.len()).sum()
+    }
+
+    /// Compute the changes that turn `self` into `new`
+    ///
+    /// Changes are ordered by adapter, then device, so the result is stable.
+    pub fn diff(&self, new: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        for (adapter_mac, new_devices) in &new.adapters {
+            let old_devices = match self.adapters.get(adapter_mac) {
+                Some(devices) => devices,
+                None => {
+                    changes.push(Change::AdapterAdded {
+                        adapter: *adapter_mac,
+                    });
+                    for device_mac in new_devices.keys() {
+                        changes.push(Change::DeviceAdded {
+                            adapter: *adapter_mac,
+                            device: *device_mac,
+                        });
+                    }
+                    continue;
+                }
+            };
+
+            for (device_mac, new_device) in new_devices {
+                match old_devices.get(device_mac) {
+                    None => changes.push(Change::DeviceAdded {
+                        adapter: *adapter_mac,
+                        device: *device_mac,
+                    }),
+                    Some(old_device) if keys_differ(old_device, new_device) => {
+                        changes.push(Change::KeysChanged {
+                            adapter: *adapter_mac,
+                            device: *device_mac,
+                        })
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            for device_mac in old_devices.keys() {
+                if !new_devices.contains_key(device_mac) {
+                    changes.push(Change::DeviceRemoved {
+                        adapter: *adapter_mac,
+                        device: *device_mac,
+                    });
+                }
+            }
+        }
+
+        for adapter_mac in self.adapters.keys() {
+            if !new.adapters.contains_key(adapter_mac) {
+                changes.push(Change::AdapterRemoved {
+                    adapter: *adapter_mac,
+                });
+            }
+        }
+
+        changes
+    }
+}
+
+fn keys_differ(old: &BluetoothDevice, new: &BluetoothDevice) -> bool {
+    old.classic != new.classic || old.le != new.le
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::LeLongTermKey;
+    use crate::config::UnknownFields;
+    use std::collections::HashMap;
+
+    const ADAPTER: &str = "00:11:22:33:44:55";
+    const DEVICE: &str = "AA:BB:CC:DD:EE:FF";
+    const KEY_A: &str = "0123456789ABCDEF0123456789ABCDEF";
+    const KEY_B: &str = "FEDCBA9876543210FEDCBA9876543210";
+
+    /// In-memory Bluetooth manager used to drive `Snapshot::capture`
+    #[derive(Default)]
+    struct FakeManager {
+        adapters: HashMap<MacAddress, Vec<BluetoothDevice>>,
+    }
+
+    impl FakeManager {
+        fn with(mut self, adapter: &str, devices: Vec<BluetoothDevice>) -> Self {
+            self.adapters.insert(mac(adapter), devices);
+            self
+        }
+    }
+
+    impl BluetoothManager for FakeManager {
+        fn get_adapters(&self) -> Result<Vec<MacAddress>, BlueVeinError> {
+            Ok(self.adapters.keys().cloned().collect())
+        }
+
+        fn get_devices(
+            &self,
+            adapter_mac: &MacAddress,
+        ) -> Result<Vec<BluetoothDevice>, BlueVeinError> {
+            self.adapters
+                .get(adapter_mac)
+                .cloned()
+                .ok_or_else(|| BlueVeinError::BackendUnavailable("unknown adapter".to_string()))
+        }
+
+        fn get_device(
+            &self,
+            adapter_mac: &MacAddress,
+            device_mac: &MacAddress,
+        ) -> Result<BluetoothDevice, BlueVeinError> {
+            self.get_devices(adapter_mac)?
+                .into_iter()
+                .find(|d| d.mac_address == *device_mac)
+                .ok_or_else(|| BlueVeinError::device_not_found(adapter_mac, device_mac))
+        }
+
+        fn set_device(
+            &mut self,
+            _adapter_mac: &MacAddress,
+            _device: &BluetoothDevice,
+        ) -> Result<(), BlueVeinError> {
+            Ok(())
+        }
+
+        fn remove_device(
+            &mut self,
+            _adapter_mac: &MacAddress,
+            _device_mac: &MacAddress,
+        ) -> Result<(), BlueVeinError> {
+            Ok(())
+        }
+    }
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    fn classic(device: &str, key: &str) -> BluetoothDevice {
+        BluetoothDevice::classic(mac(device), key.parse().unwrap())
+    }
+
+    fn le(device: &str, key: &str) -> BluetoothDevice {
+        BluetoothDevice::le_with_ltk(
+            mac(device),
+            LeLongTermKey {
+                key: key.parse().unwrap(),
+                authenticated: Some(1),
+                enc_size: Some(16),
+                ediv: Some(0),
+                rand: Some(0),
+                extra: UnknownFields::default(),
+            },
+        )
+    }
+
+    fn capture(manager: &FakeManager) -> Snapshot {
+        Snapshot::capture(manager).unwrap()
+    }
+
+    #[test]
+    fn test_identical_snapshots_have_no_changes() {
+        let manager = FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]);
+        let snapshot = capture(&manager);
+        assert!(snapshot.diff(&snapshot.clone()).is_empty());
+        assert_eq!(snapshot.adapter_count(), 1);
+        assert_eq!(snapshot.device_count(), 1);
+    }
+
+    #[test]
+    fn test_device_added() {
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![]));
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![le(DEVICE, KEY_A)]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::DeviceAdded {
+                adapter: mac(ADAPTER),
+                device: mac(DEVICE),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_le_keys_changed() {
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![le(DEVICE, KEY_A)]));
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![le(DEVICE, KEY_B)]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::KeysChanged {
+                adapter: mac(ADAPTER),
+                device: mac(DEVICE),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_classic_keys_changed() {
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]));
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_B)]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::KeysChanged {
+                adapter: mac(ADAPTER),
+                device: mac(DEVICE),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_device_removed() {
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]));
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::DeviceRemoved {
+                adapter: mac(ADAPTER),
+                device: mac(DEVICE),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_adapter_added_reports_its_devices() {
+        let old = capture(&FakeManager::default());
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                Change::AdapterAdded {
+                    adapter: mac(ADAPTER),
+                },
+                Change::DeviceAdded {
+                    adapter: mac(ADAPTER),
+                    device: mac(DEVICE),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_adapter_removed() {
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]));
+        let new = capture(&FakeManager::default());
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::AdapterRemoved {
+                adapter: mac(ADAPTER),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dual_mode_device_gaining_le_keys() {
+        let mut dual = classic(DEVICE, KEY_A);
+        dual.le = le(DEVICE, KEY_B).le;
+
+        let old = capture(&FakeManager::default().with(ADAPTER, vec![classic(DEVICE, KEY_A)]));
+        let new = capture(&FakeManager::default().with(ADAPTER, vec![dual]));
+
+        assert_eq!(
+            old.diff(&new),
+            vec![Change::KeysChanged {
+                adapter: mac(ADAPTER),
+                device: mac(DEVICE),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_capture_propagates_adapter_read_errors() {
+        struct BrokenManager;
+
+        impl BluetoothManager for BrokenManager {
+            fn get_adapters(&self) -> Result<Vec<MacAddress>, BlueVeinError> {
+                Ok(vec![mac(ADAPTER)])
+            }
+
+            fn get_devices(&self, _: &MacAddress) -> Result<Vec<BluetoothDevice>, BlueVeinError> {
+                Err(BlueVeinError::BackendUnavailable("bluetoothd".to_string()))
+            }
+
+            fn get_device(
+                &self,
+                _: &MacAddress,
+                _: &MacAddress,
+            ) -> Result<BluetoothDevice, BlueVeinError> {
+                Err(BlueVeinError::BackendUnavailable("bluetoothd".to_string()))
+            }
+
+            fn set_device(
+                &mut self,
+                _: &MacAddress,
+                _: &BluetoothDevice,
+            ) -> Result<(), BlueVeinError> {
+                Ok(())
+            }
+
+            fn remove_device(
+                &mut self,
+                _: &MacAddress,
+                _: &MacAddress,
+            ) -> Result<(), BlueVeinError> {
+                Ok(())
+            }
+        }
+
+        assert!(Snapshot::capture(&BrokenManager).is_err());
+    }
+}
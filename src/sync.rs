@@ -1,40 +1,607 @@
+use crate::backup::{Backup, BackupStore, DeviceBackup};
 use crate::bluetooth::{BluetoothDevice, BluetoothManager, CsrkKey};
-use crate::config::BlueVeinConfig;
-use crate::efi::{self, EfiContext};
+use crate::config::{BlueVeinConfig, KnownFields};
+use crate::efi::{self, EfiContext, MirroredStore, TargetStatus};
+use crate::error::BlueVeinError;
+use crate::filter::{Filters, KeyFamilies};
+use crate::gc::{GcPlan, GcPolicy};
+use crate::history::{self, History, HistoryEntry, RollbackTarget};
+use crate::inventory::{self, InventoryStatus, LocalInstallation};
+use crate::lock::{self, StoreLock, LOCK_TIMEOUT};
 use crate::log;
-use std::collections::HashMap;
-use std::error::Error;
+use crate::logger;
+use crate::outbox::Outbox;
+use crate::report::{DeviceAction, Operation, SkipReason, SyncReport};
+use crate::settings::{Hooks, Settings, SyncSettings};
+use crate::snapshot::{Change, Snapshot};
+use crate::types::MacAddress;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 /// Synchronization manager
 pub struct SyncManager {
     bt_manager: Box<dyn BluetoothManager>,
     efi_context: EfiContext,
+    /// Sessions with every ESP the config is mirrored to, kept open across operations
+    store: RefCell<MirroredStore>,
+    /// Identity recorded in the shared inventory, `None` if it could not be detected
+    installation: Option<LocalInstallation>,
+    /// Retention policy for stale entries in the shared config
+    gc_policy: GcPolicy,
+    /// Number of config generations kept in the history (0 disables it)
+    history_size: usize,
+    /// Local store for backups of system keys taken before they are changed
+    backups: BackupStore,
+    /// Backup of the operation in progress, identified by `SyncReport::backup`
+    pending_backup: Option<Backup>,
+    /// Device changes that could not be written to the ESP yet
+    outbox: Outbox,
+    /// Local file whose lock serializes writes to the shared config
+    lock_path: PathBuf,
+    /// Daemon behavior from the settings file
+    sync_settings: SyncSettings,
+    hooks: Hooks,
+    /// Which devices and key families this installation syncs
+    filters: Filters,
 }
 
 impl SyncManager {
     /// Create a new sync manager
-    pub fn new(bt_manager: Box<dyn BluetoothManager>, efi_context: EfiContext) -> Self {
-        Self {
+    pub fn new(
+        bt_manager: Box<dyn BluetoothManager>,
+        efi_context: EfiContext,
+        settings: &Settings,
+    ) -> Self {
+        let mut sync_manager = Self {
             bt_manager,
+            store: RefCell::new(Self::open_store(&efi_context)),
             efi_context,
-        }
+            installation: Self::detect_installation(),
+            gc_policy: GcPolicy::default(),
+            history_size: 0,
+            backups: BackupStore::default(),
+            pending_backup: None,
+            outbox: Outbox::default(),
+            lock_path: lock::default_lock_path(),
+            sync_settings: SyncSettings::default(),
+            hooks: Hooks::default(),
+            filters: Filters::default(),
+        };
+        sync_manager.apply_settings(settings);
+        sync_manager
     }
 
     /// Create a new sync manager with default EFI device
     #[allow(dead_code)]
     pub fn with_default_efi(bt_manager: Box<dyn BluetoothManager>) -> Self {
-        Self {
-            bt_manager,
-            efi_context: EfiContext::default(),
+        Self::new(bt_manager, EfiContext::default(), &Settings::default())
+    }
+
+    fn open_store(efi_context: &EfiContext) -> MirroredStore {
+        MirroredStore::new(&efi_context.targets, &efi_context.paths)
+            .with_exposure_policy(efi_context.exposure)
+    }
+
+    /// Apply the settings that do not concern the store
+    fn apply_settings(&mut self, settings: &Settings) {
+        self.gc_policy = GcPolicy::from_env(settings.gc);
+        self.history_size = history::history_size_from_env(settings.sync.history_size);
+        self.sync_settings = settings.sync.clone();
+        self.hooks = settings.hooks.clone();
+        self.filters = settings.filters.clone();
+        self.bt_manager.apply_settings(&settings.sync);
+        logger::set_file(settings.log.file.as_deref());
+    }
+
+    /// Load the settings file again and apply it
+    ///
+    /// Queued changes and backups are kept, and so are the open store
+    /// sessions unless the store settings changed. An invalid file or store
+    /// is reported and the current settings are kept.
+    pub fn reload_settings(&mut self) {
+        log!("[BlueVein] Reloading {}", Settings::path().display());
+        let settings = match Settings::load() {
+            Ok(settings) => settings,
+            Err(e) => {
+                log!("[BlueVein] ✗ Keeping the current settings: {}", e);
+                return;
+            }
+        };
+        let efi_context = EfiContext::from_settings(&settings.store);
+        if let Err(e) = efi_context.validate() {
+            log!("[BlueVein] ✗ Keeping the current settings: {}", e);
+            return;
+        }
+
+        if efi_context.targets != self.efi_context.targets
+            || efi_context.paths != self.efi_context.paths
+            || efi_context.exposure != self.efi_context.exposure
+        {
+            log!("[BlueVein] Store changed to {}", efi_context.display_name());
+            self.store = RefCell::new(Self::open_store(&efi_context));
+        }
+        self.efi_context = efi_context;
+        self.apply_settings(&settings);
+        log!("[BlueVein] ✓ Settings reloaded");
+    }
+
+    /// How often to check the ESP for changes made by another OS
+    #[allow(dead_code)]
+    pub fn efi_check_interval(&self) -> std::time::Duration {
+        self.sync_settings.efi_check_interval
+    }
+
+    fn detect_installation() -> Option<LocalInstallation> {
+        match LocalInstallation::detect() {
+            Ok(installation) => Some(installation),
+            Err(e) => {
+                log!(
+                    "[BlueVein] Warning: Cannot identify this installation, it will not be listed in the inventory: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    /// Read the shared config from EFI, `None` if it does not exist yet
+    fn read_efi_config(&self) -> Result<Option<BlueVeinConfig>, BlueVeinError> {
+        let config = self
+            .store
+            .borrow_mut()
+            .read_config(self.efi_context.cipher.as_ref());
+        match config {
+            Ok(config) => Ok(Some(config)),
+            Err(efi::EfiError::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the shared config to EFI, returning the number of bytes written
+    ///
+    /// Refreshes this installation's inventory entry and last-seen stamps,
+    /// drops tombstones every installation has acknowledged and, if automatic
+    /// collection is enabled, stale entries before writing. The written
+    /// generation is appended to the history. Nothing is written, and 0 is
+    /// returned, if the result equals the stored config.
+    ///
+    /// Holds the store lock for the whole read-check-write cycle.
+    fn write_efi_config(&self, config: &mut BlueVeinConfig) -> Result<usize, BlueVeinError> {
+        let _lock = StoreLock::acquire_at(&self.lock_path, LOCK_TIMEOUT)?;
+
+        // Compare-and-swap on the generation: a config read before someone
+        // else wrote the store is merged onto their generation. A store that
+        // cannot be read aborts the write rather than being overwritten.
+        let base = self.store.borrow().cached_config(config.generation);
+        let stored = self.read_efi_config()?;
+        if let Some(stored) = stored.as_ref() {
+            if stored.generation != config.generation {
+                log!(
+                    "[BlueVein] EFI config changed since it was read (generation {} -> {}), merging",
+                    config.generation,
+                    stored.generation
+                );
+                *config = match base {
+                    Some(base) => config.rebase(&base, stored),
+                    None => config.merge_by_stamps(stored),
+                };
+            }
+        }
+
+        self.record_local_state(config);
+        config.prune_tombstones();
+        if self.gc_policy.automatic {
+            let plan = GcPlan::compute(config, &self.gc_policy, inventory::unix_now());
+            if !plan.is_empty() {
+                log!("[BlueVein] Collecting stale entries:\n{}", plan);
+                plan.apply(config);
+            }
+        }
+
+        // Writes that would change nothing are skipped to spare the FAT
+        // Unknown fields are inherited from the stored config, so only the
+        // fields this build understands can differ
+        if let Some(stored) = stored
+            .as_ref()
+            .filter(|stored| stored.eq_known_fields(config))
+        {
+            log!("[BlueVein] EFI config unchanged, skipping write");
+            self.store
+                .borrow_mut()
+                .sync_mirrors(stored, self.efi_context.cipher.as_ref());
+            return Ok(0);
+        }
+        config.generation += 1;
+
+        let bytes = self
+            .store
+            .borrow_mut()
+            .write_config(config, self.efi_context.cipher.as_ref())?;
+
+        if self.history_size > 0 {
+            let entry = HistoryEntry {
+                generation: config.generation,
+                written_at: inventory::unix_now(),
+                written_by: self.local_id(),
+                config: config.clone(),
+            };
+            self.update_history(|history| history.push(entry, self.history_size));
+        }
+        Ok(bytes)
+    }
+
+    /// Health of every ESP the config is mirrored to, refreshed by a read
+    pub fn store_status(&self) -> Vec<TargetStatus> {
+        let _ = self.read_efi_config();
+        self.store.borrow().status().cloned().collect()
+    }
+
+    /// Read the stored history
+    pub fn history(&self) -> Result<History, BlueVeinError> {
+        Ok(self
+            .store
+            .borrow_mut()
+            .read_history(self.efi_context.cipher.as_ref())?)
+    }
+
+    /// Modify the stored history; failures are logged, never fatal
+    fn update_history(&self, update: impl FnOnce(&mut History)) {
+        // An unreadable history is left alone rather than replaced
+        let mut history = match self.history() {
+            Ok(history) => history,
+            Err(e) => {
+                log!("[BlueVein] Warning: Not updating config history: {}", e);
+                return;
+            }
+        };
+        update(&mut history);
+
+        let written = self
+            .store
+            .borrow_mut()
+            .write_history(&history, self.efi_context.cipher.as_ref());
+        if let Err(e) = written {
+            log!("[BlueVein] Warning: Failed to write config history: {}", e);
+        }
+    }
+
+    /// Restore an earlier state from the history and apply it to this system
+    ///
+    /// A generation restores the devices of every adapter; a device restores
+    /// its most recent stored keys that differ from the current ones. The
+    /// restored state is written as a new generation, so a rollback can
+    /// itself be rolled back. Forgotten devices stay forgotten.
+    pub fn rollback(&mut self, target: RollbackTarget) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::Rollback);
+        let history = self.history()?;
+        let mut config = self.read_efi_config()?.unwrap_or_default();
+
+        match target {
+            RollbackTarget::Generation(generation) => {
+                let entry = history.get(generation).ok_or_else(|| {
+                    BlueVeinError::RollbackUnavailable(format!(
+                        "generation {} is not in the history",
+                        generation
+                    ))
+                })?;
+                log!("[BlueVein] Rolling back to generation {}...", generation);
+                config.adapters = entry.config.adapters.clone();
+                let tombstoned: Vec<MacAddress> = config.tombstones.keys().copied().collect();
+                for adapter in config.adapters.values_mut() {
+                    for device_mac in &tombstoned {
+                        adapter.devices.remove(device_mac);
+                        adapter.devices_last_seen.remove(device_mac);
+                    }
+                }
+            }
+            RollbackTarget::Device(device_mac) => {
+                let (generation, devices) = history
+                    .previous_device(&config, &device_mac)
+                    .ok_or_else(|| {
+                        BlueVeinError::RollbackUnavailable(format!(
+                            "no earlier keys of device {} in the history",
+                            device_mac
+                        ))
+                    })?;
+                log!(
+                    "[BlueVein] Rolling back device {} to its keys from generation {}...",
+                    device_mac,
+                    generation
+                );
+                for (adapter_mac, device) in devices {
+                    config.update_device(adapter_mac, device);
+                }
+            }
+        }
+
+        report.bytes_written = self.write_efi_config(&mut config)?;
+        self.apply_config_to_system(&config, &mut report)?;
+
+        let report = report.finish();
+        log!("[BlueVein] Rollback complete ({})", report);
+        Ok(report)
+    }
+
+    /// Update last-seen stamps and this installation's inventory entry from
+    /// the current Bluetooth state
+    fn record_local_state(&self, config: &mut BlueVeinConfig) {
+        let snapshot = match Snapshot::capture(self.bt_manager.as_ref()) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                log!("[BlueVein] Warning: Not updating inventory entry: {}", e);
+                return;
+            }
+        };
+
+        let now = inventory::unix_now();
+        config.mark_seen(&snapshot, now);
+        if let Some(installation) = &self.installation {
+            config
+                .record_installation(installation.id.clone(), installation.record(&snapshot, now));
+        }
+    }
+
+    /// Remove entries no installation has seen within the retention policy
+    ///
+    /// With `dry_run` the plan is only computed. Devices paired on this
+    /// system are stamped first, so they are never collected.
+    pub fn collect_garbage(&mut self, dry_run: bool) -> Result<GcPlan, BlueVeinError> {
+        let mut config = match self.read_efi_config()? {
+            Some(config) => config,
+            None => return Ok(GcPlan::default()),
+        };
+
+        self.record_local_state(&mut config);
+        let plan = GcPlan::compute(&config, &self.gc_policy, inventory::unix_now());
+        if !dry_run && !plan.is_empty() {
+            plan.apply(&mut config);
+            self.write_efi_config(&mut config)?;
+        }
+        Ok(plan)
+    }
+
+    /// Id of this installation in the shared inventory
+    fn local_id(&self) -> Option<String> {
+        self.installation.as_ref().map(|i| i.id.clone())
+    }
+
+    /// Remove devices forgotten on another installation and acknowledge their tombstones
+    ///
+    /// A tombstone is only acknowledged once the device is gone from every
+    /// local adapter, so a failed removal is retried on the next sync.
+    /// Returns whether the config was modified.
+    fn apply_tombstones(
+        &mut self,
+        config: &mut BlueVeinConfig,
+        report: &mut SyncReport,
+    ) -> Result<bool, BlueVeinError> {
+        let local_id = self.local_id();
+        let pending = config.pending_tombstones(local_id.as_deref());
+        if pending.is_empty() {
+            return Ok(false);
+        }
+
+        let adapters = self.bt_manager.get_adapters()?;
+        let mut modified = false;
+        for device_mac in pending {
+            let mut removed_everywhere = true;
+            for adapter_mac in &adapters {
+                let action = match self.bt_manager.get_device(adapter_mac, &device_mac) {
+                    Err(BlueVeinError::DeviceNotFound { .. }) => continue,
+                    Err(e) => DeviceAction::Failed {
+                        error: e.to_string(),
+                    },
+                    Ok(_) => {
+                        log!(
+                            "[BlueVein]   - Removing device {} forgotten on another installation",
+                            device_mac
+                        );
+                        self.remove_local_device(report, adapter_mac, &device_mac)
+                    }
+                };
+                removed_everywhere &= action == DeviceAction::Removed;
+                report.record(adapter_mac, &device_mac, action);
+            }
+
+            if let (true, Some(id)) = (removed_everywhere, &local_id) {
+                config.acknowledge_tombstone(&device_mac, id);
+                modified = true;
+            }
+        }
+        Ok(modified)
+    }
+
+    /// Save the current state of a device to the operation's local backup
+    ///
+    /// The first call of an operation starts a new backup and stores its id in
+    /// the report. System keys must not be changed if this fails.
+    fn back_up_device(
+        &mut self,
+        report: &mut SyncReport,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<(), BlueVeinError> {
+        let mut backup = match (self.pending_backup.take(), &report.backup) {
+            (Some(backup), Some(id)) if backup.id == *id => backup,
+            _ => {
+                let mut backup = Backup::new(report.operation, report.started_at);
+                self.backups.assign_id(&mut backup);
+                backup
+            }
+        };
+
+        if !backup.contains(adapter_mac, device_mac) {
+            let state = match self.bt_manager.get_device(adapter_mac, device_mac) {
+                Ok(device) => Some(device),
+                Err(BlueVeinError::DeviceNotFound { .. }) => None,
+                Err(e) => return Err(e),
+            };
+            backup.devices.push(DeviceBackup {
+                adapter: *adapter_mac,
+                device: *device_mac,
+                state,
+            });
+            self.backups
+                .save(&backup, self.efi_context.cipher.as_ref())?;
+            if report.backup.is_none() {
+                log!("[BlueVein] Backed up local keys to backup {}", backup.id);
+            }
+        }
+
+        report.backup = Some(backup.id.clone());
+        self.pending_backup = Some(backup);
+        Ok(())
+    }
+
+    /// Local backups, newest first
+    pub fn backups(&self) -> Result<Vec<Backup>, BlueVeinError> {
+        self.backups.list(self.efi_context.cipher.as_ref())
+    }
+
+    /// Restore system keys from a local backup without touching the ESP
+    ///
+    /// The state being replaced is itself backed up, so a restore can be undone.
+    pub fn restore_backup(&mut self, id: &str) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::Restore);
+        let backup = self.backups.load(id, self.efi_context.cipher.as_ref())?;
+        log!(
+            "[BlueVein] Restoring {} device(s) from backup {}...",
+            backup.devices.len(),
+            backup.id
+        );
+
+        for entry in &backup.devices {
+            let current = match self.bt_manager.get_device(&entry.adapter, &entry.device) {
+                Ok(device) => Some(device),
+                Err(BlueVeinError::DeviceNotFound { .. }) => None,
+                Err(e) => {
+                    report.record(
+                        &entry.adapter,
+                        &entry.device,
+                        DeviceAction::Failed {
+                            error: e.to_string(),
+                        },
+                    );
+                    continue;
+                }
+            };
+
+            let action = match (&entry.state, &current) {
+                (Some(saved), Some(current)) if !Self::devices_differ(saved, current) => {
+                    DeviceAction::Unchanged
+                }
+                (Some(saved), _) => self.apply_device(&mut report, &entry.adapter, saved),
+                (None, Some(_)) => {
+                    self.remove_local_device(&mut report, &entry.adapter, &entry.device)
+                }
+                (None, None) => DeviceAction::Unchanged,
+            };
+            report.record(&entry.adapter, &entry.device, action);
+        }
+
+        let report = report.finish();
+        log!("[BlueVein] Restore complete ({})", report);
+        Ok(report)
+    }
+
+    /// Remove a device from this system, logging and returning the outcome
+    fn remove_local_device(
+        &mut self,
+        report: &mut SyncReport,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> DeviceAction {
+        if let Err(e) = self.back_up_device(report, adapter_mac, device_mac) {
+            log!(
+                "[BlueVein]   ✗ Not removing device {}, backup failed: {}",
+                device_mac,
+                e
+            );
+            return DeviceAction::Failed {
+                error: format!("backup failed: {}", e),
+            };
+        }
+
+        match self.bt_manager.remove_device(adapter_mac, device_mac) {
+            Ok(_) => {
+                log!("[BlueVein]   ✓ Removed device {}", device_mac);
+                DeviceAction::Removed
+            }
+            Err(e) => {
+                log!(
+                    "[BlueVein]   ✗ Failed to remove device {}: {}",
+                    device_mac,
+                    e
+                );
+                DeviceAction::Failed {
+                    error: e.to_string(),
+                }
+            }
+        }
+    }
+
+    /// Forget a device on every installation
+    ///
+    /// Removes the device from all local adapters and from bluevein.json, and
+    /// leaves a tombstone so other installations remove it on their next sync.
+    pub fn forget_device(&mut self, device_mac: &MacAddress) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::Forget);
+        log!(
+            "[BlueVein] Forgetting device {} on every installation...",
+            device_mac
+        );
+
+        let mut config = self.read_efi_config()?.unwrap_or_default();
+
+        let mut removed_locally = true;
+        for adapter_mac in self.bt_manager.get_adapters()? {
+            match self.bt_manager.get_device(&adapter_mac, device_mac) {
+                Err(BlueVeinError::DeviceNotFound { .. }) => {}
+                Err(e) => return Err(e),
+                Ok(_) => {
+                    let action = self.remove_local_device(&mut report, &adapter_mac, device_mac);
+                    removed_locally &= action == DeviceAction::Removed;
+                    report.record(&adapter_mac, device_mac, action);
+                }
+            }
         }
+
+        // Only claim the local removal if it actually succeeded
+        let origin = self.local_id().filter(|_| removed_locally);
+        if !config.forget_device(*device_mac, origin.as_deref(), inventory::unix_now()) {
+            log!(
+                "[BlueVein]   ○ Device {} was not in the EFI config",
+                device_mac
+            );
+        }
+
+        report.bytes_written = self.write_efi_config(&mut config)?;
+        // The history must not keep the keys of a forgotten device either
+        self.update_history(|history| history.purge_device(device_mac));
+        log!(
+            "[BlueVein] ✓ Device {} forgotten, other installations will remove it on their next sync",
+            device_mac
+        );
+        Ok(report.finish())
+    }
+
+    /// Status of every installation recorded in the shared config
+    pub fn inventory_status(&self) -> Result<InventoryStatus, BlueVeinError> {
+        let config = self.read_efi_config()?.unwrap_or_default();
+        Ok(InventoryStatus::from_config(
+            &config,
+            self.installation.as_ref().map(|i| i.id.as_str()),
+            inventory::unix_now(),
+        ))
     }
 
     /// Compare two devices to see if their keys differ
     fn devices_differ(dev1: &BluetoothDevice, dev2: &BluetoothDevice) -> bool {
-        if dev1.classic != dev2.classic {
+        if !dev1.classic.eq_known_fields(&dev2.classic) {
             return true;
         }
-        if dev1.le != dev2.le {
+        if !dev1.le.eq_known_fields(&dev2.le) {
             return true;
         }
         false
@@ -70,6 +637,7 @@ impl SyncManager {
                         key: sys_csrk.key.clone(),
                         counter: sys_csrk.counter.max(efi_csrk.counter),
                         authenticated: sys_csrk.authenticated || efi_csrk.authenticated,
+                        extra: efi_csrk.extra.clone(),
                     })
                 }
                 (Some(_sys_csrk), Some(efi_csrk)) => {
@@ -95,6 +663,7 @@ impl SyncManager {
                     key: sys_csrk.key.clone(),
                     counter: sys_csrk.counter.max(efi_csrk.counter),
                     authenticated: sys_csrk.authenticated || efi_csrk.authenticated,
+                    extra: efi_csrk.extra.clone(),
                 }),
                 (Some(_sys_csrk), Some(efi_csrk)) => Some((*efi_csrk).clone()),
                 (Some(csrk), None) | (None, Some(csrk)) => Some((*csrk).clone()),
@@ -108,6 +677,48 @@ impl SyncManager {
         merged
     }
 
+    /// The keys of `device` this installation syncs on `adapter_mac`
+    ///
+    /// `base` is the copy the keys go to: the system's copy when applying keys
+    /// from EFI, the stored copy when sharing them. Key families that are not
+    /// synced keep their keys from `base`, and rules also see its name and
+    /// class, which entries written by older versions lack. Devices left out
+    /// are recorded as skipped.
+    fn select_device(
+        &self,
+        report: &mut SyncReport,
+        adapter_mac: &MacAddress,
+        device: &BluetoothDevice,
+        base: Option<&BluetoothDevice>,
+    ) -> Option<BluetoothDevice> {
+        let matched = match base {
+            Some(base) => base.merge_with(device),
+            None => device.clone(),
+        };
+        let scope = self.filters.scope(adapter_mac, &matched);
+        match scope.and_then(|keys| keys.select(device)) {
+            Some(selected) if scope == Some(KeyFamilies::ALL) => Some(selected),
+            Some(selected) => Some(match base {
+                Some(base) => base.merge_with(&selected),
+                None => selected,
+            }),
+            None => {
+                log!(
+                    "[BlueVein]   ○ Device {} excluded by a filter rule",
+                    device.mac_address
+                );
+                report.record(
+                    adapter_mac,
+                    &device.mac_address,
+                    DeviceAction::Skipped {
+                        reason: SkipReason::Filtered,
+                    },
+                );
+                None
+            }
+        }
+    }
+
     /// Perform intelligent bidirectional synchronization
     ///
     /// Algorithm:
@@ -115,33 +726,45 @@ impl SyncManager {
     /// 2. Read current Bluetooth state from system
     /// 3. MERGE strategy:
     ///    - For each device in EFI:
-    ///      * If device does NOT exist in system → SKIP (don't create)
+    ///      * If device does NOT exist in system → SKIP (don't create), unless
+    ///        `skip_missing_devices` is turned off in the settings
     ///      * If device exists but keys differ → UPDATE keys from EFI (merge both Classic and LE)
     ///    - For each device in system:
     ///      * If it's NOT in EFI → ADD to EFI (new pairing on this OS)
+    ///    - Devices and key families left out by the filter rules are not
+    ///      touched in either direction
     /// 4. Write updated bluevein.json back to EFI
-    pub fn sync_bidirectional(&mut self) -> Result<(), Box<dyn Error>> {
+    ///
+    /// Per-device failures do not abort the sync; they are recorded in the report.
+    pub fn sync_bidirectional(&mut self) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::Bidirectional);
         log!(
             "[BlueVein] Starting bidirectional synchronization (EFI device: {})...",
             self.efi_context.display_name()
         );
 
         // Read config from EFI (may not exist)
-        let efi_config = match efi::read_config_with_device(Some(&self.efi_context.device)) {
-            Ok(config) => {
+        let mut efi_config = match self.read_efi_config() {
+            Ok(Some(config)) => {
                 log!("[BlueVein] Found existing EFI config");
                 Some(config)
             }
-            Err(efi::EfiError::NotFound) => {
+            Ok(None) => {
                 log!("[BlueVein] No EFI config found, will create from system state");
                 None
             }
             Err(e) => {
                 log!("[BlueVein] Error reading EFI config: {}", e);
-                return Err(Box::new(e));
+                return Err(e);
             }
         };
 
+        // Remove devices forgotten elsewhere before reading the system state,
+        // so they are not added back to EFI
+        if let Some(config) = efi_config.as_mut() {
+            self.apply_tombstones(config, &mut report)?;
+        }
+
         // Read current system state
         let mut system_config = BlueVeinConfig::new();
         let adapters = match self.bt_manager.get_adapters() {
@@ -162,11 +785,11 @@ impl SyncManager {
                             devices.len(),
                             adapter_mac
                         );
-                        let mut device_map = HashMap::new();
+                        let mut device_map = BTreeMap::new();
                         for device in devices {
-                            device_map.insert(device.mac_address.clone(), device);
+                            device_map.insert(device.mac_address, device);
                         }
-                        system_config.set_adapter_devices(adapter_mac.clone(), device_map);
+                        system_config.set_adapter_devices(*adapter_mac, device_map);
                     }
                 }
                 Err(e) => {
@@ -180,20 +803,33 @@ impl SyncManager {
         }
 
         // Merge strategy: Update existing devices from EFI, add new system devices to EFI
-        let final_config = if let Some(mut efi_cfg) = efi_config {
+        let mut final_config = if let Some(mut efi_cfg) = efi_config {
             log!("[BlueVein] Merging EFI config with system state");
 
             // Step 1: Apply EFI keys to existing system devices
+            let no_devices = BTreeMap::new();
             for adapter_mac in &adapters {
                 if let Some(efi_devices) = efi_cfg.get_adapter_devices(adapter_mac) {
-                    if let Some(system_devices) = system_config.get_adapter_devices(adapter_mac) {
+                    let system_devices = system_config.get_adapter_devices(adapter_mac);
+                    if system_devices.is_some() || !self.sync_settings.skip_missing_devices {
+                        let system_devices = system_devices.unwrap_or(&no_devices);
                         log!("[BlueVein] Processing adapter {}", adapter_mac);
 
                         for (device_mac, efi_device) in efi_devices {
-                            if let Some(system_device) = system_devices.get(device_mac) {
+                            let system_device = system_devices.get(device_mac);
+                            let Some(efi_device) = self.select_device(
+                                &mut report,
+                                adapter_mac,
+                                efi_device,
+                                system_device,
+                            ) else {
+                                continue;
+                            };
+
+                            if let Some(system_device) = system_device {
                                 // Device exists in both EFI and system
                                 // Merge to combine both Classic and LE keys if needed
-                                let merged = Self::merge_devices(system_device, efi_device);
+                                let merged = Self::merge_devices(system_device, &efi_device);
 
                                 if Self::devices_differ(system_device, &merged) {
                                     // Keys differ or missing - update from merged result
@@ -203,25 +839,34 @@ impl SyncManager {
                                         merged.classic.is_some(),
                                         merged.le.is_some()
                                     );
-                                    match self.bt_manager.set_device(adapter_mac, &merged) {
-                                        Ok(_) => {
-                                            log!("[BlueVein]   ✓ Updated device {}", device_mac)
-                                        }
-                                        Err(e) => log!(
-                                            "[BlueVein]   ✗ Failed to update device {}: {}",
-                                            device_mac,
-                                            e
-                                        ),
-                                    }
+                                    let action =
+                                        self.apply_device(&mut report, adapter_mac, &merged);
+                                    report.record(adapter_mac, device_mac, action);
                                 } else {
                                     log!(
                                         "[BlueVein]   ✓ Device {} already has correct keys",
                                         device_mac
                                     );
+                                    report.record(adapter_mac, device_mac, DeviceAction::Unchanged);
                                 }
+                            } else if !self.sync_settings.skip_missing_devices {
+                                log!(
+                                    "[BlueVein]   + Pairing device {} from EFI config",
+                                    device_mac
+                                );
+                                let action =
+                                    self.apply_device(&mut report, adapter_mac, &efi_device);
+                                report.record(adapter_mac, device_mac, action);
                             } else {
                                 // Device in EFI but NOT in system - don't create it
                                 log!("[BlueVein]   ○ Device {} exists in EFI but not in system - skipping (will sync on re-pair)", device_mac);
+                                report.record(
+                                    adapter_mac,
+                                    device_mac,
+                                    DeviceAction::Skipped {
+                                        reason: SkipReason::NotPairedLocally,
+                                    },
+                                );
                             }
                         }
                     }
@@ -240,7 +885,12 @@ impl SyncManager {
 
                         if !device_in_efi {
                             // Device in system but NOT in EFI - add it
-                            devices_to_add.push(system_device.clone());
+                            devices_to_add.extend(self.select_device(
+                                &mut report,
+                                adapter_mac,
+                                system_device,
+                                None,
+                            ));
                         }
                     }
 
@@ -252,7 +902,8 @@ impl SyncManager {
                             device.classic.is_some(),
                             device.le.is_some()
                         );
-                        efi_cfg.update_device(adapter_mac.clone(), device);
+                        report.record(adapter_mac, &device.mac_address, DeviceAction::Added);
+                        efi_cfg.update_device(*adapter_mac, device);
                     }
                 }
             }
@@ -261,39 +912,96 @@ impl SyncManager {
         } else {
             // No EFI config exists, use system state
             log!("[BlueVein] Creating new EFI config from system state");
-            system_config
+            let mut config = BlueVeinConfig::new();
+            for (adapter_mac, devices) in &system_config.adapters {
+                for device in devices.devices.values() {
+                    if let Some(device) = self.select_device(&mut report, adapter_mac, device, None)
+                    {
+                        report.record(adapter_mac, &device.mac_address, DeviceAction::Added);
+                        config.update_device(*adapter_mac, device);
+                    }
+                }
+            }
+            config
         };
 
         // Write merged config back to EFI
-        match efi::write_config_with_device(&final_config, Some(&self.efi_context.device)) {
-            Ok(_) => log!(
-                "[BlueVein] Successfully wrote merged config to EFI (device: {})",
-                self.efi_context.display_name()
-            ),
+        match self.write_efi_config(&mut final_config) {
+            Ok(0) => {}
+            Ok(bytes) => {
+                report.bytes_written = bytes;
+                log!(
+                    "[BlueVein] Successfully wrote merged config to EFI (device: {})",
+                    self.efi_context.display_name()
+                )
+            }
             Err(e) => {
                 log!("[BlueVein] Error writing config to EFI: {}", e);
-                return Err(Box::new(e));
+                return Err(e);
             }
         }
 
-        log!("[BlueVein] Bidirectional synchronization complete");
-        Ok(())
+        let report = report.finish();
+        log!(
+            "[BlueVein] Bidirectional synchronization complete ({})",
+            report
+        );
+        self.hooks.run(&report);
+        Ok(report)
+    }
+
+    /// Apply merged keys to the system, logging and returning the outcome
+    ///
+    /// The device's current keys are backed up first.
+    fn apply_device(
+        &mut self,
+        report: &mut SyncReport,
+        adapter_mac: &MacAddress,
+        device: &BluetoothDevice,
+    ) -> DeviceAction {
+        if let Err(e) = self.back_up_device(report, adapter_mac, &device.mac_address) {
+            log!(
+                "[BlueVein]   ✗ Not updating device {}, backup failed: {}",
+                device.mac_address,
+                e
+            );
+            return DeviceAction::Failed {
+                error: format!("backup failed: {}", e),
+            };
+        }
+
+        match self.bt_manager.set_device(adapter_mac, device) {
+            Ok(_) => {
+                log!("[BlueVein]   ✓ Updated device {}", device.mac_address);
+                DeviceAction::Updated
+            }
+            Err(e) => {
+                log!(
+                    "[BlueVein]   ✗ Failed to update device {}: {}",
+                    device.mac_address,
+                    e
+                );
+                DeviceAction::Failed {
+                    error: e.to_string(),
+                }
+            }
+        }
     }
 
     /// Perform initial synchronization from EFI to system
     /// This reads the shared config and updates system Bluetooth keys
     #[allow(dead_code)]
-    pub fn sync_from_efi(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn sync_from_efi(&mut self) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::FromEfi);
         log!("[BlueVein] Starting synchronization from EFI...");
 
         // Read config from EFI
-        let config = match efi::read_config_with_device(Some(&self.efi_context.device)) {
-            Ok(config) => config,
-            Err(efi::EfiError::NotFound) => {
+        let config = match self.read_efi_config()? {
+            Some(config) => config,
+            None => {
                 log!("[BlueVein] No existing config found on EFI, will create on first change");
-                return Ok(());
+                return Ok(report.finish());
             }
-            Err(e) => return Err(Box::new(e)),
         };
 
         // Get local adapters
@@ -309,34 +1017,33 @@ impl SyncManager {
                 );
 
                 for (device_mac, device) in devices {
-                    match self.bt_manager.set_device(&adapter_mac, device) {
-                        Ok(_) => log!("[BlueVein]   ✓ Updated keys for device {}", device_mac),
-                        Err(e) => log!(
-                            "[BlueVein]   ✗ Failed to update device {}: {}",
-                            device_mac,
-                            e
-                        ),
-                    }
+                    let local = self.bt_manager.get_device(&adapter_mac, device_mac).ok();
+                    let Some(device) =
+                        self.select_device(&mut report, &adapter_mac, device, local.as_ref())
+                    else {
+                        continue;
+                    };
+                    let action = self.apply_device(&mut report, &adapter_mac, &device);
+                    report.record(&adapter_mac, device_mac, action);
                 }
             }
         }
 
-        log!("[BlueVein] Synchronization from EFI complete");
-        Ok(())
+        let report = report.finish();
+        log!("[BlueVein] Synchronization from EFI complete ({})", report);
+        Ok(report)
     }
 
     /// Sync current system state to EFI
     /// This reads system Bluetooth keys and writes them to the shared config
     #[allow(dead_code)]
-    pub fn sync_to_efi(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn sync_to_efi(&mut self) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::ToEfi);
         log!("[BlueVein] Syncing current state to EFI...");
 
         // Read existing config from EFI (or create empty)
-        let mut config = match efi::read_config_with_device(Some(&self.efi_context.device)) {
-            Ok(config) => config,
-            Err(efi::EfiError::NotFound) => BlueVeinConfig::new(),
-            Err(e) => return Err(Box::new(e)),
-        };
+        let mut config = self.read_efi_config()?.unwrap_or_default();
+        self.apply_tombstones(&mut config, &mut report)?;
 
         // Get local adapters
         let adapters = self.bt_manager.get_adapters()?;
@@ -352,9 +1059,15 @@ impl SyncManager {
                     adapter_mac
                 );
 
-                let mut device_map = HashMap::new();
+                let mut device_map = BTreeMap::new();
                 for device in devices {
-                    device_map.insert(device.mac_address.clone(), device);
+                    let stored = config.get_device(&adapter_mac, &device.mac_address);
+                    if let Some(device) =
+                        self.select_device(&mut report, &adapter_mac, &device, stored)
+                    {
+                        report.record(&adapter_mac, &device.mac_address, DeviceAction::Added);
+                        device_map.insert(device.mac_address, device);
+                    }
                 }
 
                 config.set_adapter_devices(adapter_mac, device_map);
@@ -362,13 +1075,13 @@ impl SyncManager {
         }
 
         // Write config to EFI
-        efi::write_config_with_device(&config, Some(&self.efi_context.device))?;
+        report.bytes_written = self.write_efi_config(&mut config)?;
         log!(
             "[BlueVein] Successfully synced to EFI (device: {})",
             self.efi_context.display_name()
         );
 
-        Ok(())
+        Ok(report.finish())
     }
 
     /// Handle a device change event (pairing or key modification)
@@ -376,9 +1089,10 @@ impl SyncManager {
     /// Updates the device keys in bluevein.json
     pub fn handle_device_change(
         &mut self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::DeviceChange);
         log!(
             "[BlueVein] Device change detected: {} on adapter {}",
             device_mac,
@@ -394,23 +1108,64 @@ impl SyncManager {
             }
         };
 
+        // A device left out by the filter rules never reaches EFI
+        let Some(device) = self.select_device(&mut report, adapter_mac, &device, None) else {
+            return Ok(report.finish());
+        };
+
         log!("[BlueVein] Reading existing EFI config...");
         // Read existing config
-        let mut config = match efi::read_config_with_device(Some(&self.efi_context.device)) {
-            Ok(config) => {
+        let mut config = match self.read_efi_config() {
+            Ok(Some(config)) => {
                 log!("[BlueVein] Found existing EFI config");
                 config
             }
-            Err(efi::EfiError::NotFound) => {
+            Ok(None) => {
                 log!("[BlueVein] No EFI config found, creating new");
                 BlueVeinConfig::new()
             }
             Err(e) => {
                 log!("[BlueVein] Error reading EFI config: {}", e);
-                return Err(Box::new(e));
+                self.defer_device_change(adapter_mac, device_mac, &e);
+                return Err(e);
+            }
+        };
+
+        // A device forgotten on another installation is removed, not re-added
+        if config
+            .pending_tombstones(self.local_id().as_deref())
+            .contains(device_mac)
+        {
+            log!(
+                "[BlueVein] Device {} was forgotten on another installation",
+                device_mac
+            );
+            self.apply_tombstones(&mut config, &mut report)?;
+            report.bytes_written = self.write_efi_config(&mut config)?;
+            return Ok(report.finish());
+        }
+
+        // Keys of families this installation does not sync stay as stored
+        let device = match config.get_device(adapter_mac, &device.mac_address) {
+            Some(stored) if self.filters.scope(adapter_mac, &device) != Some(KeyFamilies::ALL) => {
+                stored.merge_with(&device)
             }
+            _ => device,
         };
 
+        // Attribute-only changes to the info file leave the keys as they are
+        if let Some(stored) = config.get_device(adapter_mac, &device.mac_address) {
+            if !Self::devices_differ(&device, stored) {
+                log!(
+                    "[BlueVein] ✓ Keys of device {} unchanged in EFI config",
+                    device_mac
+                );
+                report.bytes_written = self.write_efi_config(&mut config)?;
+                report.record(adapter_mac, &device.mac_address, DeviceAction::Unchanged);
+                return Ok(report.finish());
+            }
+        }
+
         log!(
             "[BlueVein] Updating device {} (Classic: {}, LE: {})",
             device.mac_address,
@@ -418,12 +1173,13 @@ impl SyncManager {
             device.le.is_some()
         );
         // Update config
-        config.update_device(adapter_mac.to_string(), device.clone());
+        config.update_device(*adapter_mac, device.clone());
 
         log!("[BlueVein] Writing updated config to EFI...");
         // Write back to EFI
-        match efi::write_config_with_device(&config, Some(&self.efi_context.device)) {
-            Ok(_) => {
+        match self.write_efi_config(&mut config) {
+            Ok(bytes) => {
+                report.bytes_written = bytes;
                 log!(
                     "[BlueVein] ✓ Successfully updated EFI config for device {} (device: {})",
                     device_mac,
@@ -431,9 +1187,8 @@ impl SyncManager {
                 );
 
                 // Verify write
-                if let Ok(verify_config) =
-                    efi::read_config_with_device(Some(&self.efi_context.device))
-                {
+                let mut action = DeviceAction::Added;
+                if let Ok(Some(verify_config)) = self.read_efi_config() {
                     if let Some(stored_device) =
                         verify_config.get_device(adapter_mac, &device.mac_address)
                     {
@@ -443,20 +1198,89 @@ impl SyncManager {
                         );
                         if Self::devices_differ(&device, stored_device) {
                             log!("[BlueVein] ✗ Warning: Device keys differ after write!");
+                            action = DeviceAction::Failed {
+                                error: "device keys differ after write".to_string(),
+                            };
                         }
                     } else {
                         log!(
                             "[BlueVein] ✗ Warning: Device {} NOT found in EFI config after write!",
                             device_mac
                         );
+                        action = DeviceAction::Failed {
+                            error: "device not found in EFI config after write".to_string(),
+                        };
                     }
                 }
 
-                Ok(())
+                report.record(adapter_mac, &device.mac_address, action);
+                Ok(report.finish())
             }
             Err(e) => {
                 log!("[BlueVein] ✗ Failed to write EFI config: {}", e);
-                Err(Box::new(e))
+                self.defer_device_change(adapter_mac, device_mac, &e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Queue a device change that could not reach the ESP for a later retry
+    fn defer_device_change(
+        &self,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+        error: &BlueVeinError,
+    ) {
+        if !error.is_transient() {
+            return;
+        }
+        match self
+            .outbox
+            .push(*adapter_mac, *device_mac, inventory::unix_now())
+        {
+            Ok(()) => log!(
+                "[BlueVein] ○ Queued device {} for a later retry",
+                device_mac
+            ),
+            Err(e) => log!("[BlueVein] ✗ Failed to queue device {}: {}", device_mac, e),
+        }
+    }
+
+    /// Retry device changes queued while the ESP was unavailable
+    ///
+    /// Does nothing until the backoff has expired, unless `force` is set
+    /// (used on shutdown). Keys are read from the system again, so a device
+    /// that was re-keyed or removed in the meantime is handled correctly.
+    pub fn retry_pending(&mut self, force: bool) {
+        let now = inventory::unix_now();
+        let pending = self.outbox.due(now, force);
+        if pending.is_empty() {
+            return;
+        }
+        log!(
+            "[BlueVein] Retrying {} pending device change(s)...",
+            pending.len()
+        );
+
+        for change in &pending {
+            match self.handle_device_change(&change.adapter, &change.device) {
+                Ok(report) => self.hooks.run(&report),
+                Err(BlueVeinError::DeviceNotFound { .. }) => {}
+                Err(e) if e.is_transient() => {
+                    log!("[BlueVein] ○ EFI partition still unavailable, will retry later");
+                    if let Err(e) = self.outbox.record_failure(now) {
+                        log!("[BlueVein] ✗ Failed to update outbox: {}", e);
+                    }
+                    return;
+                }
+                Err(e) => log!(
+                    "[BlueVein] ✗ Dropping pending change of device {}: {}",
+                    change.device,
+                    e
+                ),
+            }
+            if let Err(e) = self.outbox.remove(change) {
+                log!("[BlueVein] ✗ Failed to update outbox: {}", e);
             }
         }
     }
@@ -467,11 +1291,14 @@ impl SyncManager {
     /// - Device may still be paired on another OS
     /// - If user re-pairs on this OS, new key will be synced automatically
     /// - Keeps the shared config as a "union" of all paired devices across both OSes
+    ///
+    /// Use [`SyncManager::forget_device`] to remove a device everywhere.
     pub fn handle_device_removal(
         &mut self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<(), Box<dyn Error>> {
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::DeviceRemoval);
         log!(
             "[BlueVein] Device removal detected: {} on adapter {}",
             device_mac,
@@ -482,26 +1309,101 @@ impl SyncManager {
         // Don't modify EFI - just log the event
         // The device will remain in bluevein.json and can be used on the other OS
         // If user re-pairs on this OS, the key will be updated automatically
+        report.record(
+            adapter_mac,
+            device_mac,
+            DeviceAction::Skipped {
+                reason: SkipReason::RemovalNotPropagated,
+            },
+        );
 
-        Ok(())
+        Ok(report.finish())
+    }
+
+    /// Capture the current Bluetooth state of this system
+    pub fn snapshot(&self) -> Result<Snapshot, BlueVeinError> {
+        Snapshot::capture(self.bt_manager.as_ref())
+    }
+
+    /// Dispatch changes detected by diffing two snapshots
+    ///
+    /// Shared by the Linux and Windows monitors so both platforms react to
+    /// the same set of events in the same way.
+    pub fn handle_changes(&mut self, changes: &[Change]) {
+        for change in changes {
+            match change {
+                Change::AdapterAdded { adapter } => {
+                    log!("[BlueVein] New adapter detected: {}", adapter);
+                }
+                Change::AdapterRemoved { adapter } => {
+                    log!("[BlueVein] Adapter removed: {}", adapter);
+                }
+                Change::DeviceAdded { adapter, device } => {
+                    log!(
+                        "[BlueVein] New device paired: {} on adapter {}",
+                        device,
+                        adapter
+                    );
+                    match self.handle_device_change(adapter, device) {
+                        Ok(report) => self.hooks.run(&report),
+                        Err(e) => log!("[BlueVein] Failed to sync new device: {}", e),
+                    }
+                }
+                Change::KeysChanged { adapter, device } => {
+                    log!(
+                        "[BlueVein] Device keys changed: {} on adapter {}",
+                        device,
+                        adapter
+                    );
+                    match self.handle_device_change(adapter, device) {
+                        Ok(report) => self.hooks.run(&report),
+                        Err(e) => log!("[BlueVein] Failed to sync device change: {}", e),
+                    }
+                }
+                Change::DeviceRemoved { adapter, device } => {
+                    if let Err(e) = self.handle_device_removal(adapter, device) {
+                        log!("[BlueVein] Failed to handle device removal: {}", e);
+                    }
+                }
+            }
+        }
     }
 
     /// Check EFI for changes and apply them to the system
     /// This allows changes made by another OS to be detected
     ///
-    /// Only updates keys for devices that already exist in the system.
-    /// Does NOT create new devices.
+    /// Devices missing from the system are only created if
+    /// `skip_missing_devices` is turned off in the settings.
     #[allow(dead_code)]
-    pub fn check_efi_changes(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn check_efi_changes(&mut self) -> Result<SyncReport, BlueVeinError> {
+        let mut report = SyncReport::new(Operation::EfiCheck);
+
         // Read config from EFI
-        let config = match efi::read_config_with_device(Some(&self.efi_context.device)) {
-            Ok(config) => config,
-            Err(efi::EfiError::NotFound) => {
-                return Ok(());
-            }
-            Err(e) => return Err(Box::new(e)),
+        let mut config = match self.read_efi_config()? {
+            Some(config) => config,
+            None => return Ok(report.finish()),
         };
 
+        // Remove devices forgotten on another installation
+        if self.apply_tombstones(&mut config, &mut report)? {
+            report.bytes_written = self.write_efi_config(&mut config)?;
+        }
+
+        self.apply_config_to_system(&config, &mut report)?;
+        let report = report.finish();
+        self.hooks.run(&report);
+        Ok(report)
+    }
+
+    /// Apply EFI keys to devices that are paired on this system
+    ///
+    /// Devices missing from the system are only created if
+    /// `skip_missing_devices` is turned off in the settings.
+    fn apply_config_to_system(
+        &mut self,
+        config: &BlueVeinConfig,
+        report: &mut SyncReport,
+    ) -> Result<(), BlueVeinError> {
         // Get local adapters
         let adapters = self.bt_manager.get_adapters()?;
 
@@ -510,26 +1412,38 @@ impl SyncManager {
             if let Some(efi_devices) = config.get_adapter_devices(&adapter_mac) {
                 // Get current system devices
                 let system_devices = self.bt_manager.get_devices(&adapter_mac)?;
-                let system_map: HashMap<String, BluetoothDevice> = system_devices
+                let system_map: BTreeMap<MacAddress, BluetoothDevice> = system_devices
                     .into_iter()
-                    .map(|d| (d.mac_address.clone(), d))
+                    .map(|d| (d.mac_address, d))
                     .collect();
 
                 // Apply changes from EFI only for devices that exist in system
                 for (device_mac, efi_device) in efi_devices {
-                    if let Some(system_device) = system_map.get(device_mac) {
+                    let system_device = system_map.get(device_mac);
+                    let Some(efi_device) =
+                        self.select_device(report, &adapter_mac, efi_device, system_device)
+                    else {
+                        continue;
+                    };
+
+                    if let Some(system_device) = system_device {
                         // Device exists in system - merge and check if keys differ
-                        let merged = Self::merge_devices(system_device, efi_device);
+                        let merged = Self::merge_devices(system_device, &efi_device);
                         if Self::devices_differ(system_device, &merged) {
                             log!(
                                 "[BlueVein] Key mismatch for {} - updating from EFI",
                                 device_mac
                             );
-                            self.bt_manager.set_device(&adapter_mac, &merged)?;
+                            let action = self.apply_device(report, &adapter_mac, &merged);
+                            report.record(&adapter_mac, device_mac, action);
                         }
+                    } else if !self.sync_settings.skip_missing_devices {
+                        log!("[BlueVein] Pairing device {} from EFI config", device_mac);
+                        let action = self.apply_device(report, &adapter_mac, &efi_device);
+                        report.record(&adapter_mac, device_mac, action);
                     }
-                    // If device doesn't exist in system - don't create it
-                    // User will pair it manually if needed
+                    // Otherwise a device missing from the system is not created,
+                    // the user pairs it manually if needed
                 }
             }
         }
@@ -537,3 +1451,310 @@ impl SyncManager {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bluetooth::{LeKeys, LeLongTermKey};
+    use crate::config::UnknownFields;
+    use crate::filter::FilterRule;
+    use crate::types::Key128;
+    use std::collections::HashMap;
+    use std::fs;
+    use tempfile::{tempdir, TempDir};
+
+    const ADAPTER: &str = "00:11:22:33:44:55";
+    /// Excluded by a `sync = false` rule on its name
+    const EXCLUDED: &str = "AA:BB:CC:DD:EE:01";
+    /// Limited to its IRK by a `keys = ["irk"]` rule
+    const IRK_ONLY: &str = "AA:BB:CC:DD:EE:02";
+    const KEY_A: &str = "0123456789ABCDEF0123456789ABCDEF";
+    const KEY_B: &str = "FEDCBA9876543210FEDCBA9876543210";
+    const KEY_C: &str = "00112233445566778899AABBCCDDEEFF";
+
+    /// In-memory Bluetooth manager whose devices `set_device` replaces
+    #[derive(Default)]
+    struct FakeManager {
+        adapters: HashMap<MacAddress, Vec<BluetoothDevice>>,
+    }
+
+    impl BluetoothManager for FakeManager {
+        fn get_adapters(&self) -> Result<Vec<MacAddress>, BlueVeinError> {
+            Ok(self.adapters.keys().cloned().collect())
+        }
+
+        fn get_devices(
+            &self,
+            adapter_mac: &MacAddress,
+        ) -> Result<Vec<BluetoothDevice>, BlueVeinError> {
+            self.adapters
+                .get(adapter_mac)
+                .cloned()
+                .ok_or_else(|| BlueVeinError::BackendUnavailable("unknown adapter".to_string()))
+        }
+
+        fn get_device(
+            &self,
+            adapter_mac: &MacAddress,
+            device_mac: &MacAddress,
+        ) -> Result<BluetoothDevice, BlueVeinError> {
+            self.get_devices(adapter_mac)?
+                .into_iter()
+                .find(|d| d.mac_address == *device_mac)
+                .ok_or_else(|| BlueVeinError::device_not_found(adapter_mac, device_mac))
+        }
+
+        fn set_device(
+            &mut self,
+            adapter_mac: &MacAddress,
+            device: &BluetoothDevice,
+        ) -> Result<(), BlueVeinError> {
+            let devices = self.adapters.entry(*adapter_mac).or_default();
+            devices.retain(|d| d.mac_address != device.mac_address);
+            devices.push(device.clone());
+            Ok(())
+        }
+
+        fn remove_device(
+            &mut self,
+            adapter_mac: &MacAddress,
+            device_mac: &MacAddress,
+        ) -> Result<(), BlueVeinError> {
+            if let Some(devices) = self.adapters.get_mut(adapter_mac) {
+                devices.retain(|d| d.mac_address != *device_mac);
+            }
+            Ok(())
+        }
+    }
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    fn key(s: &str) -> Key128 {
+        s.parse().unwrap()
+    }
+
+    /// A dual-mode device whose classic key, LTK and IRK are all `key`
+    fn device(device: &str, name: &str, key_hex: &str) -> BluetoothDevice {
+        BluetoothDevice {
+            le: Some(LeKeys {
+                ltk: Some(LeLongTermKey {
+                    key: key(key_hex),
+                    authenticated: Some(1),
+                    enc_size: Some(16),
+                    ediv: Some(0),
+                    rand: Some(0),
+                    extra: UnknownFields::default(),
+                }),
+                irk: Some(key(key_hex)),
+                ..Default::default()
+            }),
+            name: Some(name.to_string()),
+            ..BluetoothDevice::classic(mac(device), key(key_hex))
+        }
+    }
+
+    fn with_irk(mut device: BluetoothDevice, key_hex: &str) -> BluetoothDevice {
+        device.le.as_mut().unwrap().irk = Some(key(key_hex));
+        device
+    }
+
+    /// The classic key, LTK and IRK of a device
+    fn keys(device: &BluetoothDevice) -> (Key128, Key128, Key128) {
+        let le = device.le.as_ref().unwrap();
+        (
+            device.classic.as_ref().unwrap().link_key.clone(),
+            le.ltk.as_ref().unwrap().key.clone(),
+            le.irk.clone().unwrap(),
+        )
+    }
+
+    /// A sync manager over `system` whose store is a directory in `tmp`
+    ///
+    /// The store starts out with both test devices keyed with KEY_B.
+    fn sync_manager(tmp: &TempDir, system: Vec<BluetoothDevice>) -> SyncManager {
+        let esp = tmp.path().join("esp");
+        fs::create_dir_all(esp.join("EFI")).unwrap();
+        let settings = Settings {
+            filters: Filters::new(vec![
+                FilterRule {
+                    name: Some("Lab *".to_string()),
+                    sync: false,
+                    ..Default::default()
+                },
+                FilterRule {
+                    device: Some(mac(IRK_ONLY)),
+                    keys: KeyFamilies::from_names(["irk"]).unwrap(),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        let manager = FakeManager {
+            adapters: HashMap::from([(mac(ADAPTER), system)]),
+        };
+        let mut sync_manager = SyncManager::new(
+            Box::new(manager),
+            EfiContext::new(esp.to_string_lossy()),
+            &settings,
+        );
+        sync_manager.backups = BackupStore::new(tmp.path().join("backups"));
+        sync_manager.outbox = Outbox::new(tmp.path().join("outbox.json"));
+        sync_manager.lock_path = tmp.path().join("bluevein.lock");
+
+        let mut config = BlueVeinConfig::new();
+        config.update_device(mac(ADAPTER), device(EXCLUDED, "Lab mouse", KEY_B));
+        config.update_device(mac(ADAPTER), device(IRK_ONLY, "Headset", KEY_B));
+        sync_manager.write_efi_config(&mut config).unwrap();
+        sync_manager
+    }
+
+    fn stored(sync_manager: &SyncManager, device: &str) -> BluetoothDevice {
+        let config = sync_manager.read_efi_config().unwrap().unwrap();
+        config
+            .get_device(&mac(ADAPTER), &mac(device))
+            .unwrap()
+            .clone()
+    }
+
+    fn system(sync_manager: &SyncManager, device: &str) -> BluetoothDevice {
+        sync_manager
+            .bt_manager
+            .get_device(&mac(ADAPTER), &mac(device))
+            .unwrap()
+    }
+
+    fn action(report: &SyncReport, device: &str) -> Option<DeviceAction> {
+        report
+            .devices()
+            .find(|(_, d)| d.device == mac(device))
+            .map(|(_, d)| d.action.clone())
+    }
+
+    const FILTERED: DeviceAction = DeviceAction::Skipped {
+        reason: SkipReason::Filtered,
+    };
+
+    #[test]
+    fn test_sync_bidirectional_respects_filters() {
+        let tmp = tempdir().unwrap();
+        let mut sync_manager = sync_manager(
+            &tmp,
+            vec![
+                device(EXCLUDED, "Lab mouse", KEY_A),
+                device(IRK_ONLY, "Headset", KEY_A),
+            ],
+        );
+
+        let report = sync_manager.sync_bidirectional().unwrap();
+        assert_eq!(action(&report, EXCLUDED), Some(FILTERED));
+        assert_eq!(action(&report, IRK_ONLY), Some(DeviceAction::Updated));
+
+        // The excluded device keeps its keys on both sides
+        assert_eq!(
+            system(&sync_manager, EXCLUDED),
+            device(EXCLUDED, "Lab mouse", KEY_A)
+        );
+        assert_eq!(
+            stored(&sync_manager, EXCLUDED),
+            device(EXCLUDED, "Lab mouse", KEY_B)
+        );
+
+        // Only the IRK is applied, the stored classic key and LTK stay as they were
+        assert_eq!(
+            keys(&system(&sync_manager, IRK_ONLY)),
+            (key(KEY_A), key(KEY_A), key(KEY_B))
+        );
+        assert_eq!(
+            keys(&stored(&sync_manager, IRK_ONLY)),
+            (key(KEY_B), key(KEY_B), key(KEY_B))
+        );
+    }
+
+    #[test]
+    fn test_sync_bidirectional_does_not_add_excluded_devices() {
+        let tmp = tempdir().unwrap();
+        let mut sync_manager = sync_manager(&tmp, vec![]);
+        let new_device = "AA:BB:CC:DD:EE:03";
+        sync_manager
+            .bt_manager
+            .set_device(&mac(ADAPTER), &device(new_device, "Lab keyboard", KEY_A))
+            .unwrap();
+
+        let report = sync_manager.sync_bidirectional().unwrap();
+        assert_eq!(action(&report, new_device), Some(FILTERED));
+        let config = sync_manager.read_efi_config().unwrap().unwrap();
+        assert!(config.get_device(&mac(ADAPTER), &mac(new_device)).is_none());
+    }
+
+    #[test]
+    fn test_handle_device_change_respects_filters() {
+        let tmp = tempdir().unwrap();
+        let mut sync_manager = sync_manager(
+            &tmp,
+            vec![
+                device(EXCLUDED, "Lab mouse", KEY_A),
+                with_irk(device(IRK_ONLY, "Headset", KEY_A), KEY_C),
+            ],
+        );
+
+        let report = sync_manager
+            .handle_device_change(&mac(ADAPTER), &mac(EXCLUDED))
+            .unwrap();
+        assert_eq!(action(&report, EXCLUDED), Some(FILTERED));
+        assert_eq!(report.bytes_written, 0);
+        assert_eq!(
+            stored(&sync_manager, EXCLUDED),
+            device(EXCLUDED, "Lab mouse", KEY_B)
+        );
+
+        // Only the new IRK is shared, the stored classic key and LTK stay as they were
+        let report = sync_manager
+            .handle_device_change(&mac(ADAPTER), &mac(IRK_ONLY))
+            .unwrap();
+        assert_eq!(action(&report, IRK_ONLY), Some(DeviceAction::Added));
+        assert_eq!(
+            keys(&stored(&sync_manager, IRK_ONLY)),
+            (key(KEY_B), key(KEY_B), key(KEY_C))
+        );
+        assert_eq!(
+            keys(&system(&sync_manager, IRK_ONLY)),
+            (key(KEY_A), key(KEY_A), key(KEY_C))
+        );
+    }
+
+    #[test]
+    fn test_check_efi_changes_respects_filters() {
+        let tmp = tempdir().unwrap();
+        let mut sync_manager = sync_manager(
+            &tmp,
+            vec![
+                device(EXCLUDED, "Lab mouse", KEY_A),
+                device(IRK_ONLY, "Headset", KEY_A),
+            ],
+        );
+
+        let report = sync_manager.check_efi_changes().unwrap();
+        assert_eq!(action(&report, EXCLUDED), Some(FILTERED));
+        assert_eq!(action(&report, IRK_ONLY), Some(DeviceAction::Updated));
+        assert_eq!(report.bytes_written, 0);
+
+        assert_eq!(
+            system(&sync_manager, EXCLUDED),
+            device(EXCLUDED, "Lab mouse", KEY_A)
+        );
+        assert_eq!(
+            stored(&sync_manager, EXCLUDED),
+            device(EXCLUDED, "Lab mouse", KEY_B)
+        );
+        assert_eq!(
+            keys(&system(&sync_manager, IRK_ONLY)),
+            (key(KEY_A), key(KEY_A), key(KEY_B))
+        );
+        assert_eq!(
+            keys(&stored(&sync_manager, IRK_ONLY)),
+            (key(KEY_B), key(KEY_B), key(KEY_B))
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Strongly typed Bluetooth identifiers and key material
+//!
+//! [`MacAddress`] and [`Key128`] validate on parse (`FromStr` and serde), so
+//! malformed addresses or keys are rejected where they enter the program
+//! instead of being carried around as arbitrary strings.
+//!
+//! [`Key128`] is also a secret: its memory is wiped on drop and `Debug` /
+//! `Display` only show a short fingerprint, so key bytes never reach the logs.
+
+use crate::bluetooth::validate_bluetooth_key;
+use crate::error::BlueVeinError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+/// 48-bit Bluetooth device address
+///
+/// Parses `AA:BB:CC:DD:EE:FF`, `AA-BB-CC-DD-EE-FF` and the Windows registry
+/// form `AABBCCDDEEFF` (case-insensitive). Always displays as upper-case,
+/// colon-separated.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddress([u8; 6]);
+
+impl MacAddress {
+    /// Create an address from raw bytes (most significant byte first)
+    #[allow(dead_code)]
+    pub const fn new(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+
+    /// Raw address bytes (most significant byte first)
+    #[allow(dead_code)]
+    pub fn bytes(self) -> [u8; 6] {
+        self.0
+    }
+
+    /// Windows registry format (`AABBCCDDEEFF`)
+    #[allow(dead_code)]
+    pub fn to_windows_format(self) -> String {
+        hex::encode_upper(self.0)
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = BlueVeinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BlueVeinError::InvalidAddress(s.to_string());
+
+        let hex_digits: String = match s.len() {
+            // Separated form: every third character must be the same ':' or '-'
+            17 => {
+                let separator = s.as_bytes()[2];
+                if separator != b':' && separator != b'-' {
+                    return Err(invalid());
+                }
+                let mut digits = String::with_capacity(12);
+                for (i, c) in s.chars().enumerate() {
+                    if i % 3 == 2 {
+                        if c != separator as char {
+                            return Err(invalid());
+                        }
+                    } else {
+                        digits.push(c);
+                    }
+                }
+                digits
+            }
+            12 => s.to_string(),
+            _ => return Err(invalid()),
+        };
+
+        let mut bytes = [0u8; 6];
+        hex::decode_to_slice(&hex_digits, &mut bytes).map_err(|_| invalid())?;
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl fmt::Debug for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MacAddress({})", self)
+    }
+}
+
+impl Serialize for MacAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// 128-bit Bluetooth key (LinkKey, LTK, IRK, CSRK)
+///
+/// Serialized as 32 upper-case hex characters. Lower-case input is accepted.
+/// Zeroed on drop; formatting shows a fingerprint instead of the key.
+#[derive(Clone, PartialEq, Eq, Zeroize, ZeroizeOnDrop)]
+pub struct Key128([u8; 16]);
+
+impl Key128 {
+    /// Parse a hex key, naming it in the error message
+    pub fn parse_named(hex_key: &str, key_name: &str) -> Result<Self, BlueVeinError> {
+        validate_bluetooth_key(hex_key, key_name)?;
+
+        // Decode straight into the key so no unwiped copy is left behind
+        let mut key = Self([0u8; 16]);
+        hex::decode_to_slice(hex_key, &mut key.0)
+            .map_err(|e| BlueVeinError::invalid_key(key_name, e.to_string()))?;
+        Ok(key)
+    }
+
+    /// Build a key from raw bytes (e.g. a REG_BINARY registry value)
+    #[allow(dead_code)]
+    pub fn from_bytes(bytes: &[u8], key_name: &str) -> Result<Self, BlueVeinError> {
+        if bytes.len() != 16 {
+            return Err(BlueVeinError::invalid_key(
+                key_name,
+                format!(
+                    "invalid length: expected 16 bytes, got {} bytes",
+                    bytes.len()
+                ),
+            ));
+        }
+
+        let mut key = Self([0u8; 16]);
+        key.0.copy_from_slice(bytes);
+        Ok(key)
+    }
+
+    /// Raw key bytes
+    #[allow(dead_code)]
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+
+    /// Upper-case hex representation (as stored in BlueZ info files and JSON)
+    pub fn to_hex(&self) -> Zeroizing<String> {
+        Zeroizing::new(hex::encode_upper(self.0))
+    }
+
+    /// Short, non-reversible identifier for logs (first 4 bytes of SHA-256)
+    pub fn fingerprint(&self) -> String {
+        let digest = Sha256::digest(self.0);
+        hex::encode(&digest[..4])
+    }
+}
+
+impl fmt::Debug for Key128 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key128(sha256:{})", self.fingerprint())
+    }
+}
+
+impl fmt::Display for Key128 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "sha256:{}", self.fingerprint())
+    }
+}
+
+impl FromStr for Key128 {
+    type Err = BlueVeinError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_named(s, "key")
+    }
+}
+
+impl Serialize for Key128 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Key128 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = Zeroizing::new(String::deserialize(deserializer)?);
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mac(s: &str) -> MacAddress {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_mac_parse_formats() {
+        assert_eq!(mac("aabbccddeeff").to_string(), "AA:BB:CC:DD:EE:FF");
+        assert_eq!(mac("aa:bb:cc:dd:ee:ff").to_string(), "AA:BB:CC:DD:EE:FF");
+        assert_eq!(mac("AA-BB-CC-DD-EE-FF").to_string(), "AA:BB:CC:DD:EE:FF");
+        assert_eq!(mac("98038E6A754D").to_string(), "98:03:8E:6A:75:4D");
+    }
+
+    #[test]
+    fn test_mac_windows_format_roundtrip() {
+        let address = mac("AA:BB:CC:DD:EE:FF");
+        let win_format = address.to_windows_format();
+        assert_eq!(win_format, "AABBCCDDEEFF");
+        assert_eq!(mac(&win_format), address);
+    }
+
+    #[test]
+    fn test_mac_rejects_garbage() {
+        assert!("hello world".parse::<MacAddress>().is_err());
+        assert!("".parse::<MacAddress>().is_err());
+        assert!("AABBCCDDEEGG".parse::<MacAddress>().is_err());
+        assert!("AA:BB:CC:DD:E".parse::<MacAddress>().is_err());
+        assert!("AA:BB-CC:DD:EE:FF".parse::<MacAddress>().is_err());
+        assert!("AABBCCDDEE".parse::<MacAddress>().is_err());
+        assert!("AABBCCDDEEFF00".parse::<MacAddress>().is_err());
+    }
+
+    #[test]
+    fn test_mac_rejects_special_registry_values() {
+        // Real Windows registry value names that are NOT device MACs
+        assert!("CentralIRK".parse::<MacAddress>().is_err());
+        assert!("LocalIRK".parse::<MacAddress>().is_err());
+        assert!("MasterIRK".parse::<MacAddress>().is_err());
+    }
+
+    #[test]
+    fn test_mac_serde() {
+        let address = mac("aa:bb:cc:dd:ee:ff");
+        let json = serde_json::to_string(&address).unwrap();
+        assert_eq!(json, "\"AA:BB:CC:DD:EE:FF\"");
+        assert_eq!(serde_json::from_str::<MacAddress>(&json).unwrap(), address);
+        assert!(serde_json::from_str::<MacAddress>("\"HE:LL:OW:OR:LD\"").is_err());
+    }
+
+    #[test]
+    fn test_key_parse_and_hex() {
+        let key: Key128 = "0123456789abcdef0123456789abcdef".parse().unwrap();
+        assert_eq!(key.to_hex().as_str(), "0123456789ABCDEF0123456789ABCDEF");
+        assert_eq!(key.as_bytes()[0], 0x01);
+    }
+
+    #[test]
+    fn test_key_rejects_wrong_length() {
+        let err = "0123456789ABCDEF".parse::<Key128>().unwrap_err();
+        assert!(err.to_string().contains("expected 32"));
+        assert!(Key128::from_bytes(&[0u8; 8], "LTK").is_err());
+        assert!(Key128::from_bytes(&[0u8; 16], "LTK").is_ok());
+    }
+
+    #[test]
+    fn test_key_serde() {
+        let json = "\"0123456789abcdef0123456789abcdef\"";
+        let key: Key128 = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            serde_json::to_string(&key).unwrap(),
+            "\"0123456789ABCDEF0123456789ABCDEF\""
+        );
+        assert!(serde_json::from_str::<Key128>("\"KEY123\"").is_err());
+    }
+
+    #[test]
+    fn test_key_formatting_hides_key_material() {
+        let key: Key128 = "0123456789ABCDEF0123456789ABCDEF".parse().unwrap();
+        let debug = format!("{:?}", key);
+        let display = key.to_string();
+
+        for shown in [&debug, &display] {
+            assert!(!shown.to_uppercase().contains("0123456789ABCDEF"));
+            assert!(shown.contains(&key.fingerprint()));
+        }
+        assert_eq!(key.fingerprint().len(), 8);
+
+        let other: Key128 = "FEDCBA9876543210FEDCBA9876543210".parse().unwrap();
+        assert_ne!(key.fingerprint(), other.fingerprint());
+    }
+
+    #[test]
+    fn test_key_zeroize() {
+        let mut key: Key128 = "0123456789ABCDEF0123456789ABCDEF".parse().unwrap();
+        key.zeroize();
+        assert_eq!(key.as_bytes(), &[0u8; 16]);
+    }
+
+    #[test]
+    fn test_key_errors_do_not_echo_input() {
+        let secret = "0123456789ABCDEF0123456789ABCDEG";
+        let err = secret.parse::<Key128>().unwrap_err().to_string();
+        assert!(!err.contains(secret));
+
+        let err = serde_json::from_str::<Key128>(&format!("\"{}\"", secret))
+            .unwrap_err()
+            .to_string();
+        assert!(!err.contains(secret));
+    }
+}
@@ -1,48 +1,77 @@
 use crate::bluetooth::{
-    is_valid_mac_hex, mac_to_windows_format, normalize_mac, validate_bluetooth_key,
-    windows_format_to_mac, BluetoothDevice, BluetoothManager, ClassicKeys, CsrkKey,
-    LeLongTermKey, LeKeys,
+    BluetoothDevice, BluetoothManager, ClassicKeys, CsrkKey, LeKeys, LeLongTermKey,
 };
+use crate::config::UnknownFields;
+use crate::error::BlueVeinError;
 use crate::log;
-use std::error::Error;
+use crate::types::{Key128, MacAddress};
 use winreg::enums::RegDisposition;
 use winreg::enums::*;
-use winreg::RegKey;
+use winreg::{RegKey, RegValue};
+use zeroize::Zeroize;
 
 const BLUETOOTH_REG_PATH: &str = r"SYSTEM\CurrentControlSet\Services\BTHPORT\Parameters\Keys";
 const BLUETOOTH_LE_REG_PATH: &str = r"SYSTEM\CurrentControlSet\Services\BTHPORT\Parameters\Keys";
+/// Per-device metadata (name, class of device), keyed by lower-case address
+const BLUETOOTH_DEVICES_REG_PATH: &str =
+    r"SYSTEM\CurrentControlSet\Services\BTHPORT\Parameters\Devices";
+
+/// Read a REG_BINARY key value, wiping the registry buffer afterwards
+///
+/// Returns `None` if the value does not exist.
+fn read_key_value(
+    reg_key: &RegKey,
+    value_name: &str,
+    key_name: &str,
+) -> Option<Result<Key128, BlueVeinError>> {
+    let mut value = reg_key.get_raw_value(value_name).ok()?;
+    let key = Key128::from_bytes(&value.bytes, key_name);
+    value.bytes.zeroize();
+    Some(key)
+}
+
+/// Write a key as a REG_BINARY value, wiping the temporary buffer afterwards
+fn write_key_value(reg_key: &RegKey, value_name: &str, key: &Key128) -> Result<(), BlueVeinError> {
+    let mut value = RegValue {
+        bytes: key.as_bytes().to_vec(),
+        vtype: RegType::REG_BINARY,
+    };
+    let result = reg_key.set_raw_value(value_name, &value);
+    value.bytes.zeroize();
+    result
+        .map_err(|e| BlueVeinError::io(format!("Failed to write registry value {}", value_name), e))
+}
 
 pub struct WindowsBluetoothManager {
     hklm: RegKey,
 }
 
 impl WindowsBluetoothManager {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+    pub fn new() -> Result<Self, BlueVeinError> {
         let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
         Ok(Self { hklm })
     }
 
-    fn open_bluetooth_keys(&self) -> Result<RegKey, Box<dyn Error>> {
+    fn open_bluetooth_keys(&self) -> Result<RegKey, BlueVeinError> {
         self.hklm
             .open_subkey_with_flags(BLUETOOTH_REG_PATH, KEY_READ | KEY_WRITE)
             .map_err(|e| {
-                format!(
-                    "Failed to open Bluetooth registry key (need admin rights): {}",
-                    e
+                BlueVeinError::io(
+                    "Failed to open Bluetooth registry key (need admin rights)",
+                    e,
                 )
-                .into()
             })
     }
 
-    fn open_bluetooth_le_keys(&self) -> Result<RegKey, Box<dyn Error>> {
+    fn open_bluetooth_le_keys(&self) -> Result<RegKey, BlueVeinError> {
         self.hklm
             .open_subkey_with_flags(BLUETOOTH_LE_REG_PATH, KEY_READ | KEY_WRITE)
-            .map_err(|e| format!("Failed to open Bluetooth LE registry key: {}", e).into())
+            .map_err(|e| BlueVeinError::io("Failed to open Bluetooth LE registry key", e))
     }
 
     /// Ensure Bluetooth LE registry path exists
     /// Creates the base BTHLE\Parameters\Keys path if missing
-    fn ensure_bluetooth_le_keys(&self) -> Result<RegKey, Box<dyn Error>> {
+    fn ensure_bluetooth_le_keys(&self) -> Result<RegKey, BlueVeinError> {
         match self.open_bluetooth_le_keys() {
             Ok(keys) => Ok(keys),
             Err(_) => {
@@ -56,29 +85,51 @@ impl WindowsBluetoothManager {
                     .create_subkey(BLUETOOTH_LE_REG_PATH)
                     .map(|(key, _)| key)
                     .map_err(|e| {
-                        format!(
-                            "Failed to create Bluetooth LE registry path (need admin rights): {}",
-                            e
+                        BlueVeinError::io(
+                            "Failed to create Bluetooth LE registry path (need admin rights)",
+                            e,
                         )
-                        .into()
                     })
             }
         }
     }
 
+    /// Fill in the name and class of device recorded by the Bluetooth stack
+    fn read_device_metadata(&self, device: &mut BluetoothDevice) {
+        let device_key_name = device.mac_address.to_windows_format().to_lowercase();
+        let Ok(device_key) = self.hklm.open_subkey_with_flags(
+            format!(r"{}\{}", BLUETOOTH_DEVICES_REG_PATH, device_key_name),
+            KEY_READ,
+        ) else {
+            return;
+        };
+
+        // Name is a NUL-terminated UTF-8 REG_BINARY value
+        if let Ok(name) = device_key.get_raw_value("Name") {
+            let end = name
+                .bytes
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(name.bytes.len());
+            device.name = Some(String::from_utf8_lossy(&name.bytes[..end]).into_owned())
+                .filter(|name| !name.is_empty());
+        }
+        device.class = device_key.get_value::<u32, _>("COD").ok();
+    }
+
     /// Read classic Bluetooth device keys
     fn read_classic_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<Option<ClassicKeys>, Box<dyn Error>> {
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<Option<ClassicKeys>, BlueVeinError> {
         let bt_keys = match self.open_bluetooth_keys() {
             Ok(keys) => keys,
             Err(_) => return Ok(None),
         };
 
-        let adapter_key_name = mac_to_windows_format(adapter_mac);
-        let device_key_name = mac_to_windows_format(device_mac);
+        let adapter_key_name = adapter_mac.to_windows_format();
+        let device_key_name = device_mac.to_windows_format();
 
         let adapter_key = match bt_keys.open_subkey_with_flags(&adapter_key_name, KEY_READ) {
             Ok(key) => key,
@@ -86,18 +137,19 @@ impl WindowsBluetoothManager {
         };
 
         // Read raw value as binary
-        if let Ok(value) = adapter_key.get_raw_value(&device_key_name) {
-            let link_key = hex::encode(&value.bytes).to_uppercase();
-
+        if let Some(link_key) = read_key_value(&adapter_key, &device_key_name, "LinkKey") {
             // Validate LinkKey length
-            if let Err(e) = validate_bluetooth_key(&link_key, "LinkKey") {
-                log!(
-                    "[BlueVein] Warning: Invalid LinkKey for device {}: {}",
-                    device_mac,
-                    e
-                );
-                return Ok(None);
-            }
+            let link_key = match link_key {
+                Ok(key) => key,
+                Err(e) => {
+                    log!(
+                        "[BlueVein] Warning: Invalid LinkKey for device {}: {}",
+                        device_mac,
+                        e
+                    );
+                    return Ok(None);
+                }
+            };
 
             return Ok(Some(ClassicKeys::new(link_key)));
         }
@@ -108,16 +160,16 @@ impl WindowsBluetoothManager {
     /// Read LE device keys
     fn read_le_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<Option<LeKeys>, Box<dyn Error>> {
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<Option<LeKeys>, BlueVeinError> {
         let bt_le_keys = match self.open_bluetooth_le_keys() {
             Ok(keys) => keys,
             Err(_) => return Ok(None),
         };
 
-        let adapter_key_name = mac_to_windows_format(adapter_mac);
-        let device_key_name = mac_to_windows_format(device_mac);
+        let adapter_key_name = adapter_mac.to_windows_format();
+        let device_key_name = device_mac.to_windows_format();
 
         let adapter_key = match bt_le_keys.open_subkey_with_flags(&adapter_key_name, KEY_READ) {
             Ok(key) => key,
@@ -133,56 +185,55 @@ impl WindowsBluetoothManager {
         let mut has_keys = false;
 
         // Read LTK (Long Term Key)
-        if let Ok(ltk_value) = device_key.get_raw_value("LTK") {
-            let key = hex::encode(&ltk_value.bytes).to_uppercase();
-
+        if let Some(ltk) = read_key_value(&device_key, "LTK", "LTK") {
             // Validate LTK length
-            if let Err(e) = validate_bluetooth_key(&key, "LTK") {
-                log!(
+            match ltk {
+                Err(e) => log!(
                     "[BlueVein] Warning: Invalid LTK for device {}: {}",
                     device_mac,
                     e
-                );
-            } else {
-                let authenticated = device_key
-                    .get_value::<u32, _>("Authenticated")
-                    .ok()
-                    .map(|v| v as u8);
-                let enc_size = device_key
-                    .get_value::<u32, _>("KeyLength")
-                    .ok()
-                    .map(|v| v as u8);
-                let ediv = device_key
-                    .get_value::<u32, _>("EDIV")
-                    .ok()
-                    .map(|v| v as u16);
-                let rand = device_key.get_value::<u64, _>("ERand").ok();
-
-                le_keys.ltk = Some(LeLongTermKey {
-                    key,
-                    authenticated,
-                    enc_size,
-                    ediv,
-                    rand,
-                });
-                has_keys = true;
+                ),
+                Ok(key) => {
+                    let authenticated = device_key
+                        .get_value::<u32, _>("Authenticated")
+                        .ok()
+                        .map(|v| v as u8);
+                    let enc_size = device_key
+                        .get_value::<u32, _>("KeyLength")
+                        .ok()
+                        .map(|v| v as u8);
+                    let ediv = device_key
+                        .get_value::<u32, _>("EDIV")
+                        .ok()
+                        .map(|v| v as u16);
+                    let rand = device_key.get_value::<u64, _>("ERand").ok();
+
+                    le_keys.ltk = Some(LeLongTermKey {
+                        key,
+                        authenticated,
+                        enc_size,
+                        ediv,
+                        rand,
+                        extra: UnknownFields::default(),
+                    });
+                    has_keys = true;
+                }
             }
         }
 
         // Read IRK (Identity Resolving Key)
-        if let Ok(irk_value) = device_key.get_raw_value("IRK") {
-            let key = hex::encode(&irk_value.bytes).to_uppercase();
-
+        if let Some(irk) = read_key_value(&device_key, "IRK", "IRK") {
             // Validate IRK length
-            if let Err(e) = validate_bluetooth_key(&key, "IRK") {
-                log!(
+            match irk {
+                Err(e) => log!(
                     "[BlueVein] Warning: Invalid IRK for device {}: {}",
                     device_mac,
                     e
-                );
-            } else {
-                le_keys.irk = Some(key);
-                has_keys = true;
+                ),
+                Ok(key) => {
+                    le_keys.irk = Some(key);
+                    has_keys = true;
+                }
             }
         }
 
@@ -205,38 +256,36 @@ impl WindowsBluetoothManager {
         // Most LE devices (keyboards, mice, headphones, gamepads) use LTK for encrypted
         // connections. CSRK signing is only used by rare IoT devices with unencrypted
         // connections. If such device fails to connect after sync, re-pair once to reset.
-        if let Ok(csrk_value) = device_key.get_raw_value("CSRK") {
-            let key = hex::encode(&csrk_value.bytes).to_uppercase();
-
+        if let Some(csrk) = read_key_value(&device_key, "CSRK", "CSRK (Local)") {
             // Validate CSRK length
-            if let Err(e) = validate_bluetooth_key(&key, "CSRK (Local)") {
-                log!(
+            match csrk {
+                Err(e) => log!(
                     "[BlueVein] Warning: Invalid CSRK for device {}: {}",
                     device_mac,
                     e
-                );
-            } else {
-                // Windows doesn't store Counter/Authenticated in registry, use defaults
-                le_keys.csrk_local = Some(CsrkKey::new(key));
-                has_keys = true;
+                ),
+                Ok(key) => {
+                    // Windows doesn't store Counter/Authenticated in registry, use defaults
+                    le_keys.csrk_local = Some(CsrkKey::new(key));
+                    has_keys = true;
+                }
             }
         }
 
         // Read CSRKInbound (Remote CSRK)
         // Same Counter limitation applies to remote CSRK
-        if let Ok(csrk_inbound) = device_key.get_raw_value("CSRKInbound") {
-            let key = hex::encode(&csrk_inbound.bytes).to_uppercase();
-
+        if let Some(csrk_inbound) = read_key_value(&device_key, "CSRKInbound", "CSRK (Remote)") {
             // Validate CSRK length
-            if let Err(e) = validate_bluetooth_key(&key, "CSRK (Remote)") {
-                log!(
+            match csrk_inbound {
+                Err(e) => log!(
                     "[BlueVein] Warning: Invalid CSRKInbound for device {}: {}",
                     device_mac,
                     e
-                );
-            } else {
-                le_keys.csrk_remote = Some(CsrkKey::new(key));
-                has_keys = true;
+                ),
+                Ok(key) => {
+                    le_keys.csrk_remote = Some(CsrkKey::new(key));
+                    has_keys = true;
+                }
             }
         }
 
@@ -250,41 +299,24 @@ impl WindowsBluetoothManager {
     /// Write classic device keys
     fn write_classic_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
         classic: &ClassicKeys,
-    ) -> Result<(), Box<dyn Error>> {
-        // Validate LinkKey before writing
-        validate_bluetooth_key(&classic.link_key, "LinkKey")?;
-
+    ) -> Result<(), BlueVeinError> {
         let bt_keys = self.open_bluetooth_keys()?;
-        let adapter_key_name = mac_to_windows_format(adapter_mac);
-        let device_key_name = mac_to_windows_format(device_mac);
+        let adapter_key_name = adapter_mac.to_windows_format();
+        let device_key_name = device_mac.to_windows_format();
 
         // Open or create adapter key
         let (adapter_key, _) = bt_keys.create_subkey(&adapter_key_name).map_err(|e| {
-            format!(
-                "Failed to create/open adapter key {}: {}",
-                adapter_key_name, e
+            BlueVeinError::io(
+                format!("Failed to create/open adapter key {}", adapter_key_name),
+                e,
             )
         })?;
 
-        // Decode hex link key to bytes
-        let key_bytes = hex::decode(&classic.link_key)
-            .map_err(|e| format!("Invalid link key format: {}", e))?;
-
         // Write as binary value (REG_BINARY)
-        adapter_key
-            .set_raw_value(
-                &device_key_name,
-                &winreg::RegValue {
-                    bytes: key_bytes,
-                    vtype: winreg::enums::RegType::REG_BINARY,
-                },
-            )
-            .map_err(|e| format!("Failed to write device key: {}", e))?;
-
-        Ok(())
+        write_key_value(&adapter_key, &device_key_name, &classic.link_key)
     }
 
     /// Write LE device keys
@@ -294,22 +326,25 @@ impl WindowsBluetoothManager {
     /// so we need to create the structure manually when syncing from another OS.
     fn write_le_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
         le: &LeKeys,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), BlueVeinError> {
         // Ensure base LE registry path exists (create if needed)
         let bt_le_keys = self.ensure_bluetooth_le_keys()?;
 
-        let adapter_key_name = mac_to_windows_format(adapter_mac);
-        let device_key_name = mac_to_windows_format(device_mac);
+        let adapter_key_name = adapter_mac.to_windows_format();
+        let device_key_name = device_mac.to_windows_format();
 
         // Create adapter key if it doesn't exist
         let (adapter_key, adapter_disp) =
             bt_le_keys.create_subkey(&adapter_key_name).map_err(|e| {
-                format!(
-                    "Failed to create adapter key {} in LE registry: {}",
-                    adapter_key_name, e
+                BlueVeinError::io(
+                    format!(
+                        "Failed to create adapter key {} in LE registry",
+                        adapter_key_name
+                    ),
+                    e,
                 )
             })?;
 
@@ -323,9 +358,12 @@ impl WindowsBluetoothManager {
         // Create device key - this is where LE keys are stored
         let (device_key, device_disp) =
             adapter_key.create_subkey(&device_key_name).map_err(|e| {
-                format!(
-                    "Failed to create device key {} in LE registry: {}",
-                    device_key_name, e
+                BlueVeinError::io(
+                    format!(
+                        "Failed to create device key {} in LE registry",
+                        device_key_name
+                    ),
+                    e,
                 )
             })?;
 
@@ -343,19 +381,7 @@ impl WindowsBluetoothManager {
 
         // Write LTK
         if let Some(ltk) = &le.ltk {
-            // Validate LTK before writing
-            validate_bluetooth_key(&ltk.key, "LTK")?;
-
-            let ltk_bytes =
-                hex::decode(&ltk.key).map_err(|e| format!("Invalid LTK format: {}", e))?;
-
-            device_key.set_raw_value(
-                "LTK",
-                &winreg::RegValue {
-                    bytes: ltk_bytes,
-                    vtype: RegType::REG_BINARY,
-                },
-            )?;
+            write_key_value(&device_key, "LTK", &ltk.key)?;
 
             // Use authenticated_or_default() to ensure default value of 0
             device_key.set_value("Authenticated", &(ltk.authenticated_or_default() as u32))?;
@@ -373,18 +399,7 @@ impl WindowsBluetoothManager {
 
         // Write IRK
         if let Some(irk) = &le.irk {
-            // Validate IRK before writing
-            validate_bluetooth_key(irk, "IRK")?;
-
-            let irk_bytes = hex::decode(irk).map_err(|e| format!("Invalid IRK format: {}", e))?;
-
-            device_key.set_raw_value(
-                "IRK",
-                &winreg::RegValue {
-                    bytes: irk_bytes,
-                    vtype: RegType::REG_BINARY,
-                },
-            )?;
+            write_key_value(&device_key, "IRK", irk)?;
         }
 
         // Write CSRK (local)
@@ -397,37 +412,13 @@ impl WindowsBluetoothManager {
         // - Combines authenticated flags with OR logic
         // This ensures replay attack protection even without registry support.
         if let Some(csrk_local) = &le.csrk_local {
-            // Validate CSRK before writing
-            validate_bluetooth_key(&csrk_local.key, "CSRK (Local)")?;
-
-            let csrk_bytes =
-                hex::decode(&csrk_local.key).map_err(|e| format!("Invalid CSRK format: {}", e))?;
-
-            device_key.set_raw_value(
-                "CSRK",
-                &winreg::RegValue {
-                    bytes: csrk_bytes,
-                    vtype: RegType::REG_BINARY,
-                },
-            )?;
+            write_key_value(&device_key, "CSRK", &csrk_local.key)?;
         }
 
         // Write CSRKInbound (remote)
         // Same Counter/Authenticated limitation and BlueVein solution as local CSRK
         if let Some(csrk_remote) = &le.csrk_remote {
-            // Validate CSRK before writing
-            validate_bluetooth_key(&csrk_remote.key, "CSRK (Remote)")?;
-
-            let csrk_bytes = hex::decode(&csrk_remote.key)
-                .map_err(|e| format!("Invalid CSRKInbound format: {}", e))?;
-
-            device_key.set_raw_value(
-                "CSRKInbound",
-                &winreg::RegValue {
-                    bytes: csrk_bytes,
-                    vtype: RegType::REG_BINARY,
-                },
-            )?;
+            write_key_value(&device_key, "CSRKInbound", &csrk_remote.key)?;
         }
 
         Ok(())
@@ -435,17 +426,17 @@ impl WindowsBluetoothManager {
 }
 
 impl BluetoothManager for WindowsBluetoothManager {
-    fn get_adapters(&self) -> Result<Vec<String>, Box<dyn Error>> {
+    fn get_adapters(&self) -> Result<Vec<MacAddress>, BlueVeinError> {
         let mut adapters = Vec::new();
 
         // Check classic Bluetooth adapters
         if let Ok(bt_keys) = self.open_bluetooth_keys() {
             for adapter in bt_keys.enum_keys() {
                 if let Ok(adapter_name) = adapter {
-                    if !is_valid_mac_hex(&adapter_name) {
-                        continue;
-                    }
-                    let mac = windows_format_to_mac(&adapter_name);
+                    let mac = match adapter_name.parse::<MacAddress>() {
+                        Ok(mac) => mac,
+                        Err(_) => continue,
+                    };
                     if !adapters.contains(&mac) {
                         adapters.push(mac);
                     }
@@ -457,10 +448,10 @@ impl BluetoothManager for WindowsBluetoothManager {
         if let Ok(bt_le_keys) = self.open_bluetooth_le_keys() {
             for adapter in bt_le_keys.enum_keys() {
                 if let Ok(adapter_name) = adapter {
-                    if !is_valid_mac_hex(&adapter_name) {
-                        continue;
-                    }
-                    let mac = windows_format_to_mac(&adapter_name);
+                    let mac = match adapter_name.parse::<MacAddress>() {
+                        Ok(mac) => mac,
+                        Err(_) => continue,
+                    };
                     if !adapters.contains(&mac) {
                         adapters.push(mac);
                     }
@@ -471,30 +462,33 @@ impl BluetoothManager for WindowsBluetoothManager {
         Ok(adapters)
     }
 
-    fn get_devices(&self, adapter_mac: &str) -> Result<Vec<BluetoothDevice>, Box<dyn Error>> {
-        let mut devices_map: std::collections::HashMap<String, BluetoothDevice> =
+    fn get_devices(&self, adapter_mac: &MacAddress) -> Result<Vec<BluetoothDevice>, BlueVeinError> {
+        let mut devices_map: std::collections::HashMap<MacAddress, BluetoothDevice> =
             std::collections::HashMap::new();
 
         // Read classic devices
         if let Ok(bt_keys) = self.open_bluetooth_keys() {
-            let adapter_key_name = mac_to_windows_format(adapter_mac);
+            let adapter_key_name = adapter_mac.to_windows_format();
             if let Ok(adapter_key) = bt_keys.open_subkey_with_flags(&adapter_key_name, KEY_READ) {
                 for device in adapter_key.enum_values() {
                     if let Ok((device_name, _)) = device {
                         // Skip special registry values like "CentralIRK", "LocalIRK" etc.
-                        if !is_valid_mac_hex(&device_name) {
-                            continue;
-                        }
-                        let device_mac = windows_format_to_mac(&device_name);
+                        let device_mac = match device_name.parse::<MacAddress>() {
+                            Ok(mac) => mac,
+                            Err(_) => continue,
+                        };
                         if let Ok(Some(classic)) =
                             self.read_classic_device(adapter_mac, &device_mac)
                         {
                             devices_map
-                                .entry(device_mac.clone())
+                                .entry(device_mac)
                                 .or_insert_with(|| BluetoothDevice {
-                                    mac_address: device_mac.clone(),
+                                    mac_address: device_mac,
                                     classic: None,
                                     le: None,
+                                    name: None,
+                                    class: None,
+                                    extra: UnknownFields::default(),
                                 })
                                 .classic = Some(classic);
                         }
@@ -505,19 +499,25 @@ impl BluetoothManager for WindowsBluetoothManager {
 
         // Read LE devices
         if let Ok(bt_le_keys) = self.open_bluetooth_le_keys() {
-            let adapter_key_name = mac_to_windows_format(adapter_mac);
+            let adapter_key_name = adapter_mac.to_windows_format();
             if let Ok(adapter_key) = bt_le_keys.open_subkey_with_flags(&adapter_key_name, KEY_READ)
             {
                 for device in adapter_key.enum_keys() {
                     if let Ok(device_name) = device {
-                        let device_mac = windows_format_to_mac(&device_name);
+                        let device_mac = match device_name.parse::<MacAddress>() {
+                            Ok(mac) => mac,
+                            Err(_) => continue,
+                        };
                         if let Ok(Some(le)) = self.read_le_device(adapter_mac, &device_mac) {
                             devices_map
-                                .entry(device_mac.clone())
+                                .entry(device_mac)
                                 .or_insert_with(|| BluetoothDevice {
-                                    mac_address: device_mac.clone(),
+                                    mac_address: device_mac,
                                     classic: None,
                                     le: None,
+                                    name: None,
+                                    class: None,
+                                    extra: UnknownFields::default(),
                                 })
                                 .le = Some(le);
                         }
@@ -526,33 +526,41 @@ impl BluetoothManager for WindowsBluetoothManager {
             }
         }
 
+        for device in devices_map.values_mut() {
+            self.read_device_metadata(device);
+        }
         Ok(devices_map.into_iter().map(|(_, device)| device).collect())
     }
 
     fn get_device(
         &self,
-        adapter_mac: &str,
-        device_mac: &str,
-    ) -> Result<BluetoothDevice, Box<dyn Error>> {
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<BluetoothDevice, BlueVeinError> {
         let classic = self.read_classic_device(adapter_mac, device_mac)?;
         let le = self.read_le_device(adapter_mac, device_mac)?;
 
         if classic.is_none() && le.is_none() {
-            return Err(format!("Device {} not found", device_mac).into());
+            return Err(BlueVeinError::device_not_found(adapter_mac, device_mac));
         }
 
-        Ok(BluetoothDevice {
-            mac_address: normalize_mac(device_mac),
+        let mut device = BluetoothDevice {
+            mac_address: *device_mac,
             classic,
             le,
-        })
+            name: None,
+            class: None,
+            extra: UnknownFields::default(),
+        };
+        self.read_device_metadata(&mut device);
+        Ok(device)
     }
 
     fn set_device(
         &mut self,
-        adapter_mac: &str,
+        adapter_mac: &MacAddress,
         device: &BluetoothDevice,
-    ) -> Result<(), Box<dyn Error>> {
+    ) -> Result<(), BlueVeinError> {
         // Write classic keys if present
         if let Some(classic) = &device.classic {
             self.write_classic_device(adapter_mac, &device.mac_address, classic)?;
@@ -566,9 +574,13 @@ impl BluetoothManager for WindowsBluetoothManager {
         Ok(())
     }
 
-    fn remove_device(&mut self, adapter_mac: &str, device_mac: &str) -> Result<(), Box<dyn Error>> {
-        let adapter_key_name = mac_to_windows_format(adapter_mac);
-        let device_key_name = mac_to_windows_format(device_mac);
+    fn remove_device(
+        &mut self,
+        adapter_mac: &MacAddress,
+        device_mac: &MacAddress,
+    ) -> Result<(), BlueVeinError> {
+        let adapter_key_name = adapter_mac.to_windows_format();
+        let device_key_name = device_mac.to_windows_format();
 
         // Remove from classic registry
         if let Ok(bt_keys) = self.open_bluetooth_keys() {
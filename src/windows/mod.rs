@@ -3,15 +3,17 @@ mod monitor;
 mod service;
 
 use crate::efi::EfiContext;
+use crate::history::RollbackTarget;
 use crate::log;
+use crate::settings::{ReloadRequests, Settings};
 use crate::sync::SyncManager;
+use crate::types::MacAddress;
 use std::error::Error;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 use std::thread;
-use std::time::Duration;
 
 pub fn run() -> Result<(), Box<dyn Error>> {
     // Check if running as service or standalone
@@ -27,6 +29,14 @@ pub fn run() -> Result<(), Box<dyn Error>> {
                 "uninstall" => service::uninstall_service(),
                 "start" => service::start_service(),
                 "stop" => service::stop_service(),
+                "status" => run_status(),
+                "forget" if args.len() == 3 => run_forget(&args[2]),
+                "gc" if args.len() == 2 => run_gc(false),
+                "gc" if args.len() == 3 && args[2] == "--dry-run" => run_gc(true),
+                "history" => run_history(),
+                "rollback" if args.len() == 3 => run_rollback(&args[2]),
+                "backups" if args.len() == 2 || args[2] == "list" => run_backups(),
+                "restore" if args.len() == 3 => run_restore(&args[2]),
                 _ => {
                     log!("BlueVein - Bluetooth Synchronization Service");
                     log!("\nUsage:");
@@ -34,6 +44,15 @@ pub fn run() -> Result<(), Box<dyn Error>> {
                     log!("  bluevein.exe uninstall - Uninstall service");
                     log!("  bluevein.exe start     - Start service");
                     log!("  bluevein.exe stop      - Stop service");
+                    log!("  bluevein.exe status    - Show installations taking part in the sync");
+                    log!("  bluevein.exe forget <mac> - Remove a device from every installation");
+                    log!("  bluevein.exe gc [--dry-run] - Remove devices and adapters not seen for a long time");
+                    log!("  bluevein.exe history - Show stored config generations");
+                    log!("  bluevein.exe rollback <generation|mac> - Restore an earlier config or device keys");
+                    log!("  bluevein.exe backups list - Show local backups of Bluetooth keys");
+                    log!(
+                        "  bluevein.exe restore <id> - Restore local Bluetooth keys from a backup"
+                    );
                     Ok(())
                 }
             }
@@ -46,17 +65,21 @@ pub fn run() -> Result<(), Box<dyn Error>> {
 }
 
 pub fn run_sync_loop() -> Result<(), Box<dyn Error>> {
-    let bt_manager = Box::new(bluetooth::WindowsBluetoothManager::new()?);
-
-    let efi_context = EfiContext::from_env();
-    efi_context.validate()?;
-
-    let mut sync_manager = SyncManager::new(bt_manager, efi_context);
+    let mut sync_manager = create_sync_manager()?;
 
     log!("[BlueVein] Performing initial bidirectional sync...");
-    if let Err(e) = sync_manager.sync_bidirectional() {
-        log!("[BlueVein] Warning: Initial sync failed: {}", e);
-        log!("[BlueVein] Continuing with monitoring...");
+    match sync_manager.sync_bidirectional() {
+        Ok(report) if report.has_failures() => {
+            log!(
+                "[BlueVein] Warning: Initial sync completed with failures: {}",
+                report
+            );
+        }
+        Ok(_) => {}
+        Err(e) => {
+            log!("[BlueVein] Warning: Initial sync failed: {}", e);
+            log!("[BlueVein] Continuing with monitoring...");
+        }
     }
 
     let running = Arc::new(AtomicBool::new(true));
@@ -80,36 +103,117 @@ pub fn run_sync_loop() -> Result<(), Box<dyn Error>> {
     monitor::monitor_bluetooth_changes(sync_manager, running)
 }
 
+fn create_sync_manager() -> Result<SyncManager, Box<dyn Error>> {
+    let settings = Settings::load()?;
+    let bt_manager = Box::new(bluetooth::WindowsBluetoothManager::new()?);
+
+    let efi_context = EfiContext::from_settings(&settings.store);
+    efi_context.validate()?;
+
+    Ok(SyncManager::new(bt_manager, efi_context, &settings))
+}
+
+/// Print the health of every ESP and the installation inventory
+fn run_status() -> Result<(), Box<dyn Error>> {
+    let sync_manager = create_sync_manager()?;
+    println!("ESPs:");
+    for target in sync_manager.store_status() {
+        println!("  {}", target);
+    }
+    println!("{}", sync_manager.inventory_status()?);
+    Ok(())
+}
+
+/// Collect stale devices and adapters from the shared config
+fn run_gc(dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let plan = create_sync_manager()?.collect_garbage(dry_run)?;
+    if plan.is_empty() {
+        println!("{}", plan);
+    } else if dry_run {
+        println!("Would remove:\n{}", plan);
+    } else {
+        println!("Removed:\n{}", plan);
+    }
+    Ok(())
+}
+
+/// Print the stored config generations
+fn run_history() -> Result<(), Box<dyn Error>> {
+    print!("{}", create_sync_manager()?.history()?);
+    Ok(())
+}
+
+/// Restore an earlier generation or device keys and apply them to this system
+fn run_rollback(target: &str) -> Result<(), Box<dyn Error>> {
+    let target: RollbackTarget = target.parse()?;
+    let report = create_sync_manager()?.rollback(target)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
+}
+
+/// Print local backups of Bluetooth keys
+fn run_backups() -> Result<(), Box<dyn Error>> {
+    let backups = create_sync_manager()?.backups()?;
+    if backups.is_empty() {
+        println!("No local backups");
+    }
+    for backup in backups {
+        println!("{}", backup);
+    }
+    Ok(())
+}
+
+/// Restore local Bluetooth keys from a backup
+fn run_restore(id: &str) -> Result<(), Box<dyn Error>> {
+    let report = create_sync_manager()?.restore_backup(id)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
+}
+
+/// Forget a device on every installation
+fn run_forget(device: &str) -> Result<(), Box<dyn Error>> {
+    let device_mac: MacAddress = device.parse()?;
+    let report = create_sync_manager()?.forget_device(&device_mac)?;
+    log!("[BlueVein] {}", report);
+    Ok(())
+}
+
 /// Periodically check EFI for changes made by other OS
 fn periodic_efi_check(running: Arc<AtomicBool>) {
-    let bt_manager = match bluetooth::WindowsBluetoothManager::new() {
-        Ok(mgr) => mgr,
+    let mut sync_manager = match create_sync_manager() {
+        Ok(sync_manager) => sync_manager,
         Err(e) => {
-            log!(
-                "[BlueVein] Failed to create BT manager for EFI checking: {}",
-                e
-            );
+            log!("[BlueVein] Failed to start EFI checking: {}", e);
             return;
         }
     };
 
-    let efi_context = EfiContext::from_env();
-    if let Err(e) = efi_context.validate() {
-        log!("[BlueVein] Invalid EFI device configuration: {}", e);
-        return;
-    }
-
-    let mut sync_manager = SyncManager::new(Box::new(bt_manager), efi_context);
-
+    let mut reload = ReloadRequests::new();
     while running.load(Ordering::Relaxed) {
-        thread::sleep(Duration::from_secs(30)); // Check every 30 seconds
+        thread::sleep(sync_manager.efi_check_interval());
 
         if !running.load(Ordering::Relaxed) {
             break;
         }
 
-        if let Err(e) = sync_manager.check_efi_changes() {
-            log!("[BlueVein] Error checking EFI changes: {}", e);
+        if reload.take() {
+            sync_manager.reload_settings();
         }
+        match sync_manager.check_efi_changes() {
+            Ok(report) if report.devices().next().is_some() => log!("[BlueVein] {}", report),
+            Ok(_) => {}
+            Err(e) => log!("[BlueVein] Error checking EFI changes: {}", e),
+        }
+        sync_manager.retry_pending(false);
+    }
+
+    sync_manager.retry_pending(true);
+}
+
+/// Write device changes still queued in the outbox, used on service stop
+pub fn flush_pending_changes() {
+    match create_sync_manager() {
+        Ok(mut sync_manager) => sync_manager.retry_pending(true),
+        Err(e) => log!("[BlueVein] Cannot write pending changes: {}", e),
     }
 }
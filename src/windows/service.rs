@@ -39,6 +39,11 @@ fn run_service_impl(_arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
                 shutdown_tx.send(()).ok();
                 ServiceControlHandlerResult::NoError
             }
+            // `sc control BlueVeinService paramchange` reloads the settings file
+            ServiceControl::Paramchange => {
+                crate::settings::request_reload();
+                ServiceControlHandlerResult::NoError
+            }
             _ => ServiceControlHandlerResult::NotImplemented,
         }
     };
@@ -49,7 +54,7 @@ fn run_service_impl(_arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
     status_handle.set_service_status(ServiceStatus {
         service_type: ServiceType::OWN_PROCESS,
         current_state: ServiceState::Running,
-        controls_accepted: ServiceControlAccept::STOP,
+        controls_accepted: ServiceControlAccept::STOP | ServiceControlAccept::PARAM_CHANGE,
         exit_code: ServiceExitCode::Win32(0),
         checkpoint: 0,
         wait_hint: Duration::default(),
@@ -66,6 +71,9 @@ fn run_service_impl(_arguments: Vec<OsString>) -> Result<(), Box<dyn Error>> {
     // Wait for shutdown signal
     let _ = shutdown_rx.recv();
 
+    // Device changes queued while the ESP was unavailable get one last try
+    super::flush_pending_changes();
+
     // Tell Windows we're stopping
     status_handle.set_service_status(ServiceStatus {
         service_type: ServiceType::OWN_PROCESS,